@@ -0,0 +1,102 @@
+use common::MAX_BATCH_SIZE;
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Longest a sample is held back for a batch unless TAILMON_BATCH_MAX_AGE_SECS says otherwise
+const DEFAULT_MAX_AGE_SECS: u64 = 300;
+
+/// Holds samples back in the offline buffer so several cycles go out in one request
+/// (TAILMON_BATCH_SIZE), which saves requests on metered links at the cost of freshness
+pub struct Batching {
+    /// Cycles per request; 1 sends every cycle
+    pub size: usize,
+
+    /// Deliver anyway once the oldest held sample is this old
+    pub max_age: Duration,
+
+    /// Samples collected since the last successful delivery
+    held: usize,
+    oldest: Option<Instant>,
+}
+
+impl Batching {
+    /// Note a newly collected sample
+    pub fn hold(&mut self) {
+        self.held += 1;
+        self.oldest.get_or_insert_with(Instant::now);
+    }
+
+    /// Whether the held samples should be delivered now
+    pub fn is_due(&self) -> bool {
+        self.held >= self.size || self.oldest.is_some_and(|oldest| oldest.elapsed() >= self.max_age)
+    }
+
+    /// When the oldest held sample reaches the max age; `None` with nothing held
+    pub fn deadline(&self) -> Option<Instant> {
+        self.oldest.map(|oldest| oldest + self.max_age)
+    }
+
+    /// Samples held since the last successful delivery
+    pub fn held(&self) -> usize {
+        self.held
+    }
+
+    /// Start the next batch after a successful delivery
+    pub fn delivered(&mut self) {
+        self.held = 0;
+        self.oldest = None;
+    }
+}
+
+/// Get the batching from TAILMON_BATCH_SIZE (1 to 100; default: 1) and TAILMON_BATCH_MAX_AGE_SECS
+/// (default: 300). A batch never exceeds the offline buffer, which holds its samples.
+pub fn get_batching(spool_max: usize) -> Batching {
+    let size = match env::var("TAILMON_BATCH_SIZE").ok().map(|raw| (raw.trim().parse::<usize>(), raw)) {
+        None => 1,
+        Some((Ok(size), _)) if (1..=MAX_BATCH_SIZE).contains(&size) => size,
+        Some((_, raw)) => {
+            warn!("Ignoring invalid value for TAILMON_BATCH_SIZE (expected 1 to {}): {}", MAX_BATCH_SIZE, raw);
+            1
+        }
+    };
+    if size > spool_max.max(1) {
+        warn!("TAILMON_BATCH_SIZE {} exceeds TAILMON_SPOOL_MAX, batching {} samples instead", size, spool_max.max(1));
+    }
+    let max_age_secs = env::var("TAILMON_BATCH_MAX_AGE_SECS").ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_MAX_AGE_SECS);
+    Batching {
+        size: size.min(spool_max.max(1)),
+        max_age: Duration::from_secs(max_age_secs),
+        held: 0,
+        oldest: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_when_full_or_at_max_age() {
+        let mut batching = Batching { size: 3, max_age: Duration::from_millis(50), held: 0, oldest: None };
+        assert!(batching.deadline().is_none());
+        batching.hold();
+        let deadline = batching.deadline().unwrap();
+        batching.hold();
+        assert_eq!(batching.deadline(), Some(deadline), "the oldest sample sets the deadline");
+        assert!(!batching.is_due());
+
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        assert!(batching.is_due());
+
+        batching.delivered();
+        assert!(batching.deadline().is_none());
+        for _ in 0..3 {
+            batching.hold();
+        }
+        assert!(batching.is_due());
+    }
+}
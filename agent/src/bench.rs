@@ -0,0 +1,72 @@
+use crate::fields;
+use crate::{collect_sample, SampleProcessing, SharedCollector};
+use std::time::{Duration, Instant};
+
+/// Collection cycles measured by `agent bench` unless a count is given
+const DEFAULT_CYCLES: u32 = 10;
+
+/// Pause between measured cycles, so CPU usage is computed over a realistic delta
+const CYCLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Parse `bench [cycles]` from the command line arguments; `None` when not benchmarking
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Result<u32, String>> {
+    if args.next().as_deref() != Some("bench") {
+        return None;
+    }
+    let cycles = match args.next() {
+        None => Ok(DEFAULT_CYCLES),
+        Some(value) => value.parse().ok().filter(|cycles| *cycles > 0)
+            .ok_or_else(|| format!("invalid cycle count: {}", value)),
+    };
+    Some(cycles)
+}
+
+/// Run `cycles` collection cycles exactly as the agent loop would (exec hook, rounding,
+/// redaction, anonymization and field trimming included), without sending anything, and print the resource cost.
+/// Returns the process exit code.
+pub async fn run(collector: &SharedCollector, cycles: u32, collect_timeout: Duration, processing: &SampleProcessing<'_>) -> i32 {
+    println!("Benchmarking {} collection cycles, {} second apart (nothing is sent)...", cycles, CYCLE_DELAY.as_secs());
+    let mut durations: Vec<Duration> = Vec::new();
+    let mut refresh_ms: Vec<u64> = Vec::new();
+    let mut sample_bytes = 0;
+    let mut peak_memory = common::collector::own_memory_bytes();
+    let mut failed = 0;
+
+    for cycle in 0..cycles {
+        if cycle > 0 {
+            tokio::time::sleep(CYCLE_DELAY).await;
+        }
+        let started = Instant::now();
+        match collect_sample(collector, collect_timeout, processing).await {
+            Some(system_info) => {
+                durations.push(started.elapsed());
+                refresh_ms.push(system_info.collect_duration_ms);
+                sample_bytes = serde_json::to_vec(&fields::payload(&system_info)).map(|body| body.len()).unwrap_or_default();
+            }
+            None => failed += 1,
+        }
+        peak_memory = peak_memory.max(common::collector::own_memory_bytes());
+    }
+
+    if durations.is_empty() {
+        println!("No sample could be collected ({} cycles failed)", failed);
+        return 1;
+    }
+    durations.sort();
+    let total: Duration = durations.iter().sum();
+    let percentile = |p: usize| durations[(durations.len() * p / 100).min(durations.len() - 1)];
+    println!("Cycles:          {} collected, {} failed", durations.len(), failed);
+    println!("Collection time: min {:.1} ms, avg {:.1} ms, p95 {:.1} ms, max {:.1} ms",
+        millis(durations[0]), millis(total / durations.len() as u32), millis(percentile(95)), millis(durations[durations.len() - 1]));
+    println!("  sysinfo:       avg {:.1} ms", refresh_ms.iter().sum::<u64>() as f64 / refresh_ms.len() as f64);
+    println!("Sample size:     {} bytes (JSON)", sample_bytes);
+    match peak_memory {
+        Some(bytes) => println!("Peak memory:     {:.1} MB resident (highest seen after a cycle)", bytes as f64 / 1024.0 / 1024.0),
+        None => println!("Peak memory:     unavailable on this platform"),
+    }
+    0
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,97 @@
+use common::collector::{Collector, SysinfoCollector};
+use common::procfs::ProcCollector;
+use common::SystemInfo;
+use std::collections::HashMap;
+use std::env;
+use tracing::{info, warn};
+
+/// Where samples are read from (TAILMON_COLLECTOR)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectorKind {
+    /// sysinfo, switching to `/proc` for good if it reports no memory at all
+    Auto,
+
+    /// sysinfo only
+    Sysinfo,
+
+    /// `/proc/stat`, `/proc/meminfo` and `/proc/loadavg` read directly (Linux)
+    Proc,
+}
+
+impl CollectorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollectorKind::Auto => "auto",
+            CollectorKind::Sysinfo => "sysinfo",
+            CollectorKind::Proc => "proc",
+        }
+    }
+}
+
+/// Get the collector from TAILMON_COLLECTOR ("auto", "sysinfo" or "proc"; default: auto)
+pub fn get_collector_kind() -> CollectorKind {
+    match env::var("TAILMON_COLLECTOR").ok().as_deref().map(str::trim) {
+        None | Some("") | Some("auto") => CollectorKind::Auto,
+        Some("sysinfo") => CollectorKind::Sysinfo,
+        Some("proc") if ProcCollector::is_available() => CollectorKind::Proc,
+        Some("proc") => {
+            warn!("TAILMON_COLLECTOR=proc needs a readable /proc, using sysinfo");
+            CollectorKind::Sysinfo
+        }
+        Some(other) => {
+            warn!("Ignoring invalid value for TAILMON_COLLECTOR (expected auto, sysinfo or proc): {}", other);
+            CollectorKind::Auto
+        }
+    }
+}
+
+/// sysinfo, replaced by `ProcCollector` once a sample reports no RAM, which happens in
+/// minimal containers that do not expose the files sysinfo reads
+struct FallbackCollector {
+    sysinfo: SysinfoCollector,
+    proc: ProcCollector,
+    fallen_back: bool,
+}
+
+impl Collector for FallbackCollector {
+    fn collect(&mut self) -> SystemInfo {
+        if !self.fallen_back {
+            let sample = self.sysinfo.collect();
+            if sample.ram_total_mb > 0 || !ProcCollector::is_available() {
+                return sample;
+            }
+            warn!("sysinfo reported no memory, reading /proc directly from now on");
+            self.fallen_back = true;
+        }
+        self.proc.collect()
+    }
+}
+
+/// Build the selected collector. With `cgroup_aware`, the limits it reports against are logged.
+pub fn build(
+    kind: CollectorKind,
+    ui_meta: HashMap<String, String>,
+    send_rates: bool,
+    cgroup_aware: bool,
+    watched: Vec<String>,
+) -> Box<dyn Collector> {
+    if kind == CollectorKind::Proc && !watched.is_empty() {
+        warn!("The proc collector cannot report TAILMON_WATCH_PROCS");
+    }
+    let proc = ProcCollector::new(ui_meta.clone(), send_rates, cgroup_aware);
+    if cgroup_aware {
+        match proc.cgroup_limits() {
+            Some(limits) => info!("Cgroup-aware mode: reporting against {}", limits),
+            None => info!("Cgroup-aware mode: no cgroup v2 found, reporting host values"),
+        }
+    }
+    match kind {
+        CollectorKind::Proc => Box::new(proc),
+        CollectorKind::Sysinfo => Box::new(SysinfoCollector::new(ui_meta, send_rates, cgroup_aware, watched)),
+        CollectorKind::Auto => Box::new(FallbackCollector {
+            sysinfo: SysinfoCollector::new(ui_meta, send_rates, cgroup_aware, watched),
+            proc,
+            fallen_back: false,
+        }),
+    }
+}
@@ -0,0 +1,101 @@
+use axum::{extract::State, response::Json, routing::get, Router};
+use crate::endpoint::Endpoint;
+use common::SystemInfo;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+/// Configuration the agent is running with; secrets are only reported as set or not
+#[derive(Debug, Serialize, Clone)]
+pub struct EffectiveConfig {
+    pub server_urls: Vec<String>,
+    pub delivery_mode: &'static str,
+    pub api_key_set: bool,
+
+    /// Key id of the signing secret (empty without one), `None` when requests are not signed
+    pub hmac_key_id: Option<String>,
+    pub interval_secs: u64,
+    pub adaptive_cpu: Option<f32>,
+    pub collect_timeout_secs: u64,
+    pub warmup_samples: u32,
+    pub stagger: bool,
+    pub exec_hook: Option<String>,
+    pub exec_hook_timeout_secs: u64,
+    pub on_failure_cmd: Option<String>,
+    pub on_failure_after: Option<u32>,
+    pub send_rates: bool,
+    pub cgroup_aware: bool,
+    pub collector: &'static str,
+    pub watch_procs: Vec<String>,
+    pub cpu_precision: u32,
+    pub float_precision: u32,
+    pub redact: Vec<&'static str>,
+    pub anonymize: bool,
+    pub send_fields: Option<Vec<&'static str>>,
+    pub spool_path: Option<String>,
+    pub spool_max: usize,
+    pub batch_size: usize,
+    pub batch_max_age_secs: u64,
+}
+
+/// Snapshot of the agent's main loop served at `GET /debug/state`
+#[derive(Debug, Serialize, Clone)]
+pub struct DebugState {
+    pub last_sample: Option<SystemInfo>,
+    pub consecutive_failures: u32,
+    pub queue_depth: usize,
+    pub endpoints: Vec<EndpointState>,
+    pub server_interval_secs: Option<u64>,
+    pub config: EffectiveConfig,
+}
+
+/// Delivery state of one server
+#[derive(Debug, Serialize, Clone)]
+pub struct EndpointState {
+    pub url: String,
+    pub consecutive_failures: u32,
+    pub queue_depth: usize,
+    pub paused: bool,
+}
+
+impl From<&Endpoint> for EndpointState {
+    fn from(endpoint: &Endpoint) -> Self {
+        EndpointState {
+            url: endpoint.url.clone(),
+            consecutive_failures: endpoint.consecutive_failures,
+            queue_depth: endpoint.spool.len(),
+            paused: endpoint.is_paused(),
+        }
+    }
+}
+
+pub type SharedDebugState = Arc<Mutex<DebugState>>;
+
+/// Handler function returning the current debug snapshot
+async fn get_state(State(state): State<SharedDebugState>) -> Json<DebugState> {
+    let state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    Json(state.clone())
+}
+
+/// Serve the debug endpoint until the process exits; failing to bind only disables it
+pub async fn serve(addr: SocketAddr, state: SharedDebugState) {
+    if !addr.ip().is_loopback() {
+        warn!("Debug endpoint bound to non-loopback address {}, it exposes the agent's configuration", addr);
+    }
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind debug endpoint on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Debug endpoint listening on http://{}/debug/state", addr);
+
+    let app = Router::new()
+        .route("/debug/state", get(get_state))
+        .with_state(state);
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("Debug endpoint stopped: {}", e);
+    }
+}
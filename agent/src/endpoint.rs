@@ -0,0 +1,98 @@
+use crate::spool::Spool;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Consecutive network failures after which a server is paused
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+
+/// How long a server is left alone after too many consecutive failures
+const PAUSE_DURATION: Duration = Duration::from_secs(30);
+
+/// When a cycle counts as delivered with several servers (TAILMON_DELIVERY_MODE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// At least one server accepted the sample
+    Any,
+
+    /// Every server accepted the sample
+    All,
+}
+
+impl DeliveryMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "any" => Some(DeliveryMode::Any),
+            "all" => Some(DeliveryMode::All),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeliveryMode::Any => "any",
+            DeliveryMode::All => "all",
+        }
+    }
+
+    /// Whether a cycle with `succeeded` of `total` servers delivering is a success
+    pub fn satisfied(self, succeeded: usize, total: usize) -> bool {
+        match self {
+            DeliveryMode::Any => succeeded > 0,
+            DeliveryMode::All => succeeded == total,
+        }
+    }
+}
+
+/// One server the agent reports to, with its own offline buffer and failure tracking,
+/// so an unreachable server neither blocks nor loses samples for the others
+pub struct Endpoint {
+    pub url: String,
+    pub spool: Spool,
+
+    /// Failed deliveries since the last success
+    pub consecutive_failures: u32,
+    paused_until: Option<Instant>,
+}
+
+impl Endpoint {
+    pub fn new(url: String, spool: Spool) -> Self {
+        Endpoint { url, spool, consecutive_failures: 0, paused_until: None }
+    }
+
+    /// Whether delivery is skipped this cycle after repeated failures (samples are still buffered)
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.paused_until = None;
+    }
+
+    /// Skip the server for `duration`, e.g. as asked by its `Retry-After` header
+    pub fn pause_for(&mut self, duration: Duration) {
+        self.paused_until = Some(Instant::now() + duration);
+    }
+
+    /// Count a failed delivery; repeated network failures pause the server for a while
+    pub fn record_failure(&mut self, network: bool) {
+        self.consecutive_failures += 1;
+        if network && self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+            warn!("⚠️  Too many consecutive failures ({}) for {}, pausing it for {} seconds...",
+                self.consecutive_failures, self.url, PAUSE_DURATION.as_secs());
+            self.paused_until = Some(Instant::now() + PAUSE_DURATION);
+        }
+    }
+}
+
+/// Spool file of the server at `index`: the configured path with a single
+/// server, `<path>.<index>` with several so their buffers stay apart
+pub fn spool_path(path: &Path, index: usize, count: usize) -> PathBuf {
+    if count == 1 {
+        return path.to_path_buf();
+    }
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", index));
+    path.with_file_name(file_name)
+}
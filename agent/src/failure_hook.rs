@@ -0,0 +1,101 @@
+use crate::endpoint::MAX_CONSECUTIVE_FAILURES;
+use crate::shell_command;
+use std::env;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Time limit for the failure command before it is killed
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the failure command is told about the outage, as TAILMON_FAILURE_* variables
+pub struct FailureContext {
+    /// Failed cycles in a row
+    pub failures: u32,
+
+    /// First failure of the round that triggered the command
+    pub error: String,
+
+    /// Samples buffered for the servers, summed
+    pub queued: usize,
+    pub servers: Vec<String>,
+}
+
+/// Local command run once per outage after repeated failed cycles (TAILMON_ON_FAILURE_CMD),
+/// so the host can raise the alarm through another channel while no server is reachable
+pub struct FailureHook {
+    pub command: String,
+
+    /// Failed cycles in a row before the command runs
+    pub after: u32,
+
+    /// Start of the current outage, and whether the command already ran for it
+    failing_since: Option<Instant>,
+    fired: bool,
+}
+
+impl FailureHook {
+    /// Count a failed cycle, starting the command in the background once the outage is long enough
+    pub fn failed(&mut self, context: FailureContext) {
+        let since = *self.failing_since.get_or_insert_with(Instant::now);
+        if self.fired || context.failures < self.after {
+            return;
+        }
+        self.fired = true;
+        warn!("{} cycles failed in a row, running TAILMON_ON_FAILURE_CMD", context.failures);
+        tokio::spawn(run(self.command.clone(), context, since.elapsed()));
+    }
+
+    /// End the outage after a successful cycle, so the next one runs the command again
+    pub fn recovered(&mut self) {
+        if self.fired {
+            info!("Delivery recovered after TAILMON_ON_FAILURE_CMD ran");
+        }
+        self.failing_since = None;
+        self.fired = false;
+    }
+}
+
+/// Get the failure command from TAILMON_ON_FAILURE_CMD and the failed cycles it waits for from
+/// TAILMON_ON_FAILURE_AFTER (default: 5); `None` when no command is configured
+pub fn get_failure_hook() -> Option<FailureHook> {
+    let command = env::var("TAILMON_ON_FAILURE_CMD").ok().filter(|command| !command.trim().is_empty())?;
+    let after = match env::var("TAILMON_ON_FAILURE_AFTER").ok().map(|raw| (raw.trim().parse::<u32>(), raw)) {
+        None => MAX_CONSECUTIVE_FAILURES,
+        Some((Ok(after), _)) if after > 0 => after,
+        Some((_, raw)) => {
+            warn!("Ignoring invalid value for TAILMON_ON_FAILURE_AFTER (expected a positive number): {}", raw);
+            MAX_CONSECUTIVE_FAILURES
+        }
+    };
+    Some(FailureHook { command, after, failing_since: None, fired: false })
+}
+
+/// Run the failure command through the platform shell with the outage in its environment
+async fn run(command: String, context: FailureContext, failing_for: Duration) {
+    let child = shell_command(&command)
+        .env("TAILMON_FAILURE_COUNT", context.failures.to_string())
+        .env("TAILMON_FAILURE_SECS", failing_for.as_secs().to_string())
+        .env("TAILMON_FAILURE_ERROR", &context.error)
+        .env("TAILMON_FAILURE_QUEUED", context.queued.to_string())
+        .env("TAILMON_FAILURE_SERVERS", context.servers.join(","))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("TAILMON_ON_FAILURE_CMD failed to start: {}", e);
+            return;
+        }
+    };
+    match tokio::time::timeout(TIMEOUT, child.wait_with_output()).await {
+        Ok(Ok(output)) if output.status.success() => info!("TAILMON_ON_FAILURE_CMD completed"),
+        Ok(Ok(output)) => warn!("TAILMON_ON_FAILURE_CMD exited with {}: {}",
+            output.status, String::from_utf8_lossy(&output.stderr).trim()),
+        Ok(Err(e)) => warn!("TAILMON_ON_FAILURE_CMD failed to run: {}", e),
+        Err(_) => warn!("TAILMON_ON_FAILURE_CMD timed out after {}s", TIMEOUT.as_secs()),
+    }
+}
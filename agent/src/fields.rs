@@ -0,0 +1,124 @@
+use common::{SystemInfo, CAPABILITY_BATTERY, CAPABILITY_CUSTOM, CAPABILITY_NET_RATES, CAPABILITY_PROCESSES, CAPABILITY_SWAP};
+use std::env;
+use tracing::warn;
+
+/// Optional SystemInfo fields the agent can leave out of what it sends (TAILMON_SEND_FIELDS).
+/// Identity, OS, CPU, RAM, timestamp, capabilities and checksum are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendField {
+    CollectDuration,
+    Swap,
+    NetRates,
+    UiMeta,
+    Custom,
+    Battery,
+    WatchedProcesses,
+    Hardware,
+}
+
+impl SendField {
+    const ALL: [SendField; 8] = [
+        SendField::CollectDuration, SendField::Swap, SendField::NetRates, SendField::UiMeta, SendField::Custom, SendField::Battery,
+        SendField::WatchedProcesses, SendField::Hardware,
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "collect_duration" => Some(SendField::CollectDuration),
+            "swap" => Some(SendField::Swap),
+            "net_rates" => Some(SendField::NetRates),
+            "ui_meta" => Some(SendField::UiMeta),
+            "custom" => Some(SendField::Custom),
+            "battery" => Some(SendField::Battery),
+            "watched_processes" => Some(SendField::WatchedProcesses),
+            "hardware" => Some(SendField::Hardware),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SendField::CollectDuration => "collect_duration",
+            SendField::Swap => "swap",
+            SendField::NetRates => "net_rates",
+            SendField::UiMeta => "ui_meta",
+            SendField::Custom => "custom",
+            SendField::Battery => "battery",
+            SendField::WatchedProcesses => "watched_processes",
+            SendField::Hardware => "hardware",
+        }
+    }
+}
+
+/// Parse the comma-separated list of optional fields to send from TAILMON_SEND_FIELDS.
+/// Unset sends everything; an empty value sends only the required fields.
+pub fn get_send_fields() -> Option<Vec<SendField>> {
+    let raw = env::var("TAILMON_SEND_FIELDS").ok()?;
+    let fields = raw.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let field = SendField::parse(name);
+            if field.is_none() {
+                warn!("Ignoring unknown TAILMON_SEND_FIELDS field: {}", name);
+            }
+            field
+        })
+        .collect();
+    Some(fields)
+}
+
+/// Reset every optional field of a sample that is not in `sent` to its default, along with
+/// its capability. Done before sealing, so the checksum matches what the server rebuilds.
+pub fn trim(system_info: &mut SystemInfo, sent: &[SendField]) {
+    for field in SendField::ALL.iter().filter(|field| !sent.contains(field)) {
+        match field {
+            SendField::CollectDuration => system_info.collect_duration_ms = 0,
+            SendField::Swap => {
+                system_info.swap_used_mb = 0;
+                system_info.swap_total_mb = 0;
+                system_info.remove_capability(CAPABILITY_SWAP);
+            }
+            SendField::NetRates => {
+                system_info.net_rx_rate_bytes_per_sec = None;
+                system_info.net_tx_rate_bytes_per_sec = None;
+                system_info.remove_capability(CAPABILITY_NET_RATES);
+            }
+            SendField::UiMeta => system_info.ui_meta.clear(),
+            SendField::Custom => {
+                system_info.custom.clear();
+                system_info.remove_capability(CAPABILITY_CUSTOM);
+            }
+            SendField::Battery => {
+                system_info.battery = None;
+                system_info.remove_capability(CAPABILITY_BATTERY);
+            }
+            SendField::WatchedProcesses => {
+                system_info.watched_processes.clear();
+                system_info.remove_capability(CAPABILITY_PROCESSES);
+            }
+            SendField::Hardware => system_info.hardware = None,
+        }
+    }
+}
+
+/// Optional keys the server fills in with their default when they are missing
+const DEFAULTED_KEYS: [&str; 5] = ["collect_duration_ms", "swap_used_mb", "swap_total_mb", "ui_meta", "custom"];
+
+/// JSON body of a sample with optional keys left out when they hold their default
+/// (e.g. trimmed fields), since the server deserializes them back to the same value
+pub fn payload(system_info: &SystemInfo) -> serde_json::Value {
+    let mut value = serde_json::to_value(system_info).unwrap_or_default();
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, value| !DEFAULTED_KEYS.contains(&key.as_str()) || !is_default(value));
+    }
+    value
+}
+
+fn is_default(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Number(number) => number.as_u64() == Some(0),
+        serde_json::Value::Object(object) => object.is_empty(),
+        _ => false,
+    }
+}
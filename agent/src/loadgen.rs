@@ -0,0 +1,166 @@
+use crate::post_json;
+use chrono::Utc;
+use common::signing::SigningKey;
+use common::{SystemInfo, CAPABILITY_SWAP};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Defaults of `agent loadgen [devices] [rate] [duration]`
+const DEFAULT_DEVICES: u32 = 100;
+const DEFAULT_RATE: u32 = 50;
+const DEFAULT_DURATION_SECS: u32 = 30;
+
+/// Parameters of a load test
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    /// Virtual devices, reporting in turn
+    devices: u32,
+
+    /// Requests started per second, across all devices
+    rate: u32,
+    duration: Duration,
+}
+
+/// Parse `loadgen [devices] [rate] [duration-secs]` from the command line arguments;
+/// `None` when not generating load
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Result<LoadOptions, String>> {
+    if args.next().as_deref() != Some("loadgen") {
+        return None;
+    }
+    Some(parse_options(args))
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<LoadOptions, String> {
+    let mut positive = |name: &str, default: u32| match args.next() {
+        None => Ok(default),
+        Some(value) => value.parse().ok().filter(|value| *value > 0)
+            .ok_or_else(|| format!("invalid {}: {}", name, value)),
+    };
+    Ok(LoadOptions {
+        devices: positive("device count", DEFAULT_DEVICES)?,
+        rate: positive("rate", DEFAULT_RATE)?,
+        duration: Duration::from_secs(positive("duration", DEFAULT_DURATION_SECS)? as u64),
+    })
+}
+
+/// xorshift64* generator; synthetic values only need to vary, not be unpredictable
+struct Rng(u64);
+
+impl Rng {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_nanos() as u64).unwrap_or_default();
+        Rng(nanos | 1)
+    }
+
+    /// Uniform value in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A plausible, sealed sample of virtual device `index` with randomized load
+fn synthetic_sample(index: u32, rng: &mut Rng) -> SystemInfo {
+    // Sizes stay fixed per device so the server does not see hardware changes
+    let ram_total_mb = 2048u64 << (index % 4);
+    let swap_total_mb = ram_total_mb / 2;
+    let mut system_info = SystemInfo {
+        device_id: format!("loadgen-{:05}", index),
+        os_info: "Loadgen virtual device".to_string(),
+        cpu_usage: (rng.next_f64() * 100.0) as f32,
+        ram_used_mb: (rng.next_f64() * ram_total_mb as f64) as u64,
+        ram_total_mb,
+        collect_duration_ms: (rng.next_f64() * 50.0) as u64,
+        last_seen: Utc::now().to_rfc3339(),
+        swap_used_mb: (rng.next_f64() * swap_total_mb as f64) as u64,
+        swap_total_mb,
+        net_rx_rate_bytes_per_sec: None,
+        net_tx_rate_bytes_per_sec: None,
+        ui_meta: Default::default(),
+        custom: Default::default(),
+        capabilities: vec![CAPABILITY_SWAP.to_string()],
+        checksum: None,
+        via: Vec::new(),
+        battery: None,
+        watched_processes: Default::default(),
+        hardware: None,
+        idempotency_key: None,
+    };
+    system_info.seal();
+    system_info
+}
+
+/// Outcome of one request
+enum Outcome {
+    Status(u16, Duration),
+    Error,
+}
+
+/// POST synthetic samples to `server_url` at the requested rate for the requested time,
+/// then print throughput, status counts and latency percentiles. Returns the process exit code.
+pub async fn run(client: &reqwest::Client, server_url: &str, api_key: Option<&str>, signing_key: Option<&SigningKey>, options: LoadOptions) -> i32 {
+    println!("Generating load on {}: {} virtual devices, {} requests/s for {} seconds...",
+        server_url, options.devices, options.rate, options.duration.as_secs());
+    let outcomes: Arc<Mutex<Vec<Outcome>>> = Arc::new(Mutex::new(Vec::new()));
+    let mut rng = Rng::seeded();
+    let mut requests = Vec::new();
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rate as f64));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let started = Instant::now();
+    let mut next_device = 0;
+    while started.elapsed() < options.duration {
+        ticker.tick().await;
+        let request = post_json(client, server_url, &synthetic_sample(next_device, &mut rng), api_key, signing_key);
+        next_device = (next_device + 1) % options.devices;
+        let outcomes = outcomes.clone();
+        requests.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            let outcome = match request.send().await {
+                Ok(response) => Outcome::Status(response.status().as_u16(), sent.elapsed()),
+                Err(_) => Outcome::Error,
+            };
+            outcomes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(outcome);
+        }));
+    }
+    let sending = started.elapsed();
+    futures_util::future::join_all(requests).await;
+    let elapsed = started.elapsed();
+
+    let outcomes = std::mem::take(&mut *outcomes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    let mut statuses: BTreeMap<u16, usize> = BTreeMap::new();
+    let mut latencies: Vec<Duration> = Vec::new();
+    let mut errors = 0;
+    for outcome in &outcomes {
+        match outcome {
+            Outcome::Status(status, latency) => {
+                *statuses.entry(*status).or_default() += 1;
+                latencies.push(*latency);
+            }
+            Outcome::Error => errors += 1,
+        }
+    }
+    let accepted: usize = statuses.iter().filter(|(status, _)| (200..300).contains(*status)).map(|(_, count)| count).sum();
+
+    println!("Requests:   {} sent in {:.1} s ({:.1}/s offered)", outcomes.len(), sending.as_secs_f64(), outcomes.len() as f64 / sending.as_secs_f64());
+    println!("Throughput: {:.1} accepted/s ({} accepted, {} network errors)", accepted as f64 / elapsed.as_secs_f64(), accepted, errors);
+    for (status, count) in &statuses {
+        println!("  HTTP {}:  {}", status, count);
+    }
+    if latencies.is_empty() {
+        println!("Latency:    no responses");
+        return 1;
+    }
+    latencies.sort();
+    let percentile = |p: usize| millis(latencies[(latencies.len() * p / 100).min(latencies.len() - 1)]);
+    println!("Latency:    min {:.1} ms, p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+        millis(latencies[0]), percentile(50), percentile(95), percentile(99), millis(latencies[latencies.len() - 1]));
+    0
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -1,88 +1,815 @@
-use common::SystemInfo;
-use sysinfo::{System, SystemExt, CpuExt};
-use chrono::Utc;
+mod batch;
+mod bench;
+mod collectors;
+mod debug;
+mod endpoint;
+mod failure_hook;
+mod fields;
+#[cfg(feature = "loadgen")]
+mod loadgen;
+mod precision;
+mod readiness;
+mod service;
+mod spool;
+
+use common::collector::Collector;
+use common::signing::{SigningKey, SIGNATURE_HEADER};
+use common::{AgentCommand, IngestResponse, SystemInfo, CAPABILITY_CUSTOM, CAPABILITY_PROCESSES, MAX_BATCH_SIZE, MAX_PROCESS_NAME_LEN, MAX_UI_META_ENTRIES, MAX_UI_META_LEN, MAX_WATCHED_PROCESSES};
+use debug::{DebugState, EffectiveConfig, EndpointState, SharedDebugState};
+use endpoint::{DeliveryMode, Endpoint};
+use failure_hook::FailureContext;
+use fields::SendField;
+use precision::Precision;
+use sha2::{Digest, Sha256};
+use spool::{Spool, DEFAULT_SPOOL_MAX};
+use std::collections::HashMap;
 use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing::{info, warn, error};
 
 // Default server URL - can be overridden by TAILMON_SERVER_URL environment variable
 const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:3000/api/metrics";
 
-/// Get server URL from environment variable or use default
-fn get_server_url() -> String {
-    env::var("TAILMON_SERVER_URL").unwrap_or_else(|_| DEFAULT_SERVER_URL.to_string())
+/// Collector shared with the blocking collection task
+type SharedCollector = Arc<Mutex<Box<dyn Collector>>>;
+
+/// Get the server URLs from TAILMON_SERVER_URL (comma-separated for several servers) or use the default
+fn get_server_urls() -> Vec<String> {
+    let raw = env::var("TAILMON_SERVER_URL").unwrap_or_default();
+    let urls: Vec<String> = raw.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+    if urls.is_empty() {
+        vec![DEFAULT_SERVER_URL.to_string()]
+    } else {
+        urls
+    }
+}
+
+/// Get when a cycle counts as delivered from TAILMON_DELIVERY_MODE ("any" or "all")
+fn get_delivery_mode() -> DeliveryMode {
+    let raw = env::var("TAILMON_DELIVERY_MODE").unwrap_or_default();
+    if raw.trim().is_empty() {
+        return DeliveryMode::Any;
+    }
+    DeliveryMode::parse(raw.trim()).unwrap_or_else(|| {
+        warn!("Ignoring invalid value for TAILMON_DELIVERY_MODE: {}", raw);
+        DeliveryMode::Any
+    })
+}
+
+/// Default seconds between reports when neither the environment nor the server sets one
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+
+/// Get the reporting interval from TAILMON_INTERVAL_SECS (a server-pushed interval takes precedence)
+fn get_interval() -> u64 {
+    env::var("TAILMON_INTERVAL_SECS").ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_INTERVAL_SECS)
+}
+
+/// CPU usage (%) above which the adaptive mode backs off, unless TAILMON_ADAPTIVE_CPU says otherwise
+const DEFAULT_ADAPTIVE_CPU: f32 = 80.0;
+
+/// Get the CPU threshold of the adaptive mode; `None` unless TAILMON_ADAPTIVE=1
+fn get_adaptive_cpu() -> Option<f32> {
+    let enabled = env::var("TAILMON_ADAPTIVE").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+    let raw = env::var("TAILMON_ADAPTIVE_CPU").unwrap_or_default();
+    if raw.trim().is_empty() {
+        return Some(DEFAULT_ADAPTIVE_CPU);
+    }
+    match raw.trim().parse::<f32>() {
+        Ok(threshold) if (0.0..=100.0).contains(&threshold) => Some(threshold),
+        _ => {
+            warn!("Ignoring invalid value for TAILMON_ADAPTIVE_CPU: {}", raw);
+            Some(DEFAULT_ADAPTIVE_CPU)
+        }
+    }
+}
+
+/// Whether the host is too busy to report at the normal interval: the last sample's
+/// CPU usage is above the adaptive threshold (always false with the adaptive mode off)
+fn is_busy(cpu_usage: f32, adaptive_cpu: Option<f32>) -> bool {
+    adaptive_cpu.is_some_and(|threshold| cpu_usage > threshold)
+}
+
+/// Get the bind address of the optional debug endpoint from TAILMON_AGENT_DEBUG_ADDR (e.g. 127.0.0.1:9101)
+fn get_debug_addr() -> Option<SocketAddr> {
+    let value = env::var("TAILMON_AGENT_DEBUG_ADDR").ok().filter(|value| !value.trim().is_empty())?;
+    match value.trim().parse() {
+        Ok(addr) => Some(addr),
+        Err(_) => {
+            warn!("Ignoring invalid TAILMON_AGENT_DEBUG_ADDR: {}", value);
+            None
+        }
+    }
+}
+
+/// Get the API key sent as a bearer token from TAILMON_API_KEY
+fn get_api_key() -> Option<String> {
+    env::var("TAILMON_API_KEY").ok().filter(|key| !key.trim().is_empty())
+}
+
+/// Get the signing key from TAILMON_HMAC_SECRET, with the key id hint from TAILMON_HMAC_KEY_ID
+fn get_signing_key() -> Option<SigningKey> {
+    let secret = env::var("TAILMON_HMAC_SECRET").ok().filter(|secret| !secret.trim().is_empty())?;
+    let key_id = env::var("TAILMON_HMAC_KEY_ID").ok().map(|key_id| key_id.trim().to_string()).filter(|key_id| !key_id.is_empty());
+    Some(SigningKey { key_id, secret })
 }
 
-/// Collects system information using sysinfo library
-async fn get_system_info() -> SystemInfo {
-    // Create a new System instance
-    let mut system = System::new_all();
+/// POST `payload` as JSON with the API key as bearer token, and the body's signature when signing is configured
+fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &impl serde::Serialize,
+    api_key: Option<&str>,
+    signing_key: Option<&SigningKey>,
+) -> reqwest::RequestBuilder {
+    let body = serde_json::to_vec(payload).unwrap_or_default();
+    let mut request = client.post(url).header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(signing_key) = signing_key {
+        request = request.header(SIGNATURE_HEADER, signing_key.header_value(&body));
+    }
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+    request.body(body)
+}
+
+/// Parse extra request headers from TAILMON_HEADERS ("Name: Value" pairs separated by newlines or
+/// semicolons), e.g. for an auth proxy in front of the server. Invalid entries are skipped with a warning.
+fn get_headers() -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let raw = env::var("TAILMON_HEADERS").unwrap_or_default();
     
-    // Refresh all system information
-    system.refresh_all();
+    for entry in raw.split(['\n', ';']).map(str::trim).filter(|entry| !entry.is_empty()) {
+        let Some((name, value)) = entry.split_once(':') else {
+            warn!("Ignoring malformed TAILMON_HEADERS entry: {}", entry);
+            continue;
+        };
+        let Ok(name) = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes()) else {
+            warn!("Ignoring TAILMON_HEADERS entry with an invalid name: {}", name.trim());
+            continue;
+        };
+        let Ok(value) = reqwest::header::HeaderValue::from_str(value.trim()) else {
+            warn!("Ignoring TAILMON_HEADERS entry with an invalid value for {}", name);
+            continue;
+        };
+        headers.append(name, value);
+    }
     
-    // Wait a bit for CPU usage calculation (sysinfo needs time to calculate)
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-    system.refresh_cpu();
+    headers
+}
+
+/// Proxy variables reqwest reads on its own (uppercase wins over lowercase)
+const PROXY_ENV_VARS: [&str; 6] = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"];
+
+/// A proxy URL with its user name and password removed, for logging.
+/// Like reqwest, a URL without a scheme is taken as http://.
+fn without_credentials(url: &str) -> String {
+    let parsed = if url.contains("://") { reqwest::Url::parse(url) } else { reqwest::Url::parse(&format!("http://{}", url)) };
+    match parsed {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        Err(_) => "<invalid URL>".to_string(),
+    }
+}
+
+/// Get the proxy for all requests from TAILMON_PROXY (http://, https:// or socks5://).
+/// Hosts in NO_PROXY bypass it, as they do for the standard proxy variables.
+fn get_proxy() -> Result<Option<reqwest::Proxy>, String> {
+    let Some(url) = env::var("TAILMON_PROXY").ok().filter(|url| !url.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let proxy = reqwest::Proxy::all(url.trim()).map_err(|e| format!("invalid TAILMON_PROXY {}: {}", without_credentials(url.trim()), e))?;
+    info!("Using proxy {} (TAILMON_PROXY)", without_credentials(url.trim()));
+    Ok(Some(proxy.no_proxy(reqwest::NoProxy::from_env())))
+}
+
+/// Get the offline buffer file from TAILMON_SPOOL_PATH (unset keeps the buffer in memory only)
+fn get_spool_path() -> Option<PathBuf> {
+    env::var("TAILMON_SPOOL_PATH").ok().filter(|path| !path.trim().is_empty()).map(PathBuf::from)
+}
+
+/// Get the offline buffer capacity from TAILMON_SPOOL_MAX
+fn get_spool_max() -> usize {
+    env::var("TAILMON_SPOOL_MAX").ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_SPOOL_MAX)
+}
+
+/// Longest `Retry-After` the agent honors, so a misconfigured server cannot stall it indefinitely
+const MAX_RETRY_AFTER_SECS: u64 = 600;
+
+/// Delay requested by a 429 or 503 response's `Retry-After` header (delta-seconds form)
+fn retry_after_secs(response: &reqwest::Response) -> Option<u64> {
+    let status = response.status();
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS && status != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    let secs: u64 = value.trim().parse().ok()?;
+    Some(secs.min(MAX_RETRY_AFTER_SECS))
+}
+
+/// Why a delivery attempt failed
+enum DeliveryError {
+    /// The server answered with a non-success status, possibly asking to retry after some seconds
+    Status(reqwest::StatusCode, Option<u64>),
+    /// The request never got a response
+    Network(reqwest::Error),
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::Status(status, _) => write!(f, "server returned {}", status),
+            DeliveryError::Network(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Outcome of a one-shot (`--once`) run, mapped to the process exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnceError {
+    /// No sample could be collected (exit code 1)
+    Collection,
+    /// The server rejected the sample as invalid, e.g. 401 or 422 (exit code 2)
+    Validation,
+    /// The server was unreachable or failed temporarily, e.g. 5xx or 429 (exit code 3)
+    Network,
+}
+
+impl OnceError {
+    fn exit_code(self) -> i32 {
+        match self {
+            OnceError::Collection => 1,
+            OnceError::Validation => 2,
+            OnceError::Network => 3,
+        }
+    }
+}
+
+impl From<DeliveryError> for OnceError {
+    fn from(error: DeliveryError) -> Self {
+        match error {
+            DeliveryError::Status(status, _)
+                if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS => OnceError::Validation,
+            DeliveryError::Status(..) | DeliveryError::Network(_) => OnceError::Network,
+        }
+    }
+}
+
+/// Send queued samples to the server, oldest first. A single sample goes to the
+/// regular endpoint; a backlog is flushed through the batch endpoint in chunks.
+/// Samples rejected with a client error are dropped since retrying cannot help.
+/// Returns the reporting interval requested by the server's last response, if any,
+/// and the commands of every response.
+async fn deliver(
+    client: &reqwest::Client,
+    server_url: &str,
+    api_key: Option<&str>,
+    signing_key: Option<&SigningKey>,
+    spool: &mut Spool,
+) -> Result<IngestResponse, DeliveryError> {
+    let mut reply = IngestResponse::default();
+    while spool.len() > 0 {
+        let chunk = spool.peek(MAX_BATCH_SIZE);
+        let request = if chunk.len() == 1 {
+            post_json(client, server_url, &fields::payload(&chunk[0]), api_key, signing_key)
+        } else {
+            info!("Flushing {} buffered samples", chunk.len());
+            let batch: Vec<_> = chunk.iter().map(fields::payload).collect();
+            post_json(client, &format!("{}/batch", server_url), &batch, api_key, signing_key)
+        };
+        
+        let response = request.send().await.map_err(DeliveryError::Network)?;
+        let status = response.status();
+        if status.is_success() {
+            spool.acknowledge(chunk.len());
+            // Older servers answer with plain text, which simply carries no interval
+            let body = response.json::<IngestResponse>().await.unwrap_or_default();
+            reply.accepted += body.accepted;
+            reply.duplicates += body.duplicates;
+            if body.duplicates > 0 {
+                info!("Server already had {} of the samples, not storing them again", body.duplicates);
+            }
+            reply.interval_secs = body.interval_secs;
+            reply.commands.extend(body.commands);
+        } else {
+            if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                warn!("Server rejected {} samples with {}, dropping them", chunk.len(), status);
+                spool.discard(chunk.len());
+            }
+            return Err(DeliveryError::Status(status, retry_after_secs(&response)));
+        }
+    }
+    Ok(reply)
+}
+
+/// Result of one delivery round to every server
+#[derive(Default)]
+struct RoundOutcome {
+    /// Servers that accepted everything queued for them
+    succeeded: usize,
+
+    /// Reporting interval requested by the first server that asked for one
+    interval_secs: Option<u64>,
+
+    /// Commands the servers sent this round
+    commands: Vec<AgentCommand>,
+
+    /// Longest `Retry-After` a server answered with this round
+    retry_after_secs: Option<u64>,
+
+    /// First failure of the round, if any
+    error: Option<DeliveryError>,
+}
+
+/// Deliver every server's queue concurrently. Paused servers (see `Endpoint::is_paused`),
+/// including ones that asked to be retried later, keep buffering and are skipped until their pause ends.
+async fn deliver_all(client: &reqwest::Client, api_key: Option<&str>, signing_key: Option<&SigningKey>, endpoints: &mut [Endpoint]) -> RoundOutcome {
+    let rounds = endpoints.iter_mut().map(|endpoint| async move {
+        if endpoint.is_paused() {
+            info!("Skipping paused {} ({} samples buffered)", endpoint.url, endpoint.spool.len());
+            return None;
+        }
+        let result = deliver(client, &endpoint.url, api_key, signing_key, &mut endpoint.spool).await;
+        match &result {
+            Ok(_) => {
+                info!("✅ Successfully sent data to {}", endpoint.url);
+                endpoint.record_success();
+            }
+            Err(DeliveryError::Status(status, retry_after)) => {
+                warn!("❌ {} returned error status: {} ({} samples buffered)", endpoint.url, status, endpoint.spool.len());
+                endpoint.record_failure(false);
+                if let Some(secs) = *retry_after {
+                    info!("{} asked to retry after {} seconds", endpoint.url, secs);
+                    endpoint.pause_for(Duration::from_secs(secs));
+                }
+            }
+            Err(DeliveryError::Network(e)) => {
+                error!("❌ Failed to send data to {}: {} ({} samples buffered)", endpoint.url, e, endpoint.spool.len());
+                endpoint.record_failure(true);
+            }
+        }
+        Some(result)
+    });
+
+    let mut outcome = RoundOutcome::default();
+    for result in futures_util::future::join_all(rounds).await.into_iter().flatten() {
+        match result {
+            Ok(reply) => {
+                outcome.succeeded += 1;
+                outcome.interval_secs = outcome.interval_secs.or(reply.interval_secs);
+                outcome.commands.extend(reply.commands);
+            }
+            Err(e) => {
+                if let DeliveryError::Status(_, Some(secs)) = e {
+                    outcome.retry_after_secs = outcome.retry_after_secs.max(Some(secs));
+                }
+                outcome.error.get_or_insert(e);
+            }
+        }
+    }
+    outcome
+}
+
+/// Build the HTTP client, adding a client certificate (mTLS) from
+/// TAILMON_CLIENT_CERT/TAILMON_CLIENT_KEY and a server CA from TAILMON_CA_CERT
+fn build_http_client() -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10));
     
-    // Get device ID (hostname)
-    let device_id = system.host_name().unwrap_or_else(|| "unknown".to_string());
+    // An explicit proxy replaces the ones reqwest picks up from HTTPS_PROXY / HTTP_PROXY / ALL_PROXY
+    match get_proxy()? {
+        Some(proxy) => builder = builder.proxy(proxy),
+        None => {
+            for (name, url) in PROXY_ENV_VARS.iter().filter_map(|name| env::var(name).ok().map(|url| (name, url))) {
+                info!("Using proxy {} ({})", without_credentials(&url), name);
+            }
+        }
+    }
+    if let Ok(no_proxy) = env::var("NO_PROXY").or_else(|_| env::var("no_proxy")) {
+        info!("Bypassing the proxy for: {}", no_proxy);
+    }
     
-    // Get OS information with platform-specific details
-    let os_name = system.name().unwrap_or_else(|| "Unknown".to_string());
-    let os_version = system.os_version().unwrap_or_else(|| "Unknown".to_string());
+    // Values may be secrets, so only the names are logged
+    let headers = get_headers();
+    if !headers.is_empty() {
+        info!("Sending custom headers: {:?}", headers.keys().collect::<Vec<_>>());
+        builder = builder.default_headers(headers);
+    }
     
-    // Add platform-specific details
-    let platform_specific_details = get_platform_specific_details(&system);
-    let os_info = if platform_specific_details.is_empty() {
-        format!("{} {}", os_name, os_version)
-    } else {
-        format!("{} {} ({})", os_name, os_version, platform_specific_details)
-    };
+    if let Ok(ca_path) = env::var("TAILMON_CA_CERT") {
+        let pem = std::fs::read(&ca_path).map_err(|e| format!("failed to read {}: {}", ca_path, e))?;
+        let ca = reqwest::Certificate::from_pem(&pem).map_err(|e| format!("invalid CA certificate {}: {}", ca_path, e))?;
+        builder = builder.use_rustls_tls().add_root_certificate(ca);
+    }
     
-    // Get CPU usage
-    let cpu_usage = system.global_cpu_info().cpu_usage();
+    match (env::var("TAILMON_CLIENT_CERT"), env::var("TAILMON_CLIENT_KEY")) {
+        (Ok(cert_path), Ok(key_path)) => {
+            let mut pem = std::fs::read(&cert_path).map_err(|e| format!("failed to read {}: {}", cert_path, e))?;
+            pem.push(b'\n');
+            pem.extend(std::fs::read(&key_path).map_err(|e| format!("failed to read {}: {}", key_path, e))?);
+            let identity = reqwest::Identity::from_pem(&pem).map_err(|e| format!("invalid client certificate/key: {}", e))?;
+            info!("Using client certificate {}", cert_path);
+            builder = builder.use_rustls_tls().identity(identity);
+        }
+        (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+            return Err("TAILMON_CLIENT_CERT and TAILMON_CLIENT_KEY must be set together".to_string());
+        }
+        (Err(_), Err(_)) => {}
+    }
     
-    // Get RAM information (convert from bytes to MB)
-    let ram_used_mb = system.used_memory() / 1024 / 1024;
-    let ram_total_mb = system.total_memory() / 1024 / 1024;
+    builder.build().map_err(|e| format!("failed to create HTTP client: {}", e))
+}
+
+/// Default time limit for the exec hook
+const DEFAULT_EXEC_HOOK_TIMEOUT_SECS: u64 = 5;
+
+/// Get the exec hook command from TAILMON_EXEC_HOOK, if configured
+fn get_exec_hook() -> Option<String> {
+    env::var("TAILMON_EXEC_HOOK").ok().filter(|command| !command.trim().is_empty())
+}
+
+/// Get the exec hook time limit from TAILMON_EXEC_HOOK_TIMEOUT_SECS
+fn get_exec_hook_timeout() -> Duration {
+    let secs = env::var("TAILMON_EXEC_HOOK_TIMEOUT_SECS").ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_EXEC_HOOK_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// `command` run through the platform shell
+fn shell_command(command: &str) -> tokio::process::Command {
+    #[cfg(windows)]
+    let mut cmd = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    cmd.arg("/C").arg(command);
+    #[cfg(not(windows))]
+    let mut cmd = tokio::process::Command::new("sh");
+    #[cfg(not(windows))]
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// Run the exec hook through the platform shell and parse its stdout as a JSON object
+async fn run_exec_hook(command: &str, timeout: Duration) -> Result<HashMap<String, serde_json::Value>, String> {
+    // kill_on_drop makes sure a timed-out hook does not linger
+    let child = shell_command(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to start: {}", e))?;
     
-    // Get current timestamp in ISO 8601 format
-    let last_seen = Utc::now().to_rfc3339();
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| format!("timed out after {}s", timeout.as_secs()))?
+        .map_err(|e| format!("failed to run: {}", e))?;
     
-    SystemInfo {
-        device_id,
-        os_info,
-        cpu_usage,
-        ram_used_mb,
-        ram_total_mb,
-        last_seen,
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("exited with {}: {}", output.status, stderr.trim()));
     }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("stdout is not a JSON object: {}", e))
 }
 
-/// Get platform-specific system details
-fn get_platform_specific_details(system: &System) -> String {
-    #[cfg(target_os = "linux")]
-    {
-        let kernel_version = system.kernel_version().unwrap_or_default();
-        if !kernel_version.is_empty() {
-            return format!("Kernel: {}", kernel_version);
+/// Placeholder for redacted string fields
+const REDACTED: &str = "[redacted]";
+
+/// Fields that TAILMON_REDACT can strip before a sample leaves the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RedactField {
+    OsInfo,
+    UiMeta,
+    Custom,
+    WatchedProcesses,
+
+    /// The machine UUID; the other hardware fields do not identify a machine
+    Hardware,
+}
+
+impl RedactField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "os_info" => Some(RedactField::OsInfo),
+            "ui_meta" => Some(RedactField::UiMeta),
+            "custom" => Some(RedactField::Custom),
+            "watched_processes" => Some(RedactField::WatchedProcesses),
+            "hardware" => Some(RedactField::Hardware),
+            _ => None,
         }
     }
     
-    #[cfg(target_os = "windows")]
-    {
-        // Windows specific details could include build number, edition, etc.
-        return "Windows".to_string();
+    fn name(&self) -> &'static str {
+        match self {
+            RedactField::OsInfo => "os_info",
+            RedactField::UiMeta => "ui_meta",
+            RedactField::Custom => "custom",
+            RedactField::WatchedProcesses => "watched_processes",
+            RedactField::Hardware => "hardware",
+        }
     }
+}
+
+/// Parse the comma-separated list of fields to redact from TAILMON_REDACT
+fn get_redact_fields() -> Vec<RedactField> {
+    let raw = env::var("TAILMON_REDACT").unwrap_or_default();
+    raw.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let field = RedactField::parse(name);
+            if field.is_none() {
+                warn!("Ignoring unknown TAILMON_REDACT field: {}", name);
+            }
+            field
+        })
+        .collect()
+}
+
+/// Redact selected fields of a collected sample: strings become "[redacted]", maps become empty
+/// and the machine UUID is left out
+fn redact(system_info: &mut SystemInfo, fields: &[RedactField]) {
+    for field in fields {
+        match field {
+            RedactField::OsInfo => system_info.os_info = REDACTED.to_string(),
+            RedactField::UiMeta => system_info.ui_meta.clear(),
+            RedactField::Custom => {
+                system_info.custom.clear();
+                system_info.remove_capability(CAPABILITY_CUSTOM);
+            }
+            RedactField::WatchedProcesses => {
+                system_info.watched_processes.clear();
+                system_info.remove_capability(CAPABILITY_PROCESSES);
+            }
+            RedactField::Hardware => {
+                if let Some(hardware) = &mut system_info.hardware {
+                    hardware.uuid = None;
+                }
+            }
+        }
+    }
+}
+
+/// Hex digits of the SHA256 digest kept in an anonymized device ID
+const ANONYMOUS_ID_HEX_LEN: usize = 16;
+
+/// Get the salt for anonymized device IDs when TAILMON_ANONYMIZE is enabled.
+/// The salt comes from TAILMON_ANONYMIZE_SALT or the file named by TAILMON_ANONYMIZE_SALT_FILE.
+fn get_anonymize_salt() -> Result<Option<String>, String> {
+    let enabled = env::var("TAILMON_ANONYMIZE").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false);
+    if !enabled {
+        return Ok(None);
+    }
+    let salt = match (env::var("TAILMON_ANONYMIZE_SALT"), env::var("TAILMON_ANONYMIZE_SALT_FILE")) {
+        (Ok(_), Ok(_)) => return Err("TAILMON_ANONYMIZE_SALT and TAILMON_ANONYMIZE_SALT_FILE are mutually exclusive".to_string()),
+        (Ok(salt), Err(_)) => salt,
+        (Err(_), Ok(path)) => std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {}: {}", path, e))?
+            .trim()
+            .to_string(),
+        (Err(_), Err(_)) => String::new(),
+    };
+    if salt.is_empty() {
+        warn!("TAILMON_ANONYMIZE is set without a salt; the anonymous ID can be matched to a guessed hostname");
+    }
+    Ok(Some(salt))
+}
+
+/// Replace the device_id (the hostname) with a stable ID derived from SHA256(salt + hostname)
+fn anonymize(system_info: &mut SystemInfo, salt: &str) {
+    let digest = Sha256::new().chain_update(salt).chain_update(&system_info.device_id).finalize();
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    system_info.device_id = format!("anon-{}", &hex[..ANONYMOUS_ID_HEX_LEN]);
+}
+
+/// Parse UI hints from TAILMON_META (e.g. "icon=🖥️,color=#4facfe").
+/// Malformed or oversized entries are skipped with a warning.
+fn get_ui_meta() -> HashMap<String, String> {
+    let mut ui_meta = HashMap::new();
+    let raw = env::var("TAILMON_META").unwrap_or_default();
     
-    #[cfg(target_os = "macos")]
-    {
-        // macOS specific details could include Darwin version, etc.
-        return "macOS".to_string();
+    for pair in raw.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            warn!("Ignoring malformed TAILMON_META entry: {}", pair);
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || key.len() > MAX_UI_META_LEN || value.len() > MAX_UI_META_LEN {
+            warn!("Ignoring oversized TAILMON_META entry: {}", pair);
+            continue;
+        }
+        if ui_meta.len() >= MAX_UI_META_ENTRIES {
+            warn!("TAILMON_META has more than {} entries, ignoring the rest", MAX_UI_META_ENTRIES);
+            break;
+        }
+        ui_meta.insert(key.to_string(), value.to_string());
     }
     
-    // Default case for other platforms
-    String::new()
+    ui_meta
+}
+
+/// Check whether TAILMON_SEND_RATES asks for per-interval counter rates
+fn get_send_rates() -> bool {
+    env::var("TAILMON_SEND_RATES").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+/// Check whether TAILMON_CGROUP_AWARE asks for container-scoped memory and CPU
+fn get_cgroup_aware() -> bool {
+    env::var("TAILMON_CGROUP_AWARE").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+/// Get the process names to report on from TAILMON_WATCH_PROCS (comma-separated, e.g. "myapp,nginx")
+fn get_watch_procs() -> Vec<String> {
+    let raw = env::var("TAILMON_WATCH_PROCS").unwrap_or_default();
+    let mut names: Vec<String> = Vec::new();
+    for name in raw.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        if name.len() > MAX_PROCESS_NAME_LEN {
+            warn!("Ignoring oversized TAILMON_WATCH_PROCS entry: {}", name);
+            continue;
+        }
+        if names.len() >= MAX_WATCHED_PROCESSES {
+            warn!("TAILMON_WATCH_PROCS has more than {} entries, ignoring the rest", MAX_WATCHED_PROCESSES);
+            break;
+        }
+        if !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Default upper bound on one collection cycle
+const DEFAULT_COLLECT_TIMEOUT_SECS: u64 = 10;
+
+/// Get the collection watchdog timeout from TAILMON_COLLECT_TIMEOUT_SECS
+fn get_collect_timeout() -> Duration {
+    let secs = env::var("TAILMON_COLLECT_TIMEOUT_SECS").ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_COLLECT_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Samples discarded at startup unless TAILMON_WARMUP_SAMPLES says otherwise
+const DEFAULT_WARMUP_SAMPLES: u32 = 1;
+
+/// Pause between warm-up samples, long enough for sysinfo to measure a CPU delta
+const WARMUP_DELAY: Duration = Duration::from_secs(1);
+
+/// Get the number of startup samples to discard from TAILMON_WARMUP_SAMPLES
+fn get_warmup_samples() -> u32 {
+    env::var("TAILMON_WARMUP_SAMPLES").ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_WARMUP_SAMPLES)
+}
+
+/// Check whether TAILMON_STAGGER asks for a fixed, per-host collection offset
+fn get_stagger() -> bool {
+    env::var("TAILMON_STAGGER").map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+/// Offset within the interval at which this host collects: a hash of its name, so every
+/// host keeps the same slot across restarts while the fleet spreads over the interval
+fn stagger_offset(host: &str, interval_secs: u64) -> Duration {
+    let digest = Sha256::digest(host.as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap_or_default());
+    Duration::from_millis(hash % (interval_secs.max(1) * 1000))
+}
+
+/// Time until the wall clock next reaches `offset` past a multiple of the interval
+fn delay_until_offset(offset: Duration, interval_secs: u64) -> Duration {
+    let interval_ms = interval_secs.max(1) as u128 * 1000;
+    let now_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis();
+    let delay_ms = (offset.as_millis() + interval_ms - now_ms % interval_ms) % interval_ms;
+    Duration::from_millis(delay_ms as u64)
+}
+
+/// Collect and discard the first `samples` readings; the first CPU usage after
+/// startup has no prior delta and reads as 0 or a spike
+async fn warm_up(collector: &SharedCollector, timeout: Duration, samples: u32) {
+    if samples == 0 {
+        return;
+    }
+    info!("Warming up: discarding the first {} samples", samples);
+    for _ in 0..samples {
+        collect_with_timeout(collector, timeout).await;
+        tokio::time::sleep(WARMUP_DELAY).await;
+    }
+    info!("Warm-up complete");
+}
+
+/// Run the collector on a blocking thread under a timeout, so a sysinfo call
+/// that blocks (e.g. on a wedged filesystem) cannot freeze the main loop.
+/// Returns `None` when collection timed out or panicked, or when an earlier
+/// timed-out collection still holds the collector.
+async fn collect_with_timeout(collector: &SharedCollector, timeout: Duration) -> Option<SystemInfo> {
+    let collector = collector.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        let mut collector = collector.try_lock().ok()?;
+        Some(collector.collect())
+    });
+    
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(Some(system_info))) => return Some(system_info),
+        Ok(Ok(None)) => error!("Previous system info collection is still running, skipping this cycle"),
+        Ok(Err(e)) => error!("System info collection failed: {}", e),
+        Err(_) => error!("System info collection did not finish within {} seconds, skipping this cycle", timeout.as_secs()),
+    }
+    None
+}
+
+/// Samples collected by this process, mixed into their idempotency keys
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Key identifying one sample across its retries, formatted as a version 4 UUID.
+/// Hashes the sample's identity with the process, a counter and the clock, so it
+/// never repeats even when two samples share a device_id and timestamp.
+fn idempotency_key(system_info: &SystemInfo) -> String {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let digest = Sha256::new()
+        .chain_update(system_info.device_id.as_bytes())
+        .chain_update(system_info.last_seen.as_bytes())
+        .chain_update(std::process::id().to_be_bytes())
+        .chain_update(SAMPLE_COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes())
+        .chain_update(nanos.to_be_bytes())
+        .finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Processing applied to every collected sample before it is sealed
+struct SampleProcessing<'a> {
+    /// Command and timeout of the exec hook providing custom metrics
+    exec_hook: Option<(&'a str, Duration)>,
+    precision: Precision,
+    redact_fields: &'a [RedactField],
+    anonymize_salt: Option<&'a str>,
+    send_fields: Option<&'a [SendField]>,
+}
+
+/// Collect one sample, merge the exec hook's custom metrics, round floats, apply redaction and trim it to the sent fields.
+/// Returns `None` when collection timed out or panicked.
+async fn collect_sample(collector: &SharedCollector, collect_timeout: Duration, processing: &SampleProcessing<'_>) -> Option<SystemInfo> {
+    // A stalled collection skips the cycle instead of hanging the agent
+    let mut system_info = collect_with_timeout(collector, collect_timeout).await?;
+    info!("Collected system info for device: {}", system_info.device_id);
+    
+    // Merge custom metrics from the exec hook; a failing hook never stops reporting
+    if let Some((command, timeout)) = processing.exec_hook {
+        match run_exec_hook(command, timeout).await {
+            Ok(custom) => {
+                system_info.custom = custom;
+                system_info.add_capability(CAPABILITY_CUSTOM);
+            }
+            Err(e) => warn!("Exec hook failed: {}", e),
+        }
+    }
+    
+    // A NaN or infinite reading would be rejected by the server along with the whole sample
+    for (field, value) in system_info.clamp_non_finite() {
+        warn!("Collected a non-finite {} ({}), sending it clamped or left out", field, value);
+    }
+    precision::normalize(&mut system_info, processing.precision);
+    
+    // Privacy post-processing happens last so nothing collected above can leak
+    redact(&mut system_info, processing.redact_fields);
+    if let Some(salt) = processing.anonymize_salt {
+        anonymize(&mut system_info, salt);
+    }
+    if let Some(sent) = processing.send_fields {
+        fields::trim(&mut system_info, sent);
+    }
+    
+    // The key travels with the sample through the offline buffer, so a retry of a sample
+    // the server already stored (its response was lost) is recognized
+    system_info.idempotency_key = Some(idempotency_key(&system_info));
+    
+    // Checksum the final contents so the server can detect corruption
+    system_info.seal();
+    Some(system_info)
 }
 
 #[tokio::main]
@@ -95,64 +822,425 @@ async fn main() {
         .with_thread_names(true)
         .init();
     
+    // Print or install the service definition instead of reporting; before any log line, so the output can be redirected
+    match service::parse_args(env::args().skip(1)) {
+        Some(Ok(options)) => std::process::exit(service::run(&options)),
+        Some(Err(e)) => {
+            error!("Usage: agent install-service [--install] [--name <name>]: {}", e);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+
+    // Report to the service control manager when started as a Windows service
+    #[cfg(windows)]
+    if env::args().skip(1).any(|arg| arg == service::WINDOWS_SERVICE_ARG) {
+        if let Err(e) = service::start_windows_service() {
+            error!("Cannot run as a Windows service: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     info!("Agent starting...");
-    let server_url = get_server_url();
-    info!("Will send data to server at: {}", server_url);
+    let once = env::args().skip(1).any(|arg| arg == "--once");
+    let bench_cycles = match bench::parse_args(env::args().skip(1)) {
+        Some(Ok(cycles)) => Some(cycles),
+        Some(Err(e)) => {
+            error!("Usage: agent bench [cycles]: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    #[cfg(feature = "loadgen")]
+    let load_options = match loadgen::parse_args(env::args().skip(1)) {
+        Some(Ok(options)) => Some(options),
+        Some(Err(e)) => {
+            error!("Usage: agent loadgen [devices] [rate] [duration-secs]: {}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let send_rates = get_send_rates();
+    let cgroup_aware = get_cgroup_aware();
+    let watch_procs = get_watch_procs();
+    if !watch_procs.is_empty() {
+        info!("Watching processes: {}", watch_procs.join(", "));
+    }
+    let collector_kind = collectors::get_collector_kind();
+    if collector_kind == collectors::CollectorKind::Proc {
+        info!("Reading /proc directly instead of using sysinfo");
+    }
+    let collector: SharedCollector = Arc::new(Mutex::new(collectors::build(collector_kind, get_ui_meta(), send_rates, cgroup_aware, watch_procs.clone())));
+    let exec_hook = get_exec_hook();
+    let exec_hook_timeout = get_exec_hook_timeout();
+    let collect_timeout = get_collect_timeout();
+    let warmup_samples = get_warmup_samples();
+    let interval = get_interval();
+    let stagger = get_stagger();
+    let mut server_interval: Option<u64> = None;
+    let adaptive_cpu = get_adaptive_cpu();
+    if let Some(threshold) = adaptive_cpu {
+        info!("Adaptive mode: the interval doubles while CPU usage is above {}%", threshold);
+    }
+    let redact_fields = get_redact_fields();
+    if !redact_fields.is_empty() {
+        info!("Redacting fields before sending: {:?}", redact_fields);
+    }
+    let anonymize_salt = match get_anonymize_salt() {
+        Ok(salt) => salt,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if anonymize_salt.is_some() {
+        info!("Reporting an anonymized device ID instead of the hostname");
+    }
+    let send_fields = fields::get_send_fields();
+    if let Some(sent) = &send_fields {
+        info!("Sending only these optional fields: {:?}", sent);
+    }
+    if let Some(command) = &exec_hook {
+        info!("Custom metrics will be collected from exec hook: {}", command);
+    }
+    let precision = precision::get_precision();
+    info!("Rounding CPU usage to {} decimals and other float fields to {}", precision.cpu, precision.other);
+    let processing = SampleProcessing {
+        exec_hook: exec_hook.as_deref().map(|command| (command, exec_hook_timeout)),
+        precision,
+        redact_fields: &redact_fields,
+        anonymize_salt: anonymize_salt.as_deref(),
+        send_fields: send_fields.as_deref(),
+    };
+    
+    // Measure the agent's own cost on this host instead of reporting
+    if let Some(cycles) = bench_cycles {
+        std::process::exit(bench::run(&collector, cycles, collect_timeout, &processing).await);
+    }
+    
+    let server_urls = get_server_urls();
+    let delivery_mode = get_delivery_mode();
+    info!("Will send data to server at: {}", server_urls.join(", "));
+    if server_urls.len() > 1 {
+        info!("A cycle succeeds when {} of the servers accept it", delivery_mode.name());
+    }
+    let api_key = get_api_key();
+    let signing_key = get_signing_key();
+    if let Some(key_id) = signing_key.as_ref().map(|key| key.key_id.as_deref().unwrap_or("no key id")) {
+        info!("Signing requests with TAILMON_HMAC_SECRET ({})", key_id);
+    }
+    
+    // Create HTTP client with timeout (and client certificate when configured)
+    let client = match build_http_client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    // Simulate a fleet against the first server instead of reporting
+    #[cfg(feature = "loadgen")]
+    if let Some(options) = load_options {
+        std::process::exit(loadgen::run(&client, &server_urls[0], api_key.as_deref(), signing_key.as_ref(), options).await);
+    }
+    
+    // Unsent samples survive failures (and restarts when TAILMON_SPOOL_PATH is set), per server
+    let spool_path = get_spool_path();
+    let spool_max = get_spool_max();
+    let mut endpoints: Vec<Endpoint> = server_urls.iter().enumerate()
+        .map(|(index, url)| {
+            let path = spool_path.as_deref().map(|path| endpoint::spool_path(path, index, server_urls.len()));
+            Endpoint::new(url.clone(), Spool::open(path, spool_max))
+        })
+        .collect();
+    let mut batching = batch::get_batching(spool_max);
+    if batching.size > 1 {
+        info!("Batching {} cycles per request (sent after {} seconds at the latest)", batching.size, batching.max_age.as_secs());
+    }
+    
+    // Containers may start before the network or DNS is up; wait instead of failing the first cycles
+    if let Some(wait_for) = readiness::get_wait_for() {
+        readiness::wait_for_servers(&client, &server_urls, delivery_mode, wait_for, readiness::get_wait_timeout()).await;
+    }
     
-    // Create HTTP client with timeout
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .expect("Failed to create HTTP client");
+    // Nothing is sent until the collector has a baseline
+    warm_up(&collector, collect_timeout, warmup_samples).await;
     
+    // One-shot mode for scripts: send a single sample and report the outcome as the exit code
+    if once {
+        let Some(system_info) = collect_sample(&collector, collect_timeout, &processing).await else {
+            std::process::exit(OnceError::Collection.exit_code());
+        };
+        for endpoint in &mut endpoints {
+            endpoint.spool.push(system_info.clone());
+        }
+        let outcome = deliver_all(&client, api_key.as_deref(), signing_key.as_ref(), &mut endpoints).await;
+        match outcome.error {
+            Some(e) if !delivery_mode.satisfied(outcome.succeeded, endpoints.len()) => {
+                std::process::exit(OnceError::from(e).exit_code());
+            }
+            _ => std::process::exit(0),
+        }
+    }
+    
+    // Cycles in a row that did not satisfy the delivery mode
     let mut consecutive_failures = 0;
-    const MAX_CONSECUTIVE_FAILURES: u32 = 5;
     
-    // Infinite loop to continuously send data
+    // Last-resort local alarm when the servers stay unreachable
+    let mut failure_hook = failure_hook::get_failure_hook();
+    if let Some(hook) = &failure_hook {
+        info!("Will run TAILMON_ON_FAILURE_CMD after {} failed cycles in a row: {}", hook.after, hook.command);
+    }
+    
+    // Whether the adaptive mode is currently backing off
+    let mut busy = false;
+    
+    // Optional local debug endpoint exposing the loop's state (off unless TAILMON_AGENT_DEBUG_ADDR is set)
+    let debug_state: Option<SharedDebugState> = get_debug_addr().map(|addr| {
+        let state = Arc::new(Mutex::new(DebugState {
+            last_sample: None,
+            consecutive_failures: 0,
+            queue_depth: endpoints.iter().map(|endpoint| endpoint.spool.len()).sum(),
+            endpoints: endpoints.iter().map(EndpointState::from).collect(),
+            server_interval_secs: None,
+            config: EffectiveConfig {
+                server_urls: server_urls.clone(),
+                delivery_mode: delivery_mode.name(),
+                api_key_set: api_key.is_some(),
+                hmac_key_id: signing_key.as_ref().map(|key| key.key_id.clone().unwrap_or_default()),
+                interval_secs: interval,
+                adaptive_cpu,
+                collect_timeout_secs: collect_timeout.as_secs(),
+                warmup_samples,
+                stagger,
+                exec_hook: exec_hook.clone(),
+                exec_hook_timeout_secs: exec_hook_timeout.as_secs(),
+                on_failure_cmd: failure_hook.as_ref().map(|hook| hook.command.clone()),
+                on_failure_after: failure_hook.as_ref().map(|hook| hook.after),
+                send_rates,
+                cgroup_aware,
+                collector: collector_kind.name(),
+                watch_procs,
+                cpu_precision: precision.cpu,
+                float_precision: precision.other,
+                redact: redact_fields.iter().map(RedactField::name).collect(),
+                anonymize: anonymize_salt.is_some(),
+                send_fields: send_fields.as_ref().map(|sent| sent.iter().map(SendField::name).collect()),
+                spool_path: spool_path.as_ref().map(|path| path.display().to_string()),
+                spool_max,
+                batch_size: batching.size,
+                batch_max_age_secs: batching.max_age.as_secs(),
+            },
+        }));
+        tokio::spawn(debug::serve(addr, state.clone()));
+        state
+    });
+    
+    // Start at this host's slot within the interval; later cycles keep the phase
+    if stagger {
+        let offset = stagger_offset(&common::collector::host_name(), interval);
+        let delay = delay_until_offset(offset, interval);
+        info!("Staggering: collecting {:.1} seconds past every {}-second boundary, first collection in {:.1} seconds",
+            offset.as_secs_f64(), interval, delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+    
+    // Infinite loop to continuously send data. A cycle that only delivers a batch which
+    // reached its max age while waiting collects nothing.
+    let mut next_cycle = tokio::time::Instant::now();
+    let mut flush_only = false;
     loop {
-        // Collect system information
-        let system_info = match get_system_info().await {
-            info => {
-                info!("Collected system info for device: {}", info.device_id);
-                info
+        let mut cpu_usage = None;
+        let mut last_sample = None;
+        if !flush_only {
+            // Collect system information
+            let Some(system_info) = collect_sample(&collector, collect_timeout, &processing).await else {
+                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                continue;
+            };
+            cpu_usage = Some(system_info.cpu_usage);
+            
+            // Send data to every server (together with anything left over from earlier failures),
+            // unless it is held back for a batch
+            last_sample = debug_state.is_some().then(|| system_info.clone());
+            for endpoint in &mut endpoints {
+                endpoint.spool.push(system_info.clone());
             }
-        };
-        
-        // Send data to server
-        match client.post(&server_url)
-            .json(&system_info)
-            .send()
-            .await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        info!("✅ Successfully sent data to server");
-                        consecutive_failures = 0; // Reset failure counter on success
-                    } else {
-                        warn!("❌ Server returned error status: {}", response.status());
-                        consecutive_failures += 1;
-                    }
+            batching.hold();
+        } else {
+            info!("Oldest of {} held samples reached {} seconds, sending the batch early", batching.held(), batching.max_age.as_secs());
+        }
+        let outcome = if batching.is_due() {
+            let outcome = deliver_all(&client, api_key.as_deref(), signing_key.as_ref(), &mut endpoints).await;
+            if delivery_mode.satisfied(outcome.succeeded, endpoints.len()) {
+                consecutive_failures = 0; // Reset failure counter on success
+                batching.delivered();
+                if let Some(hook) = &mut failure_hook {
+                    hook.recovered();
                 }
-                Err(e) => {
-                    consecutive_failures += 1;
-                    error!("❌ Failed to send data to server: {}", e);
-                    
-                    // If we have too many consecutive failures, wait longer
-                    if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
-                        warn!("⚠️  Too many consecutive failures ({}), waiting 30 seconds before retry...", consecutive_failures);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
-                        consecutive_failures = 0; // Reset counter after long wait
-                    }
+            } else {
+                consecutive_failures += 1;
+                if let Some(hook) = &mut failure_hook {
+                    hook.failed(FailureContext {
+                        failures: consecutive_failures,
+                        error: outcome.error.as_ref().map_or_else(|| "servers paused after repeated failures".to_string(), ToString::to_string),
+                        queued: endpoints.iter().map(|endpoint| endpoint.spool.len()).sum(),
+                        servers: server_urls.clone(),
+                    });
                 }
             }
+            outcome
+        } else {
+            info!("Holding {} of {} samples for the next batch", batching.held(), batching.size);
+            RoundOutcome::default()
+        };
+        if outcome.succeeded > 0 && outcome.interval_secs != server_interval {
+            match outcome.interval_secs {
+                Some(secs) => info!("Server requested a reporting interval of {} seconds", secs),
+                None => info!("Server interval override removed, using {} seconds", interval),
+            }
+            server_interval = outcome.interval_secs;
+        }
+        let mut collect_now = false;
+        for command in &outcome.commands {
+            match command {
+                AgentCommand::CollectNow => collect_now = true,
+                AgentCommand::Unknown => warn!("Ignoring a command this agent does not support"),
+            }
+        }
+        
+        if let Some(debug_state) = &debug_state {
+            let mut debug_state = debug_state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if last_sample.is_some() {
+                debug_state.last_sample = last_sample;
+            }
+            debug_state.consecutive_failures = consecutive_failures;
+            debug_state.queue_depth = endpoints.iter().map(|endpoint| endpoint.spool.len()).sum();
+            debug_state.endpoints = endpoints.iter().map(EndpointState::from).collect();
+            debug_state.server_interval_secs = server_interval;
+        }
+        
+        // Report less often while the host is busy, so the agent adds less load
+        if let Some(cpu_usage) = cpu_usage.filter(|cpu_usage| is_busy(*cpu_usage, adaptive_cpu) != busy) {
+            busy = !busy;
+            if busy {
+                info!("CPU usage at {:.1}%, doubling the reporting interval", cpu_usage);
+            } else {
+                info!("CPU usage at {:.1}%, back to the normal reporting interval", cpu_usage);
+            }
+        }
         
-        // Wait before next iteration (shorter wait if we had failures)
-        let wait_time = if consecutive_failures > 0 {
-            std::cmp::min(5 + consecutive_failures * 2, 15) // Progressive backoff, max 15 seconds
+        // Wait before next iteration (none if the server asked for a collection, shorter wait if we had
+        // failures, exactly as long as the server asked if it did)
+        let wait_time = if collect_now {
+            info!("Server asked for an immediate collection");
+            0
+        } else if consecutive_failures > 0 {
+            outcome.retry_after_secs
+                .unwrap_or_else(|| std::cmp::min(5 + consecutive_failures as u64 * 2, 15)) // Progressive backoff, max 15 seconds
+        } else if busy {
+            server_interval.unwrap_or(interval) * 2
         } else {
-            5
+            server_interval.unwrap_or(interval)
         };
         
-        info!("Waiting {} seconds before next update...", wait_time);
-        tokio::time::sleep(tokio::time::Duration::from_secs(wait_time as u64)).await;
+        // A batch delivered in between keeps the collection schedule, unless it brought a reason to go sooner
+        let wait_until = tokio::time::Instant::now() + tokio::time::Duration::from_secs(wait_time);
+        next_cycle = if flush_only { next_cycle.min(wait_until) } else { wait_until };
+        
+        // Wait for the next cycle, or until the held batch reaches its max age. After a failed
+        // delivery the backoff above retries it instead.
+        let flush_at = batching.deadline()
+            .filter(|_| consecutive_failures == 0)
+            .map(tokio::time::Instant::from_std)
+            .filter(|flush_at| *flush_at < next_cycle);
+        info!("Waiting {} seconds before next update...", next_cycle.saturating_duration_since(tokio::time::Instant::now()).as_secs_f64().round());
+        flush_only = tokio::select! {
+            _ = tokio::time::sleep_until(next_cycle) => false,
+            _ = tokio::time::sleep_until(flush_at.unwrap_or(next_cycle)), if flush_at.is_some() => true,
+        };
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collector returning one fixed reading
+    struct FixedCollector(SystemInfo);
+
+    impl Collector for FixedCollector {
+        fn collect(&mut self) -> SystemInfo {
+            self.0.clone()
+        }
+    }
+
+    fn reading(cpu_usage: f32, net_rate: f64, battery_percent: f32) -> SystemInfo {
+        let mut info = common::test_util::sample();
+        info.capabilities = vec![common::CAPABILITY_NET_RATES.to_string(), common::CAPABILITY_BATTERY.to_string()];
+        info.cpu_usage = cpu_usage;
+        info.net_rx_rate_bytes_per_sec = Some(net_rate);
+        info.net_tx_rate_bytes_per_sec = Some(net_rate);
+        info.battery = Some(common::BatteryInfo { percent: battery_percent, charging: false });
+        info
+    }
+
+    async fn collect(reading: SystemInfo) -> SystemInfo {
+        let collector: SharedCollector = Arc::new(Mutex::new(Box::new(FixedCollector(reading))));
+        let processing = SampleProcessing {
+            exec_hook: None,
+            precision: Precision { cpu: 1, other: 2 },
+            redact_fields: &[],
+            anonymize_salt: None,
+            send_fields: None,
+        };
+        collect_sample(&collector, Duration::from_secs(5), &processing).await.unwrap()
+    }
+
+    #[test]
+    fn redaction_clears_names_and_identifiers() {
+        let mut info = common::test_util::sample();
+        info.ui_meta.insert("owner".to_string(), "alice".to_string());
+        info.custom.insert("db_host".to_string(), serde_json::json!("10.0.0.7"));
+        info.watched_processes.insert("postgres".to_string(), common::ProcessUsage { cpu_usage: 2.0, memory_mb: 512, count: 3 });
+        info.hardware = Some(common::HardwareInfo { uuid: Some("4c4c4544-0042".to_string()), arch: "x86_64".to_string(), ..Default::default() });
+        info.capabilities = vec![CAPABILITY_CUSTOM.to_string(), CAPABILITY_PROCESSES.to_string()];
+
+        let fields: Vec<RedactField> = ["os_info", "ui_meta", "custom", "watched_processes", "hardware"].into_iter()
+            .map(|name| RedactField::parse(name).unwrap())
+            .collect();
+        redact(&mut info, &fields);
+        assert_eq!(info.os_info, REDACTED);
+        assert!(info.ui_meta.is_empty() && info.custom.is_empty() && info.watched_processes.is_empty());
+        assert!(info.capabilities.is_empty(), "{:?}", info.capabilities);
+        let hardware = info.hardware.unwrap();
+        assert_eq!((hardware.uuid, hardware.arch.as_str()), (None, "x86_64"));
+    }
+
+    #[tokio::test]
+    async fn non_finite_cpu_usage_is_clamped() {
+        for (cpu_usage, expected) in [(f32::NAN, 0.0), (f32::INFINITY, 100.0), (f32::NEG_INFINITY, 0.0)] {
+            let sample = collect(reading(cpu_usage, 10.0, 50.0)).await;
+            assert_eq!(sample.cpu_usage, expected, "cpu_usage {}", cpu_usage);
+            assert_eq!(sample.net_rx_rate_bytes_per_sec, Some(10.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn non_finite_rates_and_battery_are_left_out() {
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let sample = collect(reading(12.5, value, value as f32)).await;
+            assert_eq!(sample.cpu_usage, 12.5);
+            assert_eq!(sample.net_rx_rate_bytes_per_sec, None);
+            assert_eq!(sample.net_tx_rate_bytes_per_sec, None);
+            assert!(sample.battery.is_none());
+            assert!(sample.capabilities.is_empty(), "{:?}", sample.capabilities);
+
+            // What is left is sendable as JSON and passes the server's checks
+            assert!(sample.non_finite_fields().is_empty());
+            assert!(serde_json::to_string(&sample).is_ok());
+            assert_eq!(sample.verify_checksum(), Some(true));
+        }
+    }
+}
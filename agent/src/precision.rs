@@ -0,0 +1,81 @@
+use common::SystemInfo;
+use serde_json::Value;
+use std::env;
+use tracing::warn;
+
+/// Decimals of `cpu_usage` unless TAILMON_CPU_PRECISION says otherwise
+const DEFAULT_CPU_PRECISION: u32 = 1;
+
+/// Decimals of the other float fields unless TAILMON_FLOAT_PRECISION says otherwise
+const DEFAULT_FLOAT_PRECISION: u32 = 2;
+
+/// More decimals than an f32 carries would only add noise back
+const MAX_PRECISION: u32 = 6;
+
+/// Decimals float fields are rounded to before a sample is sealed
+#[derive(Debug, Clone, Copy)]
+pub struct Precision {
+    /// Decimals of `cpu_usage` and the CPU usage of watched processes
+    pub cpu: u32,
+
+    /// Decimals of network rates, the battery charge and float values in `custom`
+    pub other: u32,
+}
+
+/// Read a number of decimals from `name`, warning about and ignoring invalid values
+fn get_decimals(name: &str, default: u32) -> u32 {
+    let Ok(raw) = env::var(name) else {
+        return default;
+    };
+    match raw.trim().parse() {
+        Ok(decimals) if decimals <= MAX_PRECISION => decimals,
+        _ => {
+            warn!("Ignoring invalid value for {} (expected 0 to {}): {}", name, MAX_PRECISION, raw);
+            default
+        }
+    }
+}
+
+/// Get the rounding of float fields from TAILMON_CPU_PRECISION and TAILMON_FLOAT_PRECISION
+pub fn get_precision() -> Precision {
+    Precision {
+        cpu: get_decimals("TAILMON_CPU_PRECISION", DEFAULT_CPU_PRECISION),
+        other: get_decimals("TAILMON_FLOAT_PRECISION", DEFAULT_FLOAT_PRECISION),
+    }
+}
+
+fn round(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    (value * scale).round() / scale
+}
+
+/// Round the floats of a JSON value (e.g. exec hook output), leaving integers as they are
+fn round_json(value: &mut Value, decimals: u32) {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            if let Some(rounded) = number.as_f64().and_then(|float| serde_json::Number::from_f64(round(float, decimals))) {
+                *number = rounded;
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| round_json(item, decimals)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| round_json(field, decimals)),
+        _ => {}
+    }
+}
+
+/// Round every float field of a sample, so it carries no false precision (`12.3` instead of `12.34567`)
+pub fn normalize(system_info: &mut SystemInfo, precision: Precision) {
+    system_info.cpu_usage = round(system_info.cpu_usage as f64, precision.cpu) as f32;
+    for usage in system_info.watched_processes.values_mut() {
+        usage.cpu_usage = round(usage.cpu_usage as f64, precision.cpu) as f32;
+    }
+    for rate in [&mut system_info.net_rx_rate_bytes_per_sec, &mut system_info.net_tx_rate_bytes_per_sec].into_iter().flatten() {
+        *rate = round(*rate, precision.other);
+    }
+    if let Some(battery) = &mut system_info.battery {
+        battery.percent = round(battery.percent as f64, precision.other) as f32;
+    }
+    for value in system_info.custom.values_mut() {
+        round_json(value, precision.other);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::endpoint::DeliveryMode;
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Default upper bound on the startup wait
+const DEFAULT_WAIT_SECS: u64 = 60;
+
+/// Delay before the first retry; doubled after every failed round
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between two rounds
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// What counts as a server being ready at startup (TAILMON_WAIT_FOR_SERVER)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitFor {
+    /// The server's host name resolves
+    Dns,
+
+    /// The host name resolves and the server answers HTTP requests (any status)
+    Http,
+}
+
+impl WaitFor {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dns" => Some(WaitFor::Dns),
+            "http" => Some(WaitFor::Http),
+            _ => None,
+        }
+    }
+}
+
+/// Get the startup readiness check from TAILMON_WAIT_FOR_SERVER ("dns" or "http"); `None` when disabled
+pub fn get_wait_for() -> Option<WaitFor> {
+    let raw = env::var("TAILMON_WAIT_FOR_SERVER").unwrap_or_default();
+    if raw.trim().is_empty() {
+        return None;
+    }
+    let wait_for = WaitFor::parse(raw.trim());
+    if wait_for.is_none() {
+        warn!("Ignoring invalid value for TAILMON_WAIT_FOR_SERVER: {}", raw);
+    }
+    wait_for
+}
+
+/// Get the upper bound on the startup wait from TAILMON_WAIT_FOR_SERVER_SECS
+pub fn get_wait_timeout() -> Duration {
+    let secs = env::var("TAILMON_WAIT_FOR_SERVER_SECS").ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_WAIT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Check one server; the error describes what is not ready yet
+async fn check(client: &reqwest::Client, url: &str, wait_for: WaitFor) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    let host = parsed.host_str().ok_or("URL has no host")?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let mut addrs = tokio::net::lookup_host((host, port)).await.map_err(|e| format!("cannot resolve {}: {}", host, e))?;
+    if addrs.next().is_none() {
+        return Err(format!("{} resolves to no address", host));
+    }
+    if wait_for == WaitFor::Http {
+        // Any response, even an error status, shows the server is up
+        client.get(url).send().await.map_err(|e| format!("no response: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Wait until enough servers (per the delivery mode) pass the readiness check, retrying with
+/// backoff. Gives up after `timeout` and lets the main loop buffer samples as usual.
+pub async fn wait_for_servers(client: &reqwest::Client, urls: &[String], mode: DeliveryMode, wait_for: WaitFor, timeout: Duration) {
+    info!("Waiting up to {} seconds for the server to be ready ({:?} check)", timeout.as_secs(), wait_for);
+    let started = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut ready = vec![false; urls.len()];
+    loop {
+        for (url, ready) in urls.iter().zip(ready.iter_mut()).filter(|(_, ready)| !**ready) {
+            match check(client, url, wait_for).await {
+                Ok(()) => {
+                    info!("{} is ready", url);
+                    *ready = true;
+                }
+                Err(e) => info!("{} is not ready yet: {}", url, e),
+            }
+        }
+        if mode.satisfied(ready.iter().filter(|ready| **ready).count(), urls.len()) {
+            info!("Server ready after {:.1} seconds", started.elapsed().as_secs_f64());
+            return;
+        }
+        if started.elapsed() + backoff > timeout {
+            warn!("Server still not ready after {} seconds, starting anyway", started.elapsed().as_secs());
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,412 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Service name used unless `--name` gives another
+const DEFAULT_NAME: &str = "tailmon-agent";
+
+/// Argument the Windows service starts the agent with, to report to the service control manager
+pub const WINDOWS_SERVICE_ARG: &str = "--windows-service";
+
+/// Options of `agent install-service [--install] [--name <name>]`
+pub struct ServiceOptions {
+    /// Write and enable the service instead of only printing it
+    pub install: bool,
+    pub name: String,
+}
+
+/// Parse `install-service [--install] [--name <name>]` from the command line arguments;
+/// `None` when not generating a service
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Result<ServiceOptions, String>> {
+    if args.next().as_deref() != Some("install-service") {
+        return None;
+    }
+    let mut options = ServiceOptions { install: false, name: DEFAULT_NAME.to_string() };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--install" => options.install = true,
+            "--name" => match args.next() {
+                Some(name) if is_valid_name(&name) => options.name = name,
+                Some(name) => return Some(Err(format!("invalid service name (letters, digits, '.', '_' and '-'): {}", name))),
+                None => return Some(Err("--name needs a value".to_string())),
+            },
+            other => return Some(Err(format!("unknown argument: {}", other))),
+        }
+    }
+    Some(Ok(options))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// A file of the service definition
+struct ServiceFile {
+    path: PathBuf,
+    contents: String,
+
+    /// Holds the environment, which may include the API key, so only its owner may read it
+    private: bool,
+}
+
+/// Files and activation commands of the service for this platform
+struct ServiceDefinition {
+    files: Vec<ServiceFile>,
+    commands: Vec<Vec<String>>,
+}
+
+/// Generate the service definition for the running binary with the current TAILMON_* environment,
+/// then print it or, with `--install`, write and enable it. The definition is printed instead when
+/// installing fails, e.g. without root. Returns the process exit code.
+pub fn run(options: &ServiceOptions) -> i32 {
+    let exe = match env::current_exe().and_then(|exe| exe.canonicalize()) {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("Cannot locate the agent binary: {}", e);
+            return 1;
+        }
+    };
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(key, _)| key.starts_with("TAILMON_") || key == "RUST_LOG")
+        .collect();
+    vars.sort();
+    if !vars.iter().any(|(key, _)| key == "TAILMON_SERVER_URL") {
+        eprintln!("Warning: TAILMON_SERVER_URL is not set, the service will report to the default server");
+    }
+
+    let definition = match env::consts::OS {
+        "linux" => systemd(&options.name, &exe, &vars),
+        "macos" => Ok(launchd(&options.name, &exe, &vars)),
+        "windows" => Ok(windows_service(&options.name, &exe, &vars)),
+        other => {
+            eprintln!("Services are not supported on {}; run the agent with your platform's service manager", other);
+            return 1;
+        }
+    };
+    let definition = match definition {
+        Ok(definition) => definition,
+        Err(e) => {
+            eprintln!("Cannot generate the service: {}", e);
+            return 1;
+        }
+    };
+    if !options.install {
+        print_definition(&definition);
+        return 0;
+    }
+
+    for file in &definition.files {
+        if let Err(e) = write_file(file) {
+            eprintln!("Cannot write {}: {}", file.path.display(), e);
+            eprintln!("Install the service by hand, or run this command again as an administrator:");
+            print_definition(&definition);
+            return 1;
+        }
+        println!("Wrote {}", file.path.display());
+    }
+    for command in &definition.commands {
+        match Command::new(&command[0]).args(&command[1..]).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("`{}` exited with {}", command.join(" "), status);
+                return 1;
+            }
+            Err(e) => {
+                eprintln!("`{}` failed to run: {}", command.join(" "), e);
+                return 1;
+            }
+        }
+    }
+    println!("Service {} installed and started", options.name);
+    0
+}
+
+/// Print every file under a header with its path, followed by the commands that activate it
+fn print_definition(definition: &ServiceDefinition) {
+    for file in &definition.files {
+        println!("# {}", file.path.display());
+        println!("{}", file.contents);
+    }
+    println!("# Activate with:");
+    for command in &definition.commands {
+        println!("{}", command.join(" "));
+    }
+}
+
+fn write_file(file: &ServiceFile) -> std::io::Result<()> {
+    if let Some(dir) = file.path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&file.path, &file.contents)?;
+    #[cfg(unix)]
+    if file.private {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&file.path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// systemd unit with the environment in a separate root-only EnvironmentFile
+fn systemd(name: &str, exe: &Path, vars: &[(String, String)]) -> Result<ServiceDefinition, String> {
+    let env_path = PathBuf::from(format!("/etc/default/{}", name));
+    let environment = environment_file(vars)?;
+    let exe = exe.display().to_string();
+    let exec_start = if exe.contains(char::is_whitespace) { format!("\"{}\"", exe) } else { exe };
+    let unit = format!(r#"[Unit]
+Description=Tailmon Monitoring Agent
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+EnvironmentFile=-{}
+ExecStart={}
+Restart=always
+RestartSec=10
+
+[Install]
+WantedBy=multi-user.target
+"#, env_path.display(), exec_start);
+    Ok(ServiceDefinition {
+        files: vec![
+            ServiceFile { path: env_path, contents: environment, private: true },
+            ServiceFile { path: PathBuf::from(format!("/etc/systemd/system/{}.service", name)), contents: unit, private: false },
+        ],
+        commands: vec![
+            vec!["systemctl".to_string(), "daemon-reload".to_string()],
+            vec!["systemctl".to_string(), "enable".to_string(), "--now".to_string(), name.to_string()],
+        ],
+    })
+}
+
+/// EnvironmentFile lines with double-quoted values, escaping the characters systemd unescapes
+/// there so nothing is expanded. A line break would end the assignment, so such values are refused.
+fn environment_file(vars: &[(String, String)]) -> Result<String, String> {
+    let mut environment = String::new();
+    for (key, value) in vars {
+        if value.contains(['\n', '\r']) {
+            return Err(if key == "TAILMON_HEADERS" {
+                "TAILMON_HEADERS contains a line break; separate the headers with ';' instead".to_string()
+            } else {
+                format!("{} contains a line break, which an EnvironmentFile cannot hold", key)
+            });
+        }
+        let mut quoted = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '\\' | '"' | '$' | '`') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        environment.push_str(&format!("{}=\"{}\"\n", key, quoted));
+    }
+    Ok(environment)
+}
+
+/// launchd daemon started at boot and restarted when it exits
+fn launchd(name: &str, exe: &Path, vars: &[(String, String)]) -> ServiceDefinition {
+    let path = PathBuf::from(format!("/Library/LaunchDaemons/{}.plist", name));
+    let environment: String = vars.iter()
+        .map(|(key, value)| format!("        <key>{}</key>\n        <string>{}</string>\n", xml_escape(key), xml_escape(value)))
+        .collect();
+    let log = xml_escape(&format!("/var/log/{}.log", name));
+    let plist = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+{}    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#, xml_escape(name), xml_escape(&exe.display().to_string()), environment);
+    ServiceDefinition {
+        commands: vec![vec!["launchctl".to_string(), "bootstrap".to_string(), "system".to_string(), path.display().to_string()]],
+        files: vec![ServiceFile { path, contents: plist, private: true }],
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Service registered with the service control manager, started at boot as LocalSystem and
+/// restarted 10 seconds after it exits. The variables go into the service's `Environment` registry
+/// value through a `.reg` file next to the binary, written as hex so no value needs escaping.
+fn windows_service(name: &str, exe: &Path, vars: &[(String, String)]) -> ServiceDefinition {
+    let reg_path = exe.with_file_name(format!("{}.reg", name));
+
+    // REG_MULTI_SZ: NUL-terminated UTF-16LE strings followed by an empty one
+    let environment: Vec<String> = vars.iter()
+        .flat_map(|(key, value)| format!("{}={}", key, value).encode_utf16().chain([0]).collect::<Vec<u16>>())
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let registry = format!(
+        "Windows Registry Editor Version 5.00\r\n\r\n[HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\{}]\r\n\"Environment\"=hex(7):{}\r\n",
+        name, environment.join(","),
+    );
+
+    // Drop the \\?\ prefix canonicalize adds to local paths
+    let exe = exe.display().to_string();
+    let exe = exe.strip_prefix(r"\\?\").filter(|path| path.get(1..2) == Some(":")).unwrap_or(&exe);
+    let bin_path = format!("\"{}\" {}", exe, WINDOWS_SERVICE_ARG);
+    let reg_file = reg_path.display().to_string();
+    let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+    ServiceDefinition {
+        files: vec![ServiceFile { path: reg_path, contents: registry, private: true }],
+        commands: vec![
+            command(&["sc.exe", "create", name, "binPath=", &bin_path, "start=", "auto", "DisplayName=", "Tailmon Monitoring Agent"]),
+            command(&["sc.exe", "failure", name, "reset=", "86400", "actions=", "restart/10000"]),
+            command(&["reg.exe", "import", &reg_file]),
+            command(&["sc.exe", "start", name]),
+        ],
+    }
+}
+
+/// Start the service control dispatcher when the agent was started as a Windows service
+/// (with [`WINDOWS_SERVICE_ARG`]) and report it running; stopping the service exits the process
+#[cfg(windows)]
+pub fn start_windows_service() -> Result<(), String> {
+    scm::start()
+}
+
+#[cfg(windows)]
+mod scm {
+    use std::ffi::c_void;
+    use std::ptr;
+    use std::sync::mpsc::{self, SyncSender};
+    use std::sync::OnceLock;
+    use windows_sys::core::PWSTR;
+    use windows_sys::Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR};
+    use windows_sys::Win32::System::Services::{
+        RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SERVICE_ACCEPT_SHUTDOWN,
+        SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP,
+        SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE, SERVICE_STOPPED,
+        SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+    };
+
+    /// Outcome of the service start, sent once by the dispatcher thread
+    static STARTED: OnceLock<SyncSender<Result<(), String>>> = OnceLock::new();
+    static STATUS_HANDLE: OnceLock<SERVICE_STATUS_HANDLE> = OnceLock::new();
+
+    pub fn start() -> Result<(), String> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        STARTED.set(sender).map_err(|_| "the service was already started".to_string())?;
+        std::thread::Builder::new()
+            .name("service-dispatcher".to_string())
+            .spawn(|| {
+                // An own-process service may register under any name
+                let mut name = wide("");
+                let table = [
+                    SERVICE_TABLE_ENTRYW { lpServiceName: name.as_mut_ptr(), lpServiceProc: Some(service_main) },
+                    SERVICE_TABLE_ENTRYW { lpServiceName: ptr::null_mut(), lpServiceProc: None },
+                ];
+                // Returns only once the service stopped, or right away when not started by the SCM
+                if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+                    report(Err(format!("cannot connect to the service control manager: {}", std::io::Error::last_os_error())));
+                }
+            })
+            .map_err(|e| format!("cannot start the service dispatcher: {}", e))?;
+        receiver.recv().map_err(|_| "the service dispatcher exited".to_string())?
+    }
+
+    fn report(result: Result<(), String>) {
+        if let Some(sender) = STARTED.get() {
+            let _ = sender.try_send(result);
+        }
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain([0]).collect()
+    }
+
+    fn set_status(handle: SERVICE_STATUS_HANDLE, state: SERVICE_STATUS_CURRENT_STATE) {
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+            dwWin32ExitCode: NO_ERROR,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        };
+        unsafe { SetServiceStatus(handle, &status) };
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = wide("");
+        let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), ptr::null());
+        if handle == 0 {
+            report(Err(format!("cannot register the service control handler: {}", std::io::Error::last_os_error())));
+            return;
+        }
+        let _ = STATUS_HANDLE.set(handle);
+        set_status(handle, SERVICE_RUNNING);
+        report(Ok(()));
+
+        // The agent runs on the main thread; this one only keeps the service alive until it is stopped
+        loop {
+            std::thread::park();
+        }
+    }
+
+    unsafe extern "system" fn control_handler(control: u32, _event_type: u32, _event_data: *mut c_void, _context: *mut c_void) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                if let Some(&handle) = STATUS_HANDLE.get() {
+                    set_status(handle, SERVICE_STOPPED);
+                }
+                std::process::exit(0);
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn environment_file_escapes_quotes_and_expansions() {
+        let environment = environment_file(&vars(&[("TAILMON_API_KEY", r#"a"b\c$HOME`id`"#)])).unwrap();
+        assert_eq!(environment, "TAILMON_API_KEY=\"a\\\"b\\\\c\\$HOME\\`id\\`\"\n");
+    }
+
+    #[test]
+    fn environment_file_refuses_line_breaks() {
+        let error = environment_file(&vars(&[("TAILMON_HEADERS", "X-A: 1\nX-B: 2")])).unwrap_err();
+        assert!(error.contains("';'"), "{}", error);
+        assert!(environment_file(&vars(&[("TAILMON_DEVICE_ID", "web\r1")])).is_err());
+    }
+
+    #[test]
+    fn windows_environment_is_multi_string_hex() {
+        let definition = windows_service("tailmon-agent", Path::new(r"C:\tailmon\agent.exe"), &vars(&[("A", "%x\"\n")]));
+        let registry = &definition.files[0].contents;
+        // "A=%x"<LF>", its terminator and the list terminator, in UTF-16LE
+        assert!(registry.ends_with("\"Environment\"=hex(7):41,00,3d,00,25,00,78,00,22,00,0a,00,00,00,00,00\r\n"), "{}", registry);
+        assert_eq!(definition.commands[0][4], format!("\"C:\\tailmon\\agent.exe\" {}", WINDOWS_SERVICE_ARG));
+    }
+}
@@ -0,0 +1,146 @@
+use common::SystemInfo;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Default maximum number of unsent samples kept in the offline buffer
+pub const DEFAULT_SPOOL_MAX: usize = 1000;
+
+/// Bounded queue of samples not yet delivered to the server.
+/// When a spool path is configured the queue is mirrored to a JSON-lines
+/// file so it survives agent restarts, and the last delivered sample is
+/// kept next to it.
+pub struct Spool {
+    queue: VecDeque<SystemInfo>,
+    max_len: usize,
+    path: Option<PathBuf>,
+}
+
+impl Spool {
+    /// Create a spool, loading any samples left over from a previous run
+    pub fn open(path: Option<PathBuf>, max_len: usize) -> Self {
+        let mut spool = Spool { queue: VecDeque::new(), max_len: max_len.max(1), path };
+
+        if let Some(path) = &spool.path {
+            match load_samples(path) {
+                Ok(samples) if !samples.is_empty() => {
+                    info!("Loaded {} unsent samples from {}", samples.len(), path.display());
+                    spool.queue = samples.into();
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to load spool {}: {}", path.display(), e),
+            }
+            match spool.last_delivered() {
+                Some(last) => info!("Last sample delivered before restart was taken at {}", last.last_seen),
+                None => info!("No previously delivered sample recorded"),
+            }
+            // Enforce the bound in case it shrank since the file was written
+            spool.trim();
+        }
+
+        spool
+    }
+
+    /// Number of samples waiting to be delivered
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Queue a sample, dropping the oldest one when the buffer is full
+    pub fn push(&mut self, sample: SystemInfo) {
+        self.queue.push_back(sample);
+        self.trim();
+        self.persist();
+    }
+
+    /// The oldest queued samples, at most `max` of them
+    pub fn peek(&self, max: usize) -> Vec<SystemInfo> {
+        self.queue.iter().take(max).cloned().collect()
+    }
+
+    /// Remove the `count` oldest samples after they were delivered
+    pub fn acknowledge(&mut self, count: usize) {
+        let delivered: Vec<SystemInfo> = self.queue.drain(..count.min(self.queue.len())).collect();
+        if let (Some(path), Some(last)) = (&self.path, delivered.last()) {
+            if let Err(e) = write_samples(&last_delivered_path(path), std::slice::from_ref(last)) {
+                warn!("Failed to record last delivered sample: {}", e);
+            }
+        }
+        self.persist();
+    }
+
+    /// Remove the `count` oldest samples without delivering them (e.g. rejected by the server)
+    pub fn discard(&mut self, count: usize) {
+        self.queue.drain(..count.min(self.queue.len()));
+        self.persist();
+    }
+
+    /// The last sample delivered by this or a previous run, if recorded
+    pub fn last_delivered(&self) -> Option<SystemInfo> {
+        let path = last_delivered_path(self.path.as_ref()?);
+        load_samples(&path).ok()?.pop()
+    }
+
+    fn trim(&mut self) {
+        while self.queue.len() > self.max_len {
+            self.queue.pop_front();
+            warn!("Offline buffer full ({} samples), dropping oldest sample", self.max_len);
+        }
+    }
+
+    /// Mirror the queue to disk (no-op without a spool path)
+    fn persist(&self) {
+        if let Some(path) = &self.path {
+            let samples: Vec<SystemInfo> = self.queue.iter().cloned().collect();
+            if let Err(e) = write_samples(path, &samples) {
+                warn!("Failed to write spool {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Sibling file holding the last delivered sample
+fn last_delivered_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".last");
+    path.with_file_name(file_name)
+}
+
+/// Read samples from a JSON-lines file, skipping corrupt lines
+fn load_samples(path: &Path) -> std::io::Result<Vec<SystemInfo>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut samples = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(sample) => samples.push(sample),
+            Err(e) => warn!("Skipping corrupt line in {}: {}", path.display(), e),
+        }
+    }
+    Ok(samples)
+}
+
+/// Atomically replace a JSON-lines file with the given samples
+fn write_samples(path: &Path, samples: &[SystemInfo]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    for sample in samples {
+        serde_json::to_writer(&mut file, sample)?;
+        file.write_all(b"\n")?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
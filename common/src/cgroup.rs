@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Mount point of the cgroup v2 unified hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Limits and usage of the cgroup v2 the current process runs in (e.g. a container),
+/// read from the cgroup filesystem on every sample
+pub struct Cgroup {
+    dir: PathBuf,
+
+    /// Time and cumulative `usage_usec` of the previous CPU reading
+    previous_cpu: Option<(Instant, u64)>,
+}
+
+impl Cgroup {
+    /// Find the current process's cgroup; `None` without a cgroup v2 hierarchy (cgroup v1,
+    /// non-Linux) or when the process is in the root cgroup, which has no limits
+    pub fn detect() -> Option<Self> {
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return None;
+        }
+        let membership = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let path = membership.lines().find_map(|line| line.strip_prefix("0::"))?;
+
+        // Inside a cgroup namespace the path is "/" and the container's own cgroup is mounted at the root
+        let dir = root.join(path.trim().trim_start_matches('/'));
+        let dir = if dir.join("memory.max").exists() { dir } else { root.to_path_buf() };
+        dir.join("memory.max").exists().then_some(Cgroup { dir, previous_cpu: None })
+    }
+
+    /// Read a single-value file such as `memory.max`; `None` when missing or set to "max"
+    fn read_value(&self, name: &str) -> Option<u64> {
+        fs::read_to_string(self.dir.join(name)).ok()?.trim().parse().ok()
+    }
+
+    /// Read one key of a flat-keyed file such as `memory.stat`
+    fn read_key(&self, name: &str, key: &str) -> Option<u64> {
+        fs::read_to_string(self.dir.join(name)).ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.trim().parse().ok())
+    }
+
+    /// (used, limit) memory in bytes when a limit is set. Reclaimable page cache is not
+    /// counted as used, matching `docker stats`.
+    pub fn memory(&self) -> Option<(u64, u64)> {
+        let limit = self.read_value("memory.max")?;
+        let current = self.read_value("memory.current")?;
+        let inactive_file = self.read_key("memory.stat", "inactive_file").unwrap_or(0);
+        Some((current.saturating_sub(inactive_file).min(limit), limit))
+    }
+
+    /// (used, limit) swap in bytes when a swap limit is set
+    pub fn swap(&self) -> Option<(u64, u64)> {
+        let limit = self.read_value("memory.swap.max")?;
+        let current = self.read_value("memory.swap.current")?;
+        Some((current.min(limit), limit))
+    }
+
+    /// CPU quota in cores from `cpu.max` ("quota period"); `None` when unlimited
+    pub fn cpu_quota(&self) -> Option<f64> {
+        let cpu_max = fs::read_to_string(self.dir.join("cpu.max")).ok()?;
+        let mut parts = cpu_max.split_whitespace();
+        let quota: f64 = parts.next()?.parse().ok()?;
+        let period: f64 = parts.next()?.parse().ok()?;
+        (period > 0.0).then_some(quota / period)
+    }
+
+    /// CPU usage in percent of the quota since the previous call. `None` without a quota
+    /// or on the first call, since there is nothing to diff against.
+    pub fn cpu_usage(&mut self) -> Option<f32> {
+        let now = Instant::now();
+        let usage_usec = self.read_key("cpu.stat", "usage_usec")?;
+        let previous = self.previous_cpu.replace((now, usage_usec));
+        let quota = self.cpu_quota()?;
+        let (then, previous_usec) = previous?;
+        let elapsed_usec = now.duration_since(then).as_micros() as f64;
+        if elapsed_usec <= 0.0 {
+            return None;
+        }
+        let used_cores = usage_usec.saturating_sub(previous_usec) as f64 / elapsed_usec;
+        Some((used_cores / quota * 100.0).clamp(0.0, 100.0) as f32)
+    }
+
+    /// Replace host CPU usage and (used, total) RAM and swap in MB with the cgroup's
+    /// values; each unlimited resource keeps the host values
+    pub fn constrain(&mut self, cpu_usage: &mut f32, ram_mb: (&mut u64, &mut u64), swap_mb: (&mut u64, &mut u64)) {
+        if let Some(usage) = self.cpu_usage() {
+            *cpu_usage = usage;
+        }
+        if let Some((used, limit)) = self.memory() {
+            *ram_mb.0 = used / 1024 / 1024;
+            *ram_mb.1 = (*ram_mb.1).min(limit / 1024 / 1024);
+        }
+        if let Some((used, limit)) = self.swap() {
+            *swap_mb.0 = used / 1024 / 1024;
+            *swap_mb.1 = (*swap_mb.1).min(limit / 1024 / 1024);
+        }
+    }
+
+    /// Short description of the limits in effect, for the startup log
+    pub fn describe(&self) -> String {
+        let memory = match self.read_value("memory.max") {
+            Some(limit) => format!("{} MB", limit / 1024 / 1024),
+            None => "unlimited".to_string(),
+        };
+        let cpu = match self.cpu_quota() {
+            Some(cores) => format!("{:.2} CPUs", cores),
+            None => "unlimited".to_string(),
+        };
+        format!("cgroup {} (memory {}, CPU {})", self.dir.display(), memory, cpu)
+    }
+}
@@ -0,0 +1,307 @@
+use chrono::Utc;
+use crate::cgroup::Cgroup;
+use crate::{BatteryInfo, HardwareInfo, ProcessUsage, SystemInfo, CAPABILITY_BATTERY, CAPABILITY_NET_RATES, CAPABILITY_PROCESSES, CAPABILITY_SWAP};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use sysinfo::{CpuExt, NetworkExt, ProcessExt, System, SystemExt};
+
+/// Source of system samples. Implementations may keep state between
+/// calls (e.g. a reused `System` or previous counter values).
+pub trait Collector: Send {
+    /// Collect one sample. May block, so callers run it off the async runtime.
+    fn collect(&mut self) -> SystemInfo;
+}
+
+/// Name of the local host ("unknown" when it cannot be determined)
+pub fn host_name() -> String {
+    System::new().host_name().unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Resident memory of the current process in bytes (None when it cannot be read)
+pub fn own_memory_bytes() -> Option<u64> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = System::new();
+    system.refresh_process(pid);
+    system.process(pid).map(|process| process.memory())
+}
+
+/// UUID identifying this machine across reinstalls of the agent: the systemd machine-id
+/// (formatted as a UUID) or, failing that, the SMBIOS product UUID (Linux only)
+pub(crate) fn machine_uuid() -> Option<String> {
+    let machine_id = ["/etc/machine-id", "/var/lib/dbus/machine-id"].iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_ascii_lowercase())
+        .find(|id| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()));
+    if let Some(id) = machine_id {
+        return Some(format!("{}-{}-{}-{}-{}", &id[..8], &id[8..12], &id[12..16], &id[16..20], &id[20..]));
+    }
+    std::fs::read_to_string("/sys/class/dmi/id/product_uuid").ok()
+        .map(|uuid| uuid.trim().to_ascii_lowercase())
+        .filter(|uuid| !uuid.is_empty())
+}
+
+/// Hardware identity with the CPU model and count as reported by the collector
+pub(crate) fn hardware_info(uuid: Option<String>, cpu_brand: &str, cpu_count: usize) -> HardwareInfo {
+    let cpu_brand = cpu_brand.trim();
+    HardwareInfo {
+        uuid,
+        arch: std::env::consts::ARCH.to_string(),
+        cpu_brand: (!cpu_brand.is_empty()).then(|| cpu_brand.to_string()),
+        cpu_count: (cpu_count > 0).then_some(cpu_count as u32),
+    }
+}
+
+/// Turns cumulative network counters into per-second rates between samples
+#[derive(Default)]
+pub(crate) struct RateTracker {
+    /// Time and (received, transmitted) totals of the previous sample
+    previous: Option<(Instant, u64, u64)>,
+}
+
+impl RateTracker {
+    /// Record new totals and return the (rx, tx) rates since the previous sample.
+    /// Returns `None` for the first sample since there is nothing to diff against.
+    pub(crate) fn update(&mut self, rx_total: u64, tx_total: u64) -> (Option<f64>, Option<f64>) {
+        let now = Instant::now();
+        let rates = self.previous.map(|(then, prev_rx, prev_tx)| {
+            let elapsed = now.duration_since(then).as_secs_f64().max(f64::EPSILON);
+            (
+                Some(counter_rate(prev_rx, rx_total, elapsed)),
+                Some(counter_rate(prev_tx, tx_total, elapsed)),
+            )
+        });
+        self.previous = Some((now, rx_total, tx_total));
+        rates.unwrap_or((None, None))
+    }
+}
+
+/// Rate of a cumulative counter; a counter reset (e.g. after reboot) reports 0
+fn counter_rate(previous: u64, current: u64, elapsed_secs: f64) -> f64 {
+    if current < previous {
+        0.0
+    } else {
+        (current - previous) as f64 / elapsed_secs
+    }
+}
+
+/// Sum of received/transmitted bytes over all non-loopback interfaces
+fn get_network_totals(system: &System) -> (u64, u64) {
+    system.networks()
+        .into_iter()
+        .filter(|(name, _)| name.as_str() != "lo")
+        .fold((0, 0), |(rx, tx), (_, data)| (rx + data.total_received(), tx + data.total_transmitted()))
+}
+
+/// Summed usage of every process called `name`, matched on the process name or the file
+/// name of its executable (Linux truncates process names to 15 characters)
+fn process_usage(system: &System, name: &str) -> ProcessUsage {
+    let (cpu_usage, memory_bytes, count) = system.processes().values()
+        .filter(|process| process.name() == name || process.exe().file_name().is_some_and(|file| file == name))
+        .fold((0.0, 0, 0), |(cpu, memory, count), process| (cpu + process.cpu_usage(), memory + process.memory(), count + 1));
+    ProcessUsage { cpu_usage, memory_mb: memory_bytes / 1024 / 1024, count }
+}
+
+/// Combined charge of the device's batteries (a laptop may have two); `None` without a
+/// battery or when it cannot be read
+#[cfg(feature = "battery")]
+pub(crate) fn get_battery() -> Option<BatteryInfo> {
+    use battery::units::energy::watt_hour;
+    let batteries: Vec<battery::Battery> = battery::Manager::new().ok()?.batteries().ok()?.flatten().collect();
+    let full: f32 = batteries.iter().map(|battery| battery.energy_full().get::<watt_hour>()).sum();
+    if full <= 0.0 {
+        return None;
+    }
+    let energy: f32 = batteries.iter().map(|battery| battery.energy().get::<watt_hour>()).sum();
+    Some(BatteryInfo {
+        percent: (energy / full * 100.0).clamp(0.0, 100.0),
+        charging: batteries.iter().any(|battery| battery.state() == battery::State::Charging),
+    })
+}
+
+/// Agents built without the `battery` feature never report one
+#[cfg(not(feature = "battery"))]
+pub(crate) fn get_battery() -> Option<BatteryInfo> {
+    None
+}
+
+/// Collects system information using the sysinfo library, reusing one `System`
+/// so CPU usage is measured over the time between samples
+pub struct SysinfoCollector {
+    system: Option<System>,
+    ui_meta: HashMap<String, String>,
+    rates: Option<RateTracker>,
+
+    /// Container limits replacing host totals, when cgroup-aware and running in a cgroup
+    cgroup: Option<Cgroup>,
+
+    /// Process names whose usage is reported in `watched_processes`
+    watched: Vec<String>,
+
+    /// Read once, the machine does not change between samples
+    uuid: Option<String>,
+}
+
+impl SysinfoCollector {
+    /// Create a collector attaching `ui_meta` to every sample; `send_rates` enables network rates,
+    /// `cgroup_aware` reports memory and CPU against the limits of the process's cgroup v2,
+    /// and the usage of the processes named in `watched` is reported in every sample
+    pub fn new(ui_meta: HashMap<String, String>, send_rates: bool, cgroup_aware: bool, watched: Vec<String>) -> Self {
+        SysinfoCollector {
+            system: None,
+            ui_meta,
+            rates: send_rates.then(RateTracker::default),
+            cgroup: if cgroup_aware { Cgroup::detect() } else { None },
+            watched,
+            uuid: machine_uuid(),
+        }
+    }
+
+    /// Limits the collector reports against when cgroup-aware; `None` when reporting host values
+    pub fn cgroup_limits(&self) -> Option<String> {
+        self.cgroup.as_ref().map(Cgroup::describe)
+    }
+
+    /// Build a sample from a refreshed `System`
+    fn sample(&mut self, system: &System, collect_duration: Duration) -> SystemInfo {
+        let collect_duration_ms = collect_duration.as_millis() as u64;
+
+        // Get device ID (hostname)
+        let device_id = system.host_name().unwrap_or_else(|| "unknown".to_string());
+
+        // Get OS information with platform-specific details
+        let os_name = system.name().unwrap_or_else(|| "Unknown".to_string());
+        let os_version = system.os_version().unwrap_or_else(|| "Unknown".to_string());
+
+        // Add platform-specific details
+        let platform_specific_details = get_platform_specific_details(system);
+        let os_info = if platform_specific_details.is_empty() {
+            format!("{} {}", os_name, os_version)
+        } else {
+            format!("{} {} ({})", os_name, os_version, platform_specific_details)
+        };
+
+        // Get CPU usage
+        let mut cpu_usage = system.global_cpu_info().cpu_usage();
+
+        // Get RAM information (convert from bytes to MB)
+        let mut ram_used_mb = system.used_memory() / 1024 / 1024;
+        let mut ram_total_mb = system.total_memory() / 1024 / 1024;
+        let mut swap_used_mb = system.used_swap() / 1024 / 1024;
+        let mut swap_total_mb = system.total_swap() / 1024 / 1024;
+
+        // Inside a container, report against its limits; each unlimited resource keeps the host values
+        if let Some(cgroup) = &mut self.cgroup {
+            cgroup.constrain(&mut cpu_usage, (&mut ram_used_mb, &mut ram_total_mb), (&mut swap_used_mb, &mut swap_total_mb));
+        }
+
+        // Get network rates when enabled (cumulative totals are diffed against the previous sample)
+        let (net_rx_rate_bytes_per_sec, net_tx_rate_bytes_per_sec) = match &mut self.rates {
+            Some(tracker) => {
+                let (rx_total, tx_total) = get_network_totals(system);
+                tracker.update(rx_total, tx_total)
+            }
+            None => (None, None),
+        };
+
+        // Get current timestamp in ISO 8601 format
+        let last_seen = Utc::now().to_rfc3339();
+
+        // Advertise optional data only when it was actually collected
+        let mut capabilities = Vec::new();
+        if swap_total_mb > 0 {
+            capabilities.push(CAPABILITY_SWAP.to_string());
+        }
+        if net_rx_rate_bytes_per_sec.is_some() {
+            capabilities.push(CAPABILITY_NET_RATES.to_string());
+        }
+        let battery = get_battery();
+        if battery.is_some() {
+            capabilities.push(CAPABILITY_BATTERY.to_string());
+        }
+
+        // Watched processes are always listed, with a count of 0 while not running
+        let watched_processes: HashMap<String, ProcessUsage> = self.watched.iter()
+            .map(|name| (name.clone(), process_usage(system, name)))
+            .collect();
+        if !watched_processes.is_empty() {
+            capabilities.push(CAPABILITY_PROCESSES.to_string());
+        }
+
+        SystemInfo {
+            device_id,
+            os_info,
+            cpu_usage,
+            ram_used_mb,
+            ram_total_mb,
+            swap_used_mb,
+            swap_total_mb,
+            collect_duration_ms,
+            last_seen,
+            net_rx_rate_bytes_per_sec,
+            net_tx_rate_bytes_per_sec,
+            ui_meta: self.ui_meta.clone(),
+            custom: HashMap::new(),
+            capabilities,
+            checksum: None,
+            via: Vec::new(),
+            battery,
+            watched_processes,
+            hardware: Some(hardware_info(self.uuid.clone(), system.global_cpu_info().brand(), system.cpus().len())),
+            idempotency_key: None,
+        }
+    }
+}
+
+impl Collector for SysinfoCollector {
+    fn collect(&mut self) -> SystemInfo {
+        // Collection time is measured around the sysinfo calls, excluding the CPU sampling sleep
+        let refresh_start = Instant::now();
+        let (system, collect_duration) = match self.system.take() {
+            Some(mut system) => {
+                system.refresh_all();
+                (system, refresh_start.elapsed())
+            }
+            None => {
+                let mut system = System::new_all();
+                system.refresh_all();
+                let mut collect_duration = refresh_start.elapsed();
+
+                // The first CPU reading needs a second refresh a moment later
+                std::thread::sleep(Duration::from_millis(100));
+                let cpu_refresh_start = Instant::now();
+                system.refresh_cpu();
+                collect_duration += cpu_refresh_start.elapsed();
+                (system, collect_duration)
+            }
+        };
+        let system_info = self.sample(&system, collect_duration);
+        self.system = Some(system);
+        system_info
+    }
+}
+
+/// Get platform-specific system details
+fn get_platform_specific_details(system: &System) -> String {
+    #[cfg(target_os = "linux")]
+    {
+        let kernel_version = system.kernel_version().unwrap_or_default();
+        if !kernel_version.is_empty() {
+            return format!("Kernel: {}", kernel_version);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Windows specific details could include build number, edition, etc.
+        return "Windows".to_string();
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // macOS specific details could include Darwin version, etc.
+        return "macOS".to_string();
+    }
+
+    // Default case for other platforms
+    String::new()
+}
@@ -0,0 +1,50 @@
+/// Units of powers of 1024
+const BINARY_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Units of powers of 1000
+const DECIMAL_UNITS: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+/// "15.3 GiB"; whole bytes below 1 KiB ("512 B")
+pub fn format_bytes(bytes: u64) -> String {
+    scaled(bytes, 1024.0, &BINARY_UNITS)
+}
+
+/// "16.4 GB", in the powers of 1000 drives and memory modules are labeled with
+pub fn format_bytes_decimal(bytes: u64) -> String {
+    scaled(bytes, 1000.0, &DECIMAL_UNITS)
+}
+
+/// A size of the samples' `_mb` fields, which count MiB, e.g. 15669 → "15.3 GiB"
+pub fn format_mb(mb: u64) -> String {
+    format_bytes(mb.saturating_mul(1024 * 1024))
+}
+
+fn scaled(value: u64, base: f64, units: &[&str]) -> String {
+    if (value as f64) < base {
+        return format!("{} {}", value, units[0]);
+    }
+    let mut size = value as f64;
+    let mut unit = 0;
+    // Compare the rounded value, so 1023.96 KiB becomes "1.0 MiB" rather than "1024.0 KiB"
+    while (size * 10.0).round() / 10.0 >= base && unit < units.len() - 1 {
+        size /= base;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// The two largest non-zero units of a duration: "45s", "1m 30s", "2h", "3d 4h"
+pub fn format_duration(secs: u64) -> String {
+    let parts = [(secs / 86400, "d"), (secs / 3600 % 24, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")];
+    let shown: Vec<String> = parts.iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if shown.is_empty() {
+        "0s".to_string()
+    } else {
+        shown.join(" ")
+    }
+}
@@ -1,4 +1,61 @@
+#[cfg(feature = "collector")]
+mod cgroup;
+#[cfg(feature = "collector")]
+pub mod collector;
+#[cfg(feature = "collector")]
+pub mod procfs;
+pub mod humanize;
+pub mod signing;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Maximum number of samples accepted in one batch request
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Maximum number of entries accepted in `SystemInfo::ui_meta`
+pub const MAX_UI_META_ENTRIES: usize = 8;
+
+/// Maximum length (in bytes) of a single `ui_meta` key or value
+pub const MAX_UI_META_LEN: usize = 64;
+
+/// Capability reported when the device has swap configured
+pub const CAPABILITY_SWAP: &str = "swap";
+
+/// Capability reported when the sample carries network rates
+pub const CAPABILITY_NET_RATES: &str = "net_rates";
+
+/// Capability reported when the sample carries exec hook metrics
+pub const CAPABILITY_CUSTOM: &str = "custom";
+
+/// Capability reported when the device has a battery (agents built with the `battery` feature)
+pub const CAPABILITY_BATTERY: &str = "battery";
+
+/// Capability reported when the sample carries watched process usage (TAILMON_WATCH_PROCS)
+pub const CAPABILITY_PROCESSES: &str = "processes";
+
+/// Maximum number of entries accepted in `SystemInfo::watched_processes`
+pub const MAX_WATCHED_PROCESSES: usize = 16;
+
+/// Maximum length (in bytes) of a watched process name
+pub const MAX_PROCESS_NAME_LEN: usize = 64;
+
+/// Maximum number of entries accepted in `SystemInfo::capabilities`
+pub const MAX_CAPABILITIES: usize = 32;
+
+/// Maximum length (in bytes) of a single capability name
+pub const MAX_CAPABILITY_LEN: usize = 64;
+
+/// Maximum number of relays listed in `SystemInfo::via`; longer chains are treated as a loop
+pub const MAX_VIA_HOPS: usize = 8;
+
+/// Maximum length (in bytes) of a relay id listed in `SystemInfo::via`
+pub const MAX_RELAY_ID_LEN: usize = 64;
+
+/// Maximum length (in bytes) of `SystemInfo::idempotency_key`
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
 
 /// System information structure that will be sent from agent to server
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -18,6 +75,347 @@ pub struct SystemInfo {
     /// Total RAM in MB
     pub ram_total_mb: u64,
     
+    /// Time the agent spent in sysinfo refresh calls for this sample, in milliseconds
+    #[serde(default)]
+    pub collect_duration_ms: u64,
+    
     /// Timestamp when data was sent (ISO 8601 format)
     pub last_seen: String,
-} 
\ No newline at end of file
+    
+    /// Used swap in MB
+    #[serde(default)]
+    pub swap_used_mb: u64,
+    
+    /// Total swap in MB
+    #[serde(default)]
+    pub swap_total_mb: u64,
+    
+    /// Bytes received per second since the previous sample (TAILMON_SEND_RATES=1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_rx_rate_bytes_per_sec: Option<f64>,
+    
+    /// Bytes transmitted per second since the previous sample (TAILMON_SEND_RATES=1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_tx_rate_bytes_per_sec: Option<f64>,
+    
+    /// Presentational hints for the dashboard (e.g. "icon", "color")
+    #[serde(default)]
+    pub ui_meta: HashMap<String, String>,
+    
+    /// Custom metrics produced by the agent's exec hook (TAILMON_EXEC_HOOK)
+    #[serde(default)]
+    pub custom: HashMap<String, serde_json::Value>,
+    
+    /// Optional data this sample actually carries (e.g. "swap", "net_rates"),
+    /// so the dashboard can skip sections the agent cannot provide
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    
+    /// CRC32 (hex) of the canonical form of all other fields, see `SystemInfo::compute_checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    
+    /// Relay servers the sample passed through, in order (TAILMON_RELAY_ID of each hop)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub via: Vec<String>,
+    
+    /// Battery of a laptop or other mobile device; `None` when the device has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<BatteryInfo>,
+    
+    /// Usage of the processes named in the agent's TAILMON_WATCH_PROCS, by name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub watched_processes: HashMap<String, ProcessUsage>,
+    
+    /// Identity of the machine the agent runs on, for asset inventories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware: Option<HardwareInfo>,
+    
+    /// Unique per sample and kept across the agent's retries, so the server stores a
+    /// sample it already received only once (see TAILMON_IDEMPOTENCY_TTL_SECS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+/// Identity of a device's hardware, which only changes when the machine does
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct HardwareInfo {
+    /// Machine UUID (the systemd machine-id, or the SMBIOS product UUID), when readable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    
+    /// CPU architecture (e.g. "x86_64", "aarch64")
+    pub arch: String,
+    
+    /// CPU model (e.g. "AMD EPYC 7763 64-Core Processor"), when reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_brand: Option<String>,
+    
+    /// Logical CPUs of the machine, when reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_count: Option<u32>,
+}
+
+/// Combined usage of every running instance of a watched process
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct ProcessUsage {
+    /// CPU usage summed over the instances, in percent of one core (may exceed 100)
+    pub cpu_usage: f32,
+    
+    /// Resident memory summed over the instances, in MB
+    pub memory_mb: u64,
+    
+    /// Running instances; 0 when the process is not running
+    pub count: u32,
+}
+
+/// Charge and state of a device's battery
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct BatteryInfo {
+    /// State of charge as a percentage
+    pub percent: f32,
+    
+    /// Connected to power and charging
+    pub charging: bool,
+}
+
+impl SystemInfo {
+    /// Whether the sample reports `capability`
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+    
+    /// Add `capability` unless it is already reported
+    pub fn add_capability(&mut self, capability: &str) {
+        if !self.has_capability(capability) {
+            self.capabilities.push(capability.to_string());
+        }
+    }
+    
+    /// Stop reporting `capability`
+    pub fn remove_capability(&mut self, capability: &str) {
+        self.capabilities.retain(|c| c != capability);
+    }
+    
+    /// Name and value of every float field that is NaN or infinite, which JSON cannot carry
+    pub fn non_finite_fields(&self) -> Vec<(&'static str, f64)> {
+        [
+            ("cpu_usage", Some(self.cpu_usage as f64)),
+            ("net_rx_rate_bytes_per_sec", self.net_rx_rate_bytes_per_sec),
+            ("net_tx_rate_bytes_per_sec", self.net_tx_rate_bytes_per_sec),
+            ("battery.percent", self.battery.map(|battery| battery.percent as f64)),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.filter(|value| !value.is_finite()).map(|value| (name, value)))
+        .collect()
+    }
+    
+    /// Replace NaN or infinite readings: `cpu_usage` is clamped to 0–100 (NaN reads as 0),
+    /// network rates and the battery are dropped. Returns the replaced fields with their original values.
+    pub fn clamp_non_finite(&mut self) -> Vec<(&'static str, f64)> {
+        let fields = self.non_finite_fields();
+        if !self.cpu_usage.is_finite() {
+            self.cpu_usage = if self.cpu_usage.is_nan() { 0.0 } else { self.cpu_usage.clamp(0.0, 100.0) };
+        }
+        for rate in [&mut self.net_rx_rate_bytes_per_sec, &mut self.net_tx_rate_bytes_per_sec] {
+            if rate.is_some_and(|rate| !rate.is_finite()) {
+                *rate = None;
+            }
+        }
+        if self.net_rx_rate_bytes_per_sec.is_none() && self.net_tx_rate_bytes_per_sec.is_none() {
+            self.remove_capability(CAPABILITY_NET_RATES);
+        }
+        if self.battery.is_some_and(|battery| !battery.percent.is_finite()) {
+            self.battery = None;
+            self.remove_capability(CAPABILITY_BATTERY);
+        }
+        fields
+    }
+    
+    /// CRC32 of the sample without its checksum and relay chain, as 8 lowercase hex digits.
+    /// Relays append to `via`, so it is left out and the agent's checksum stays valid.
+    /// The canonical form is the compact JSON serialization with object keys
+    /// sorted (serde_json's default map), so map ordering does not matter.
+    pub fn compute_checksum(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(object) = value.as_object_mut() {
+            object.remove("checksum");
+            object.remove("via");
+        }
+        format!("{:08x}", crc32fast::hash(value.to_string().as_bytes()))
+    }
+    
+    /// Attach the checksum of the current contents
+    pub fn seal(&mut self) {
+        self.checksum = Some(self.compute_checksum());
+    }
+    
+    /// Whether the attached checksum matches the contents (`None` when there is none)
+    pub fn verify_checksum(&self) -> Option<bool> {
+        let checksum = self.checksum.as_deref()?;
+        Some(checksum.eq_ignore_ascii_case(&self.compute_checksum()))
+    }
+}
+
+/// Partial update of a device's last sample (`PATCH /api/metrics/:device_id`).
+/// Every field is optional; omitted fields keep their stored value.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SystemInfoPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_info: Option<String>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_usage: Option<f32>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram_used_mb: Option<u64>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram_total_mb: Option<u64>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collect_duration_ms: Option<u64>,
+    
+    /// Defaults to the time the server applies the patch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_used_mb: Option<u64>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_total_mb: Option<u64>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_rx_rate_bytes_per_sec: Option<f64>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net_tx_rate_bytes_per_sec: Option<f64>,
+    
+    /// Replaces the whole map when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ui_meta: Option<HashMap<String, String>>,
+    
+    /// Replaces the whole map when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom: Option<HashMap<String, serde_json::Value>>,
+    
+    /// Replaces the whole list when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<BatteryInfo>,
+    
+    /// Replaces the whole map when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watched_processes: Option<HashMap<String, ProcessUsage>>,
+}
+
+impl SystemInfoPatch {
+    /// Merge the provided fields into `info`; `now` is used when no `last_seen` was sent.
+    /// The stored checksum no longer matches the merged sample and is dropped.
+    pub fn apply(self, mut info: SystemInfo, now: String) -> SystemInfo {
+        fn set<T>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+        set(&mut info.os_info, self.os_info);
+        set(&mut info.cpu_usage, self.cpu_usage);
+        set(&mut info.ram_used_mb, self.ram_used_mb);
+        set(&mut info.ram_total_mb, self.ram_total_mb);
+        set(&mut info.collect_duration_ms, self.collect_duration_ms);
+        info.last_seen = self.last_seen.unwrap_or(now);
+        set(&mut info.swap_used_mb, self.swap_used_mb);
+        set(&mut info.swap_total_mb, self.swap_total_mb);
+        set(&mut info.net_rx_rate_bytes_per_sec, self.net_rx_rate_bytes_per_sec.map(Some));
+        set(&mut info.net_tx_rate_bytes_per_sec, self.net_tx_rate_bytes_per_sec.map(Some));
+        set(&mut info.ui_meta, self.ui_meta);
+        set(&mut info.custom, self.custom);
+        set(&mut info.capabilities, self.capabilities);
+        set(&mut info.battery, self.battery.map(Some));
+        set(&mut info.watched_processes, self.watched_processes);
+        info.checksum = None;
+        info.idempotency_key = None;
+        info
+    }
+}
+
+/// Body of a successful ingest response
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IngestResponse {
+    /// Number of samples queued for storage
+    pub accepted: usize,
+    
+    /// Number of samples skipped because their idempotency key was already received
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub duplicates: usize,
+    
+    /// Reporting interval the server wants this device to use, if one is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+
+    /// Commands queued for this device since its last report, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<AgentCommand>,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+/// Action the server asks an agent to take, queued from the dashboard's command connection
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AgentCommand {
+    /// Collect and send a sample right away instead of waiting for the next interval
+    CollectNow,
+
+    /// A command this agent does not know (sent by a newer server); ignored
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SystemInfo {
+        let mut info = test_util::sample();
+        info.ui_meta.insert("icon".to_string(), "🖥️".to_string());
+        info
+    }
+
+    #[test]
+    fn sealed_sample_verifies() {
+        let mut info = sample();
+        assert_eq!(info.verify_checksum(), None);
+        info.seal();
+        assert_eq!(info.verify_checksum(), Some(true));
+
+        // Survives the JSON round trip to the server, and relays appending to `via`
+        let mut received: SystemInfo = serde_json::from_str(&serde_json::to_string(&info).unwrap()).unwrap();
+        assert_eq!(received.verify_checksum(), Some(true));
+        received.via.push("relay-1".to_string());
+        assert_eq!(received.verify_checksum(), Some(true));
+    }
+
+    #[test]
+    fn changed_field_after_seal_mismatches() {
+        let changes: [fn(&mut SystemInfo); 6] = [
+            |info| info.device_id.push('x'),
+            |info| info.os_info.clear(),
+            |info| info.cpu_usage += 0.1,
+            |info| info.ram_used_mb += 1,
+            |info| info.last_seen = "2026-01-01T00:00:01Z".to_string(),
+            |info| { info.ui_meta.insert("color".to_string(), "#fff".to_string()); },
+        ];
+        for change in changes {
+            let mut info = sample();
+            info.seal();
+            change(&mut info);
+            assert_eq!(info.verify_checksum(), Some(false), "{:?}", info);
+        }
+    }
+}
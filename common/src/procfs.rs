@@ -0,0 +1,214 @@
+use crate::cgroup::Cgroup;
+use crate::collector::{get_battery, hardware_info, host_name, machine_uuid, Collector, RateTracker};
+use crate::{SystemInfo, CAPABILITY_BATTERY, CAPABILITY_NET_RATES, CAPABILITY_SWAP};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+/// Collects system information by reading `/proc` directly, for minimal Linux containers
+/// where sysinfo reports zeros because the files it needs are not exposed.
+/// Watched processes are not supported.
+pub struct ProcCollector {
+    ui_meta: HashMap<String, String>,
+    rates: Option<RateTracker>,
+
+    /// Container limits replacing host totals, when cgroup-aware and running in a cgroup
+    cgroup: Option<Cgroup>,
+
+    /// Busy and total jiffies of the previous `/proc/stat` reading
+    previous_cpu: Option<(u64, u64)>,
+
+    /// Machine UUID, CPU model and CPU count, read once
+    uuid: Option<String>,
+    cpu_brand: String,
+    cpu_count: usize,
+}
+
+impl ProcCollector {
+    /// Create a collector with the same options as `SysinfoCollector::new`, except watched processes
+    pub fn new(ui_meta: HashMap<String, String>, send_rates: bool, cgroup_aware: bool) -> Self {
+        ProcCollector {
+            ui_meta,
+            rates: send_rates.then(RateTracker::default),
+            cgroup: if cgroup_aware { Cgroup::detect() } else { None },
+            previous_cpu: None,
+            uuid: machine_uuid(),
+            cpu_brand: read_cpu_brand(),
+            cpu_count: read_cpu_count(),
+        }
+    }
+
+    /// Whether `/proc` can be read on this host
+    pub fn is_available() -> bool {
+        fs::metadata("/proc/meminfo").is_ok()
+    }
+
+    /// Limits the collector reports against when cgroup-aware; `None` when reporting host values
+    pub fn cgroup_limits(&self) -> Option<String> {
+        self.cgroup.as_ref().map(Cgroup::describe)
+    }
+
+    /// CPU usage since the previous reading of `/proc/stat`, estimated from the load average
+    /// on the first call or when `/proc/stat` cannot be read
+    fn cpu_usage(&mut self) -> f32 {
+        let times = read_cpu_times();
+        let previous = std::mem::replace(&mut self.previous_cpu, times);
+        match (previous, times) {
+            (Some((previous_busy, previous_total)), Some((busy, total))) if total > previous_total => {
+                let busy = busy.saturating_sub(previous_busy) as f64;
+                (busy / (total - previous_total) as f64 * 100.0).clamp(0.0, 100.0) as f32
+            }
+            _ => load_estimate().unwrap_or(0.0),
+        }
+    }
+}
+
+/// (busy, total) jiffies of all CPUs from the first line of `/proc/stat`
+fn read_cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let fields: Vec<u64> = stat.lines().next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    // user nice system idle iowait irq softirq steal; guest time is already part of user
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields.get(3)? + fields.get(4).copied().unwrap_or(0);
+    Some((total.saturating_sub(idle), total))
+}
+
+/// CPU usage estimated from the 1-minute load average over the available CPUs
+fn load_estimate() -> Option<f32> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    let load: f32 = loadavg.split_whitespace().next()?.parse().ok()?;
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get()) as f32;
+    Some((load / cpus * 100.0).clamp(0.0, 100.0))
+}
+
+/// CPU model from the first "model name" of `/proc/cpuinfo` (empty when the architecture has none)
+fn read_cpu_brand() -> String {
+    fs::read_to_string("/proc/cpuinfo").unwrap_or_default()
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == "model name").then(|| value.trim().to_string())
+        })
+        .unwrap_or_default()
+}
+
+/// Logical CPUs listed in `/proc/cpuinfo`, or those available to the process when it cannot be read
+fn read_cpu_count() -> usize {
+    let listed = fs::read_to_string("/proc/cpuinfo").unwrap_or_default()
+        .lines()
+        .filter(|line| line.split_once(':').is_some_and(|(key, _)| key.trim() == "processor"))
+        .count();
+    if listed > 0 {
+        return listed;
+    }
+    std::thread::available_parallelism().map_or(0, |cpus| cpus.get())
+}
+
+/// Values of `/proc/meminfo` in kB, by key (e.g. "MemTotal")
+fn read_meminfo() -> HashMap<String, u64> {
+    fs::read_to_string("/proc/meminfo").unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some((key.to_string(), value.split_whitespace().next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Sum of received/transmitted bytes over all non-loopback interfaces from `/proc/net/dev`
+fn read_network_totals() -> Option<(u64, u64)> {
+    let dev = fs::read_to_string("/proc/net/dev").ok()?;
+    let totals = dev.lines()
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            if name.trim() == "lo" {
+                return None;
+            }
+            let counters: Vec<u64> = counters.split_whitespace().filter_map(|counter| counter.parse().ok()).collect();
+            Some((*counters.first()?, *counters.get(8)?))
+        })
+        .fold((0, 0), |(rx, tx), (rx_bytes, tx_bytes)| (rx + rx_bytes, tx + tx_bytes));
+    Some(totals)
+}
+
+/// "Ubuntu 22.04 (Kernel: 5.15.0)" from `/etc/os-release` and the kernel release, like sysinfo reports it
+fn read_os_info() -> String {
+    let os_release = fs::read_to_string("/etc/os-release").unwrap_or_default();
+    let value = |key: &str| os_release.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        .map(|value| value.trim().trim_matches('"').to_string());
+    let name = value("NAME").unwrap_or_else(|| "Linux".to_string());
+    let version = value("VERSION_ID").unwrap_or_else(|| "Unknown".to_string());
+    match fs::read_to_string("/proc/sys/kernel/osrelease") {
+        Ok(kernel) if !kernel.trim().is_empty() => format!("{} {} (Kernel: {})", name, version, kernel.trim()),
+        _ => format!("{} {}", name, version),
+    }
+}
+
+impl Collector for ProcCollector {
+    fn collect(&mut self) -> SystemInfo {
+        let start = Instant::now();
+        let mut cpu_usage = self.cpu_usage();
+
+        // Convert from kB to MB; without MemAvailable (kernels before 3.14) count free memory and caches
+        let meminfo = read_meminfo();
+        let kb = |key: &str| meminfo.get(key).copied().unwrap_or(0);
+        let available = meminfo.get("MemAvailable").copied()
+            .unwrap_or_else(|| kb("MemFree") + kb("Buffers") + kb("Cached"));
+        let mut ram_total_mb = kb("MemTotal") / 1024;
+        let mut ram_used_mb = kb("MemTotal").saturating_sub(available) / 1024;
+        let mut swap_total_mb = kb("SwapTotal") / 1024;
+        let mut swap_used_mb = kb("SwapTotal").saturating_sub(kb("SwapFree")) / 1024;
+
+        if let Some(cgroup) = &mut self.cgroup {
+            cgroup.constrain(&mut cpu_usage, (&mut ram_used_mb, &mut ram_total_mb), (&mut swap_used_mb, &mut swap_total_mb));
+        }
+
+        let (net_rx_rate_bytes_per_sec, net_tx_rate_bytes_per_sec) = match (&mut self.rates, read_network_totals()) {
+            (Some(tracker), Some((rx_total, tx_total))) => tracker.update(rx_total, tx_total),
+            _ => (None, None),
+        };
+        let os_info = read_os_info();
+        let collect_duration_ms = start.elapsed().as_millis() as u64;
+
+        let mut capabilities = Vec::new();
+        if swap_total_mb > 0 {
+            capabilities.push(CAPABILITY_SWAP.to_string());
+        }
+        if net_rx_rate_bytes_per_sec.is_some() {
+            capabilities.push(CAPABILITY_NET_RATES.to_string());
+        }
+        let battery = get_battery();
+        if battery.is_some() {
+            capabilities.push(CAPABILITY_BATTERY.to_string());
+        }
+
+        SystemInfo {
+            device_id: host_name(),
+            os_info,
+            cpu_usage,
+            ram_used_mb,
+            ram_total_mb,
+            swap_used_mb,
+            swap_total_mb,
+            collect_duration_ms,
+            last_seen: Utc::now().to_rfc3339(),
+            net_rx_rate_bytes_per_sec,
+            net_tx_rate_bytes_per_sec,
+            ui_meta: self.ui_meta.clone(),
+            custom: HashMap::new(),
+            capabilities,
+            checksum: None,
+            via: Vec::new(),
+            battery,
+            watched_processes: HashMap::new(),
+            hardware: Some(hardware_info(self.uuid.clone(), &self.cpu_brand, self.cpu_count)),
+            idempotency_key: None,
+        }
+    }
+}
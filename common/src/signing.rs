@@ -0,0 +1,47 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Header carrying the signature of an ingest request body
+pub const SIGNATURE_HEADER: &str = "x-tailmon-signature";
+
+/// Shared secret request bodies are signed with (HMAC-SHA256), and the id that
+/// tells the server which of its secrets to check the signature against
+#[derive(Debug, Clone)]
+pub struct SigningKey {
+    pub key_id: Option<String>,
+    pub secret: String,
+}
+
+impl SigningKey {
+    /// HMAC-SHA256 of `body` as lowercase hex
+    pub fn sign(&self, body: &[u8]) -> String {
+        // HMAC accepts keys of any length, so this never fails
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()) else {
+            return String::new();
+        };
+        mac.update(body);
+        mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Signature header for `body`: `keyid=<id>,sha256=<hex>`, without `keyid` when the key has no id
+    pub fn header_value(&self, body: &[u8]) -> String {
+        match &self.key_id {
+            Some(key_id) => format!("keyid={},sha256={}", key_id, self.sign(body)),
+            None => format!("sha256={}", self.sign(body)),
+        }
+    }
+}
+
+/// Key id hint (if any) and hex signature of a signature header; `None` without a signature
+pub fn parse_header(value: &str) -> Option<(Option<&str>, &str)> {
+    let mut key_id = None;
+    let mut signature = None;
+    for part in value.split(',').map(str::trim) {
+        match part.split_once('=') {
+            Some(("keyid", id)) => key_id = Some(id.trim()),
+            Some(("sha256", hex)) => signature = Some(hex.trim()),
+            _ => {}
+        }
+    }
+    Some((key_id.filter(|id| !id.is_empty()), signature?))
+}
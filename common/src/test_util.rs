@@ -0,0 +1,14 @@
+use crate::SystemInfo;
+
+/// Sample of device `web-1` with only the required fields, unsealed; tests set what else they need
+pub fn sample() -> SystemInfo {
+    serde_json::from_value(serde_json::json!({
+        "device_id": "web-1",
+        "os_info": "Ubuntu 22.04",
+        "cpu_usage": 12.5,
+        "ram_used_mb": 2048,
+        "ram_total_mb": 8192,
+        "last_seen": "2026-01-01T00:00:00Z",
+    }))
+    .expect("fixture sample deserializes")
+}
@@ -0,0 +1,145 @@
+use crate::alerts::{AlertEvent, AlertState};
+use crate::changes::ChangeEvent;
+use crate::{device_status, AppState, DeviceStatus};
+use axum::extract::{Query, State};
+use axum::response::Json as JsonResponse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Activity events kept in memory; the oldest are dropped first
+const MAX_ACTIVITY: usize = 1000;
+
+/// Events returned when the request sets no limit
+const DEFAULT_LIMIT: usize = 50;
+
+/// Seconds between scans for devices going offline or coming back
+const STATUS_SCAN_SECS: u64 = 10;
+
+/// What happened, with its details
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActivityKind {
+    /// First report of a device_id
+    NewDevice,
+
+    /// A tracked field differs from the previous sample (see changes.rs)
+    Change { field: &'static str, old: String, new: String },
+
+    /// A threshold started or stopped being exceeded
+    Alert { metric: &'static str, state: AlertState, value: f64, threshold: f64 },
+
+    /// No report for OFFLINE_AFTER_SECS
+    Offline,
+
+    /// Reporting again after being offline
+    Online,
+
+    /// Moved to a new device_id by an admin; `device_id` is the new name
+    Renamed { from: String },
+}
+
+/// One entry of the activity feed
+#[derive(Debug, Serialize, Clone)]
+pub struct ActivityEvent {
+    pub timestamp: DateTime<Utc>,
+    pub device_id: String,
+    #[serde(flatten)]
+    pub kind: ActivityKind,
+}
+
+impl From<ChangeEvent> for ActivityEvent {
+    fn from(event: ChangeEvent) -> Self {
+        ActivityEvent {
+            timestamp: event.timestamp,
+            device_id: event.device_id,
+            kind: ActivityKind::Change { field: event.field, old: event.old, new: event.new },
+        }
+    }
+}
+
+impl From<AlertEvent> for ActivityEvent {
+    fn from(event: AlertEvent) -> Self {
+        ActivityEvent {
+            timestamp: event.timestamp,
+            device_id: event.device_id,
+            kind: ActivityKind::Alert { metric: event.metric, state: event.state, value: event.value, threshold: event.threshold },
+        }
+    }
+}
+
+/// Bounded, chronological feed of everything noteworthy across the fleet
+pub struct Activity {
+    log: Mutex<VecDeque<ActivityEvent>>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Activity { log: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Record an event for `device_id` happening now
+    pub fn record(&self, device_id: &str, kind: ActivityKind) {
+        self.extend([ActivityEvent { timestamp: Utc::now(), device_id: device_id.to_string(), kind }]);
+    }
+
+    /// Record events from another source (changes, alerts)
+    pub fn extend(&self, events: impl IntoIterator<Item = ActivityEvent>) {
+        let mut log = self.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for event in events {
+            // Sources stamp their events slightly before handing them over, so keep the feed sorted
+            let position = log.iter().rposition(|existing| existing.timestamp <= event.timestamp).map_or(0, |index| index + 1);
+            log.insert(position, event);
+        }
+        let excess = log.len().saturating_sub(MAX_ACTIVITY);
+        log.drain(..excess);
+    }
+
+    /// The most recent `limit` events, newest first
+    pub fn recent(&self, limit: usize) -> Vec<ActivityEvent> {
+        let log = self.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        log.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// Query parameters for the activity endpoint
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    /// Number of events to return (default 50)
+    limit: Option<usize>,
+}
+
+/// Handler function to get the activity feed, newest first
+pub async fn get_activity(State(state): State<Arc<AppState>>, Query(query): Query<ActivityQuery>) -> JsonResponse<Vec<ActivityEvent>> {
+    JsonResponse(state.activity.recent(query.limit.unwrap_or(DEFAULT_LIMIT)))
+}
+
+/// Periodically record devices going offline and coming back online
+pub async fn run_status_watch(state: Arc<AppState>) {
+    let mut offline: HashSet<String> = HashSet::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(STATUS_SCAN_SECS));
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let statuses: Vec<(String, DeviceStatus)> = state.store.all().into_iter()
+            .map(|record| (record.info.device_id, device_status(&record.info.last_seen, now)))
+            .collect();
+        for (device_id, status) in statuses {
+            match (offline.contains(&device_id), status == DeviceStatus::Offline) {
+                (false, true) => {
+                    state.activity.record(&device_id, ActivityKind::Offline);
+                    offline.insert(device_id);
+                }
+                (true, false) => {
+                    state.activity.record(&device_id, ActivityKind::Online);
+                    offline.remove(&device_id);
+                }
+                _ => {}
+            }
+        }
+        // Renamed devices are not tracked under their old name any more
+        offline.retain(|device_id| state.store.contains(device_id));
+    }
+}
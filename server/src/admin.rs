@@ -0,0 +1,88 @@
+use crate::activity::ActivityKind;
+use crate::inventory::InventoryRecord;
+use crate::{history, series, AppState};
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::info;
+
+/// Request body of the rename endpoint
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    from: String,
+    to: String,
+}
+
+/// Handler function to move a device's metrics and history to a new device_id.
+/// If the target already exists the histories are merged by timestamp and the
+/// newer of the two current records wins.
+pub async fn rename_device(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RenameRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let RenameRequest { from, to } = request;
+    if from == to || to.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "'from' and 'to' must differ and 'to' must not be empty".to_string()));
+    }
+
+    let (mut record, mut moved) = state.store.remove(&from)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown device: {}", from)))?;
+    record.info.device_id = to.clone();
+
+    // The device has been reporting since the earlier of the two first reports
+    let moved_first_seen = state.first_seen.remove(&from).map(|(_, first_seen)| first_seen);
+    let first_seen = {
+        let mut target = state.first_seen.entry(to.clone()).or_insert(record.first_seen);
+        if let Some(moved_first_seen) = moved_first_seen {
+            *target = (*target).min(moved_first_seen);
+        }
+        *target
+    };
+    record.first_seen = first_seen;
+
+    let merged = match state.store.get(&to) {
+        Some(existing) if existing.info.last_seen >= record.info.last_seen => {
+            state.store.update(&to, &mut |existing| existing.first_seen = first_seen);
+            true
+        }
+        existing => {
+            state.store.upsert(record);
+            existing.is_some()
+        }
+    };
+
+    // The raw payload still names the old device_id, so it is not carried over
+    state.raw.remove(&from);
+    state.conflicts.forget(&from);
+    state.inventory.remove(&from);
+    if let Some(record) = state.store.get(&to) {
+        state.inventory.observe(&InventoryRecord::from_record(&record));
+    }
+
+    // Merge the history buffers by timestamp under the new key
+    let retention_secs = state.config().history_retention_secs;
+    state.store.update_history(&to, &mut |target| {
+        let mut combined: Vec<_> = target.drain(..).chain(moved.drain(..)).collect();
+        combined.sort_by_key(|point| point.timestamp);
+        for point in &mut combined {
+            point.info.device_id = to.clone();
+        }
+        *target = combined.into();
+        history::trim(target, retention_secs, Utc::now());
+    });
+    if let Some((_, moved)) = state.series.remove(&from) {
+        series::merge(&mut state.series.entry(to.clone()).or_default(), moved);
+    }
+    if let Some((_, moved)) = state.percentiles.remove(&from) {
+        state.percentiles.entry(to.clone()).or_default().merge(moved);
+    }
+    state.annotations.rename(&from, &to);
+
+    state.activity.record(&to, ActivityKind::Renamed { from: from.clone() });
+    info!("Renamed device {} to {}{}", from, to, if merged { " (merged into existing device)" } else { "" });
+    Ok(format!("Renamed {} to {}", from, to))
+}
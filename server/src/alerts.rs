@@ -0,0 +1,292 @@
+use crate::{ram_pct, AppState};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use chrono::{DateTime, Utc};
+use common::SystemInfo;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// Effective alert thresholds, in percent
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Thresholds {
+    pub cpu_max: f64,
+    pub ram_pct_max: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        // Same critical thresholds the dashboard highlights
+        Thresholds { cpu_max: 80.0, ram_pct_max: 90.0 }
+    }
+}
+
+/// How alert transitions are delivered to the webhook (TAILMON_ALERT_MODE)
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMode {
+    /// One webhook call per transition
+    Immediate,
+
+    /// One call every TAILMON_ALERT_DIGEST_SECS listing all active breaches
+    Digest,
+}
+
+/// Per-device override; unset fields fall back to the global defaults
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct AlertRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_max: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram_pct_max: Option<f64>,
+}
+
+impl AlertRule {
+    /// Thresholds of this rule layered over `defaults`
+    fn resolve(&self, defaults: Thresholds) -> Thresholds {
+        Thresholds {
+            cpu_max: self.cpu_max.unwrap_or(defaults.cpu_max),
+            ram_pct_max: self.ram_pct_max.unwrap_or(defaults.ram_pct_max),
+        }
+    }
+}
+
+/// Current value and threshold of a metric that exceeds its threshold
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Breach {
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// Which thresholds a device currently exceeds
+#[derive(Debug, Clone, Copy, Default)]
+struct Firing {
+    cpu: Option<Breach>,
+    ram: Option<Breach>,
+}
+
+/// Whether an alert started or resolved
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// An alert transition, as delivered to the webhook
+#[derive(Debug, Serialize, Clone)]
+pub struct AlertEvent {
+    pub device_id: String,
+    pub metric: &'static str,
+    pub state: AlertState,
+    pub value: f64,
+    pub threshold: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A threshold a device exceeds right now, as listed in a digest
+#[derive(Debug, Serialize, Clone)]
+pub struct ActiveBreach {
+    pub device_id: String,
+    pub metric: &'static str,
+    #[serde(flatten)]
+    pub breach: Breach,
+}
+
+/// Evaluates every ingested sample against the global or per-device
+/// thresholds and logs when an alert starts or resolves.
+/// With notifications enabled, transitions are also buffered for the webhook task,
+/// except those of silenced devices.
+pub struct Alerts {
+    /// Global thresholds, replaced on configuration reload
+    defaults: RwLock<Thresholds>,
+    rules: DashMap<String, AlertRule>,
+    firing: DashMap<String, Firing>,
+
+    /// Devices kept off the webhook, with the end of their silence
+    silenced: DashMap<String, DateTime<Utc>>,
+
+    /// Transitions waiting to be delivered (None when no webhook is configured)
+    pending: Option<Mutex<Vec<AlertEvent>>>,
+
+    /// Wakes the webhook task when a transition is buffered
+    pub notify: Notify,
+}
+
+impl Alerts {
+    pub fn new(defaults: Thresholds, notifications: bool) -> Self {
+        Alerts {
+            defaults: RwLock::new(defaults),
+            rules: DashMap::new(),
+            firing: DashMap::new(),
+            silenced: DashMap::new(),
+            pending: notifications.then(|| Mutex::new(Vec::new())),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Global thresholds applying to devices without a rule
+    pub fn defaults(&self) -> Thresholds {
+        *self.defaults.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set_defaults(&self, defaults: Thresholds) {
+        *self.defaults.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = defaults;
+    }
+
+    /// Thresholds in effect for a device: its rule over the global defaults
+    pub fn thresholds_for(&self, device_id: &str) -> Thresholds {
+        let defaults = self.defaults();
+        self.rules.get(device_id)
+            .map(|rule| rule.resolve(defaults))
+            .unwrap_or(defaults)
+    }
+
+    /// Keep a device's transitions off the webhook until `until`; `None` lifts the silence
+    pub fn silence(&self, device_id: &str, until: Option<DateTime<Utc>>) {
+        match until {
+            Some(until) => {
+                info!("Alerts of {} silenced until {}", device_id, until.to_rfc3339());
+                self.silenced.insert(device_id.to_string(), until);
+            }
+            None => {
+                if self.silenced.remove(device_id).is_some() {
+                    info!("Alerts of {} no longer silenced", device_id);
+                }
+            }
+        }
+    }
+
+    /// Whether a device is silenced at `now`; expired silences are dropped
+    fn is_silenced(&self, device_id: &str, now: DateTime<Utc>) -> bool {
+        self.silenced.remove_if(device_id, |_, until| *until <= now);
+        self.silenced.contains_key(device_id)
+    }
+
+    /// Check a sample and log alert transitions for its device, returning them
+    pub fn evaluate(&self, info: &SystemInfo) -> Vec<AlertEvent> {
+        let thresholds = self.thresholds_for(&info.device_id);
+        let cpu = info.cpu_usage as f64;
+        let ram = ram_pct(info);
+        let breach = |value: f64, threshold: f64| (value > threshold).then_some(Breach { value, threshold });
+        let now = Firing {
+            cpu: breach(cpu, thresholds.cpu_max),
+            ram: breach(ram, thresholds.ram_pct_max),
+        };
+
+        let before = self.firing.insert(info.device_id.clone(), now).unwrap_or_default();
+        let mut events = Vec::new();
+        match (before.cpu.is_some(), now.cpu.is_some()) {
+            (false, true) => {
+                warn!("Alert: device {} CPU at {:.1}% exceeds {:.1}%", info.device_id, cpu, thresholds.cpu_max);
+                events.push(("cpu", AlertState::Firing, cpu, thresholds.cpu_max));
+            }
+            (true, false) => {
+                info!("Resolved: device {} CPU back to {:.1}%", info.device_id, cpu);
+                events.push(("cpu", AlertState::Resolved, cpu, thresholds.cpu_max));
+            }
+            _ => {}
+        }
+        match (before.ram.is_some(), now.ram.is_some()) {
+            (false, true) => {
+                warn!("Alert: device {} RAM at {:.1}% exceeds {:.1}%", info.device_id, ram, thresholds.ram_pct_max);
+                events.push(("ram", AlertState::Firing, ram, thresholds.ram_pct_max));
+            }
+            (true, false) => {
+                info!("Resolved: device {} RAM back to {:.1}%", info.device_id, ram);
+                events.push(("ram", AlertState::Resolved, ram, thresholds.ram_pct_max));
+            }
+            _ => {}
+        }
+
+        let timestamp = Utc::now();
+        let events: Vec<AlertEvent> = events.into_iter()
+            .map(|(metric, state, value, threshold)| AlertEvent {
+                device_id: info.device_id.clone(),
+                metric,
+                state,
+                value,
+                threshold,
+                timestamp,
+            })
+            .collect();
+        if let (Some(pending), false) = (&self.pending, events.is_empty() || self.is_silenced(&info.device_id, timestamp)) {
+            pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).extend(events.iter().cloned());
+            self.notify.notify_one();
+        }
+        events
+    }
+
+    /// Take all buffered transitions
+    pub fn drain_events(&self) -> Vec<AlertEvent> {
+        match &self.pending {
+            Some(pending) => std::mem::take(&mut *pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())),
+            None => Vec::new(),
+        }
+    }
+
+    /// Every threshold currently exceeded by a device that is not silenced, ordered by device_id
+    pub fn active_breaches(&self) -> Vec<ActiveBreach> {
+        let now = Utc::now();
+        let mut active: Vec<ActiveBreach> = self.firing.iter()
+            .filter(|entry| !self.is_silenced(entry.key(), now))
+            .flat_map(|entry| {
+                let device_id = entry.key().clone();
+                let firing = *entry.value();
+                [("cpu", firing.cpu), ("ram", firing.ram)].into_iter()
+                    .filter_map(move |(metric, breach)| {
+                        breach.map(|breach| ActiveBreach { device_id: device_id.clone(), metric, breach })
+                    })
+            })
+            .collect();
+        active.sort_by(|a, b| a.device_id.cmp(&b.device_id).then(a.metric.cmp(b.metric)));
+        active
+    }
+}
+
+/// Response of the alert rules endpoint
+#[derive(Debug, Serialize)]
+pub struct AlertsResponse {
+    defaults: Thresholds,
+    devices: BTreeMap<String, AlertRule>,
+
+    /// End of the silence of every silenced device
+    silenced: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// Handler function to list the global thresholds and all per-device rules
+pub async fn get_alerts(State(state): State<Arc<AppState>>) -> JsonResponse<AlertsResponse> {
+    let devices = state.alerts.rules.iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    let now = Utc::now();
+    let silenced = state.alerts.silenced.iter()
+        .filter(|entry| *entry.value() > now)
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    JsonResponse(AlertsResponse { defaults: state.alerts.defaults(), devices, silenced })
+}
+
+/// Handler function to set the alert rule of one device
+pub async fn put_alert_rule(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Json(rule): Json<AlertRule>,
+) -> Result<JsonResponse<AlertRule>, (StatusCode, String)> {
+    for (name, value) in [("cpu_max", rule.cpu_max), ("ram_pct_max", rule.ram_pct_max)] {
+        if value.is_some_and(|value| !(0.0..=100.0).contains(&value)) {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("{} must be between 0 and 100", name)));
+        }
+    }
+
+    info!("Alert rule for {} set to {:?}", device_id, rule);
+    state.alerts.rules.insert(device_id, rule);
+    Ok(JsonResponse(rule))
+}
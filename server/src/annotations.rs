@@ -0,0 +1,122 @@
+use crate::AppState;
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Annotations kept per device and for all devices; the oldest are dropped first
+const MAX_ANNOTATIONS: usize = 100;
+
+/// Longest annotation text in bytes
+const MAX_TEXT_LEN: usize = 256;
+
+/// A note on the timeline of a device (or of every device), e.g. a deploy,
+/// drawn by graphs as a vertical marker
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Annotation {
+    /// `null` for an annotation of all devices
+    pub device_id: Option<String>,
+    pub ts: DateTime<Utc>,
+    pub text: String,
+}
+
+/// Annotations by device, sorted by time. Kept in memory only.
+#[derive(Default)]
+pub struct Annotations {
+    devices: DashMap<String, Vec<Annotation>>,
+    global: Mutex<Vec<Annotation>>,
+}
+
+impl Annotations {
+    pub fn add(&self, annotation: Annotation) {
+        match &annotation.device_id {
+            Some(device_id) => insert(&mut self.devices.entry(device_id.clone()).or_default(), annotation),
+            None => insert(&mut self.global.lock().unwrap_or_else(|poisoned| poisoned.into_inner()), annotation),
+        }
+    }
+
+    /// Annotations of the device and global ones from `from` to `to` (inclusive), oldest first
+    pub fn between(&self, device_id: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Annotation> {
+        let global = self.global.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let mut annotations: Vec<Annotation> = self.devices.get(device_id)
+            .map(|annotations| annotations.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(global)
+            .filter(|annotation| (from..=to).contains(&annotation.ts))
+            .collect();
+        annotations.sort_by_key(|annotation| annotation.ts);
+        annotations
+    }
+
+    /// Move a renamed device's annotations to its new device_id
+    pub fn rename(&self, from: &str, to: &str) {
+        if let Some((_, moved)) = self.devices.remove(from) {
+            let mut target = self.devices.entry(to.to_string()).or_default();
+            for mut annotation in moved {
+                annotation.device_id = Some(to.to_string());
+                insert(&mut target, annotation);
+            }
+        }
+    }
+
+    /// Drop annotations older than the longest retention, which no history response can overlap
+    pub fn trim(&self, retention_secs: u64, now: DateTime<Utc>) {
+        let cutoff = now - chrono::Duration::seconds(retention_secs as i64);
+        self.devices.retain(|_, annotations| {
+            annotations.retain(|annotation| annotation.ts >= cutoff);
+            !annotations.is_empty()
+        });
+        self.global.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).retain(|annotation| annotation.ts >= cutoff);
+    }
+}
+
+/// Insert at the annotation's place in time, dropping the oldest beyond MAX_ANNOTATIONS
+fn insert(annotations: &mut Vec<Annotation>, annotation: Annotation) {
+    let index = annotations.partition_point(|existing| existing.ts <= annotation.ts);
+    annotations.insert(index, annotation);
+    let excess = annotations.len().saturating_sub(MAX_ANNOTATIONS);
+    annotations.drain(..excess);
+}
+
+/// Request body of the annotations endpoint
+#[derive(Debug, Deserialize)]
+pub struct AnnotationRequest {
+    /// `null` or absent annotates all devices
+    #[serde(default)]
+    device_id: Option<String>,
+
+    /// Defaults to now
+    ts: Option<DateTime<Utc>>,
+    text: String,
+}
+
+/// Handler function to add an annotation shown in history responses overlapping its time
+pub async fn post_annotation(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<(StatusCode, JsonResponse<Annotation>), (StatusCode, String)> {
+    let text = request.text.trim();
+    if text.is_empty() || text.len() > MAX_TEXT_LEN {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("text must be 1 to {} bytes", MAX_TEXT_LEN)));
+    }
+    if request.device_id.as_deref().is_some_and(str::is_empty) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "device_id must not be empty (null annotates all devices)".to_string()));
+    }
+
+    let annotation = Annotation {
+        device_id: request.device_id,
+        ts: request.ts.unwrap_or_else(Utc::now),
+        text: text.to_string(),
+    };
+    info!("Annotation for {} at {}: {}",
+        annotation.device_id.as_deref().unwrap_or("all devices"), annotation.ts.to_rfc3339(), annotation.text);
+    state.annotations.add(annotation.clone());
+    Ok((StatusCode::CREATED, JsonResponse(annotation)))
+}
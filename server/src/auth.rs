@@ -0,0 +1,67 @@
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Extract the presented key from `Authorization: Bearer <key>` or `X-API-Key: <key>`
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer.or_else(|| headers.get("x-api-key").and_then(|value| value.to_str().ok()))
+        .map(str::trim)
+}
+
+/// Compare two secrets in time independent of where they differ
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether the request carries the expected key
+pub fn has_key(headers: &HeaderMap, expected: &str) -> bool {
+    presented_key(headers).is_some_and(|key| constant_time_eq(key, expected))
+}
+
+/// Middleware guarding write routes (ingest) with TAILMON_API_KEY.
+/// Without a configured key the routes stay open for backward compatibility.
+pub async fn require_api_key(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if let Some(expected) = &state.config().api_key {
+        if !has_key(request.headers(), expected) {
+            warn!("Rejected {} {}: missing or invalid API key", request.method(), request.uri().path());
+            return (StatusCode::UNAUTHORIZED, "Missing or invalid API key").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Middleware guarding read routes (`GET /api/*`) with TAILMON_READ_API_KEY.
+/// The write key is accepted too; without a read key the routes stay open for backward compatibility.
+pub async fn require_read_key(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if let Some(expected) = &state.config().read_api_key {
+        let headers = request.headers();
+        let write_key = state.config().api_key.as_deref().is_some_and(|key| has_key(headers, key));
+        if !write_key && !has_key(headers, expected) {
+            warn!("Rejected {} {}: missing or invalid read API key", request.method(), request.uri().path());
+            return (StatusCode::UNAUTHORIZED, "Missing or invalid API key").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Middleware guarding admin routes, which are disabled entirely without TAILMON_API_KEY
+pub async fn require_admin_key(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(expected) = &state.config().api_key else {
+        return (StatusCode::FORBIDDEN, "Admin endpoints require TAILMON_API_KEY to be configured").into_response();
+    };
+    if !has_key(request.headers(), expected) {
+        warn!("Rejected {} {}: missing or invalid API key", request.method(), request.uri().path());
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid API key").into_response();
+    }
+    next.run(request).await
+}
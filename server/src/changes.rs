@@ -0,0 +1,143 @@
+use crate::{webhook, AppState};
+use axum::extract::{Query, State};
+use axum::response::Json as JsonResponse;
+use chrono::{DateTime, Utc};
+use common::SystemInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// Change events kept in memory; the oldest are dropped first
+const MAX_EVENTS: usize = 1000;
+
+/// A tracked field of a device that differs from its previous sample,
+/// e.g. an OS upgrade or a RAM change
+#[derive(Debug, Serialize, Clone)]
+pub struct ChangeEvent {
+    pub device_id: String,
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Fields compared between consecutive samples of a device
+fn tracked_fields(info: &SystemInfo) -> [(&'static str, String); 2] {
+    [
+        ("os_info", info.os_info.clone()),
+        ("ram_total_mb", info.ram_total_mb.to_string()),
+    ]
+}
+
+/// Event log of configuration changes detected on ingest.
+/// With notifications enabled, events are also buffered for the webhook task.
+pub struct Changes {
+    log: Mutex<VecDeque<ChangeEvent>>,
+
+    /// Events waiting to be delivered (None unless TAILMON_CHANGE_WEBHOOK is set)
+    pending: Option<Mutex<Vec<ChangeEvent>>>,
+
+    /// Wakes the webhook task when an event is buffered
+    notify: Notify,
+}
+
+impl Changes {
+    pub fn new(notifications: bool) -> Self {
+        Changes {
+            log: Mutex::new(VecDeque::new()),
+            pending: notifications.then(|| Mutex::new(Vec::new())),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Record an event for every tracked field that differs between `previous` and `current`, returning them
+    pub fn diff(&self, previous: &SystemInfo, current: &SystemInfo) -> Vec<ChangeEvent> {
+        let timestamp = Utc::now();
+        let events: Vec<ChangeEvent> = tracked_fields(previous).into_iter()
+            .zip(tracked_fields(current))
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((field, old), (_, new))| ChangeEvent {
+                device_id: current.device_id.clone(),
+                field,
+                old,
+                new,
+                timestamp,
+            })
+            .collect();
+        if events.is_empty() {
+            return events;
+        }
+
+        let mut log = self.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for event in &events {
+            info!("Change: device {} {} changed from {:?} to {:?}", event.device_id, event.field, event.old, event.new);
+            log.push_back(event.clone());
+        }
+        let excess = log.len().saturating_sub(MAX_EVENTS);
+        log.drain(..excess);
+
+        if let Some(pending) = &self.pending {
+            pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).extend(events.iter().cloned());
+            self.notify.notify_one();
+        }
+        events
+    }
+
+    /// Logged events, oldest first
+    pub fn events(&self) -> Vec<ChangeEvent> {
+        self.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().cloned().collect()
+    }
+
+    fn drain_pending(&self) -> Vec<ChangeEvent> {
+        match &self.pending {
+            Some(pending) => std::mem::take(&mut *pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Query parameters for the events endpoint
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Only events of this device
+    device_id: Option<String>,
+
+    /// Return only the most recent N events
+    limit: Option<usize>,
+}
+
+/// Handler function to list logged change events, oldest first
+pub async fn get_events(State(state): State<Arc<AppState>>, Query(query): Query<EventsQuery>) -> JsonResponse<Vec<ChangeEvent>> {
+    let mut events = state.changes.events();
+    if let Some(device_id) = &query.device_id {
+        events.retain(|event| &event.device_id == device_id);
+    }
+    let skip = query.limit.map_or(0, |limit| events.len().saturating_sub(limit));
+    JsonResponse(events.split_off(skip))
+}
+
+/// Deliver change events to TAILMON_ALERT_WEBHOOK_URL, one call per event
+/// (no-op unless both the webhook and TAILMON_CHANGE_WEBHOOK are set)
+pub async fn run_webhook(state: Arc<AppState>) {
+    let (Some(url), true) = (state.config().alert_webhook_url.clone(), state.config().change_webhook) else {
+        return;
+    };
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Change webhook disabled: failed to create HTTP client: {}", e);
+            return;
+        }
+    };
+
+    info!("Sending change events to webhook {}", url);
+    loop {
+        state.changes.notify.notified().await;
+        for event in state.changes.drain_pending() {
+            webhook::post(&client, &url, &event).await;
+        }
+    }
+}
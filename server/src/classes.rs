@@ -0,0 +1,51 @@
+use common::SystemInfo;
+use serde::Serialize;
+
+/// Classes used when TAILMON_DEVICE_CLASSES is unset
+pub const DEFAULT_DEVICE_CLASSES: &str = "tiny:2048:2,standard:32768:16,large";
+
+/// A resource profile devices are bucketed into (TAILMON_DEVICE_CLASSES)
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DeviceClass {
+    pub name: String,
+
+    /// Most RAM a device of the class has, in MB (None: any)
+    pub max_ram_mb: Option<u64>,
+
+    /// Most CPUs a device of the class has (None: any)
+    pub max_cpus: Option<u32>,
+}
+
+impl DeviceClass {
+    /// Parse `name:max_ram_mb:max_cpus`, where the limits may be left out or `*` for any
+    pub fn parse(entry: &str) -> Option<Self> {
+        let mut parts = entry.split(':').map(str::trim);
+        let name = parts.next().filter(|name| !name.is_empty())?.to_string();
+        let max_ram_mb = parse_limit(parts.next())?;
+        let max_cpus = parse_limit(parts.next())?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(DeviceClass { name, max_ram_mb, max_cpus })
+    }
+
+    /// A device without a reported CPU count is classed by its RAM alone
+    fn fits(&self, system_info: &SystemInfo) -> bool {
+        let cpus = system_info.hardware.as_ref().and_then(|hardware| hardware.cpu_count);
+        self.max_ram_mb.is_none_or(|max| system_info.ram_total_mb <= max)
+            && self.max_cpus.zip(cpus).is_none_or(|(max, cpus)| cpus <= max)
+    }
+}
+
+/// `None` for an absent or `*` limit, `Some(None)` for one that does not parse
+fn parse_limit<T: std::str::FromStr>(part: Option<&str>) -> Option<Option<T>> {
+    match part {
+        None | Some("") | Some("*") => Some(None),
+        Some(limit) => limit.parse().ok().map(Some),
+    }
+}
+
+/// Name of the first class the device fits, `None` when it fits none
+pub fn classify(classes: &[DeviceClass], system_info: &SystemInfo) -> Option<String> {
+    classes.iter().find(|class| class.fits(system_info)).map(|class| class.name.clone())
+}
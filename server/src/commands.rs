@@ -0,0 +1,231 @@
+use crate::{auth, set_interval, AppState};
+use axum::{
+    extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use common::humanize::format_duration;
+use common::AgentCommand;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// Longest silence the command connection can put on a device's alerts (one week)
+const MAX_SILENCE_SECS: u64 = 7 * 86400;
+
+/// Commands waiting for their device's next report
+#[derive(Default)]
+pub struct CommandQueue {
+    pending: DashMap<String, Vec<AgentCommand>>,
+}
+
+impl CommandQueue {
+    /// Queue a command for a device; returns false when the same command is already waiting
+    fn push(&self, device_id: &str, command: AgentCommand) -> bool {
+        let mut queued = self.pending.entry(device_id.to_string()).or_default();
+        if queued.contains(&command) {
+            return false;
+        }
+        queued.push(command);
+        true
+    }
+
+    /// Take every command queued for a device
+    pub fn take(&self, device_id: &str) -> Vec<AgentCommand> {
+        self.pending.remove(device_id).map(|(_, commands)| commands).unwrap_or_default()
+    }
+}
+
+/// Command sent by a dashboard, e.g. `{"device_id": "web-1", "command": "collect_now"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// Push a reporting interval to the agent; `null` clears the override
+    SetInterval { interval_secs: Option<u64> },
+
+    /// Have the agent collect and report right after its next report
+    CollectNow,
+
+    /// Keep the device's alerts off the webhook for `secs` seconds; 0 lifts the silence
+    SilenceAlerts { secs: u64 },
+}
+
+/// One message received on the command connection
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    device_id: String,
+
+    #[serde(flatten)]
+    command: Command,
+}
+
+/// Answer to one command message
+#[derive(Debug, Serialize)]
+struct CommandReply {
+    /// The request's `id`, if it had one, so a client can match replies to commands
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    ok: bool,
+    message: String,
+}
+
+/// Query parameters of the command connection
+#[derive(Debug, Deserialize)]
+pub struct SocketQuery {
+    /// TAILMON_API_KEY, for clients that cannot send it as a header
+    key: Option<String>,
+}
+
+/// Handler function opening the dashboard's command connection. It is disabled without
+/// TAILMON_API_KEY, and the key is accepted as a header or as `?key=`.
+pub async fn command_socket(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SocketQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let Some(expected) = state.config().api_key.clone() else {
+        return (StatusCode::FORBIDDEN, "The command connection requires TAILMON_API_KEY to be configured").into_response();
+    };
+    let query_key = query.key.as_deref().is_some_and(|key| auth::constant_time_eq(key.trim(), &expected));
+    if !query_key && !auth::has_key(&headers, &expected) {
+        warn!("Rejected command connection: missing or invalid API key");
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid API key").into_response();
+    }
+    upgrade.on_upgrade(move |socket| serve(state, socket, expected))
+}
+
+/// Counts a command connection as open (for /metrics) until dropped
+struct OpenConnection<'a>(&'a AtomicU64);
+
+impl<'a> OpenConnection<'a> {
+    fn new(connections: &'a AtomicU64) -> Self {
+        connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(connections)
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answer every command message until the client disconnects. The connection is closed
+/// when the API key it was opened with is changed or removed by a reload, and when the
+/// client answers nothing between two pings, so the pong timeout is TAILMON_WS_PING_SECS too.
+async fn serve(state: Arc<AppState>, mut socket: WebSocket, key: String) {
+    let _open = OpenConnection::new(&state.command_connections);
+    info!("Command connection opened");
+    let ping_secs = state.config().ws_ping_secs;
+    let mut ping = tokio::time::interval(std::time::Duration::from_secs(ping_secs.max(1)));
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping.tick().await;
+    let mut awaiting_pong = false;
+    loop {
+        let message = tokio::select! {
+            message = socket.recv() => message,
+            _ = ping.tick(), if ping_secs > 0 => {
+                // A vanished client would not read a close frame, so the connection is just dropped
+                if awaiting_pong {
+                    warn!("Closing command connection: no pong within {}", format_duration(ping_secs));
+                    break;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                awaiting_pong = true;
+                continue;
+            }
+        };
+        let Some(Ok(message)) = message else {
+            break;
+        };
+        // Any message shows the client is still there, not only the pong
+        awaiting_pong = false;
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        if state.config().api_key.as_deref() != Some(key.as_str()) {
+            let frame = CloseFrame { code: close_code::POLICY, reason: "API key changed".into() };
+            let _ = socket.send(Message::Close(Some(frame))).await;
+            break;
+        }
+        let reply = handle(&state, &text);
+        let Ok(reply) = serde_json::to_string(&reply) else {
+            continue;
+        };
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+    info!("Command connection closed");
+}
+
+/// Check and carry out one command message
+fn handle(state: &AppState, text: &str) -> CommandReply {
+    let failed = |id, device_id, message| CommandReply { id, device_id, ok: false, message };
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return failed(None, None, format!("Invalid JSON: {}", e)),
+    };
+    let id = request.get("id").cloned();
+    let CommandRequest { device_id, command } = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => return failed(id, None, format!("Invalid command: {}", e)),
+    };
+    match authorize(state, &device_id).and_then(|()| apply(state, &device_id, command)) {
+        Ok(message) => CommandReply { id, device_id: Some(device_id), ok: true, message },
+        Err(message) => failed(id, Some(device_id), message),
+    }
+}
+
+/// Commands only go to devices the server knows, and with TAILMON_COMMAND_DEVICES only to those listed
+fn authorize(state: &AppState, device_id: &str) -> Result<(), String> {
+    if let Some(allowed) = &state.config().command_devices {
+        if !allowed.iter().any(|allowed| allowed == device_id) {
+            warn!("Rejected command for {}: not in TAILMON_COMMAND_DEVICES", device_id);
+            return Err(format!("Device {} does not accept commands", device_id));
+        }
+    }
+    if state.store.get(device_id).is_none() {
+        return Err(format!("Unknown device {}", device_id));
+    }
+    Ok(())
+}
+
+fn apply(state: &AppState, device_id: &str, command: Command) -> Result<String, String> {
+    match command {
+        Command::SetInterval { interval_secs } => set_interval(state, device_id, interval_secs),
+        Command::CollectNow => {
+            if state.commands.push(device_id, AgentCommand::CollectNow) {
+                info!("Queued an immediate collection for {}", device_id);
+                Ok(format!("Collection queued for {}'s next report", device_id))
+            } else {
+                Ok(format!("A collection is already queued for {}", device_id))
+            }
+        }
+        Command::SilenceAlerts { secs: 0 } => {
+            state.alerts.silence(device_id, None);
+            Ok(format!("Alerts of {} no longer silenced", device_id))
+        }
+        Command::SilenceAlerts { secs } if secs > MAX_SILENCE_SECS => {
+            Err(format!("secs must be at most {}", MAX_SILENCE_SECS))
+        }
+        Command::SilenceAlerts { secs } => {
+            state.alerts.silence(device_id, Some(Utc::now() + Duration::seconds(secs as i64)));
+            Ok(format!("Alerts of {} silenced for {}", device_id, format_duration(secs)))
+        }
+    }
+}
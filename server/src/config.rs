@@ -0,0 +1,730 @@
+use crate::alerts::{AlertMode, Thresholds};
+use crate::classes::{DeviceClass, DEFAULT_DEVICE_CLASSES};
+use crate::health::HealthWeights;
+use crate::history::{GapFillMode, TimeSource};
+use crate::retain::{RetainField, RetainRule, Retention};
+use crate::series::{Metric, SeriesSpec};
+use crate::{MAX_INTERVAL_SECS, OFFLINE_AFTER_SECS, STALE_AFTER_SECS};
+use chrono_tz::Tz;
+use common::signing::SigningKey;
+use common::MAX_BATCH_SIZE;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+use tracing::warn;
+
+/// Values from TAILMON_CONFIG_FILE, taking precedence over the environment
+static FILE_VALUES: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+#[cfg(test)]
+thread_local! {
+    /// Set while `Config::defaults` builds a configuration, so no setting is looked up
+    static DEFAULTS_ONLY: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Server configuration read from TAILMON_* environment variables (and the
+/// optional TAILMON_CONFIG_FILE) at startup. Fields marked reloadable are
+/// re-read on SIGHUP, see `Config::reload`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Reverse-proxy path prefix (e.g. "/tailmon"), empty when hosted at the root
+    pub base_path: String,
+
+    /// Seconds browsers may cache dashboard assets without revalidating; 0 always revalidates.
+    /// index.html is always revalidated and fingerprinted assets are cached for a year (reloadable)
+    pub static_cache_secs: u64,
+
+    /// How long history samples are kept per device, in seconds
+    pub history_retention_secs: u64,
+
+    /// Samples older than this many seconds are downsampled (None disables downsampling)
+    pub downsample_after_secs: Option<u64>,
+
+    /// Width of a downsampled history bucket in seconds
+    pub downsample_bucket_secs: u64,
+
+    /// Store a history point only when CPU or RAM% moved by this much (None stores every sample; reloadable)
+    pub history_delta_pct: Option<f64>,
+
+    /// With a delta filter, still store at least one point per this many seconds (reloadable)
+    pub history_min_interval_secs: u64,
+
+    /// History responses fill gaps up to this many seconds with synthetic points (None disables filling; reloadable)
+    pub max_gap_fill_secs: Option<u64>,
+
+    /// Interpolate or repeat the previous point when filling gaps (reloadable)
+    pub gap_fill_mode: GapFillMode,
+
+    /// Most points one history response returns, whatever `limit` asks for (None is unlimited; reloadable)
+    pub max_history_limit: Option<usize>,
+
+    /// Clock that sets and orders sample times: the agent's last_seen or the server's receive time
+    pub time_source: TimeSource,
+
+    /// Metrics kept in their own series with independent retention and resolution
+    pub series: Vec<SeriesSpec>,
+
+    /// PEM server certificate chain; TLS is enabled when this and the key are set
+    pub tls_cert_path: Option<String>,
+
+    /// PEM server private key
+    pub tls_key_path: Option<String>,
+
+    /// PEM CA bundle used to verify agent client certificates
+    pub client_ca_path: Option<String>,
+
+    /// Require a client certificate whose CN matches the reporting device_id
+    pub require_client_cert: bool,
+
+    /// MaxMind GeoLite2/GeoIP2 City database used to locate devices
+    pub geoip_db_path: Option<String>,
+
+    /// Rhai script deriving `custom` entries from every ingested sample
+    pub transform_script: Option<String>,
+
+    /// Time limit for one run of the transform script, in milliseconds
+    pub transform_timeout_ms: u64,
+
+    /// Shared secret required for ingest and admin routes (reloadable)
+    pub api_key: Option<String>,
+
+    /// Devices the command connection may send commands to (None allows every known device; reloadable)
+    pub command_devices: Option<Vec<String>>,
+
+    /// Seconds between pings on a command connection, and the pong timeout: a client that has not
+    /// answered one ping by the next is disconnected. 0 disables pings (reloadable, applies to new connections)
+    pub ws_ping_secs: u64,
+
+    /// Shared secret required for `GET /api/*` routes (the ingest key is accepted too; reloadable)
+    pub read_api_key: Option<String>,
+
+    /// Secrets an ingest request may be signed with; several are active during a rotation.
+    /// Empty accepts unsigned requests (reloadable)
+    pub hmac_secrets: Vec<SigningKey>,
+
+    /// Capacity of the ingest queue; a full queue answers 503
+    pub ingest_queue_size: usize,
+
+    /// Weights of the health score signals (reloadable)
+    pub health_weights: HealthWeights,
+
+    /// Global alert thresholds, overridable per device via the API (reloadable)
+    pub alert_defaults: Thresholds,
+
+    /// URL alert transitions are POSTed to as JSON
+    pub alert_webhook_url: Option<String>,
+
+    /// One webhook call per transition or a periodic digest
+    pub alert_mode: AlertMode,
+
+    /// Seconds between alert digests
+    pub alert_digest_secs: u64,
+
+    /// Also POST device change events to the alert webhook
+    pub change_webhook: bool,
+
+    /// Also POST an event to the alert webhook when a device goes offline and when it comes back
+    pub offline_webhook: bool,
+
+    /// Seconds without a report after which the offline webhook fires
+    pub offline_webhook_after_secs: u64,
+
+    /// File the history buffers are snapshotted to and restored from on startup
+    pub snapshot_path: Option<String>,
+
+    /// Seconds between history snapshots
+    pub snapshot_interval_secs: u64,
+
+    /// Ingest payloads larger than this many bytes are logged as oversized (reloadable)
+    pub ingest_warn_bytes: u64,
+
+    /// Optional fields kept in stored records (None keeps every field; reloadable)
+    pub retain_fields: Option<Vec<RetainField>>,
+
+    /// Optional fields kept per group of devices, first match wins over `retain_fields` (reloadable)
+    pub retain_rules: Vec<RetainRule>,
+
+    /// Resource profiles devices are classed into on ingest, first fit wins (empty disables; reloadable)
+    pub device_classes: Vec<DeviceClass>,
+
+    /// Reverse-resolve device source addresses into `resolved_name`
+    pub reverse_dns: bool,
+
+    /// How long a reverse DNS result is cached, in seconds
+    pub reverse_dns_ttl_secs: u64,
+
+    /// Largest ingest body kept verbatim for the raw endpoint (0 disables it; reloadable)
+    pub raw_max_bytes: usize,
+
+    /// Report the server host's own metrics as a device
+    pub self_monitor: bool,
+
+    /// Seconds between self-monitor samples
+    pub self_monitor_interval_secs: u64,
+
+    /// Ingest requests handled at once before answering 503 (None is unlimited)
+    pub max_concurrent: Option<usize>,
+
+    /// IANA zone used to render timestamps in logs; stored and API values stay UTC
+    pub timezone: Tz,
+
+    /// Ingest URL of an upstream tailmon server every sample is relayed to
+    pub upstream_url: Option<String>,
+
+    /// API key presented to the upstream server
+    pub upstream_api_key: Option<String>,
+
+    /// Key relayed requests are signed with for the upstream server
+    pub upstream_hmac: Option<SigningKey>,
+
+    /// Samples buffered for the upstream while it is unreachable
+    pub upstream_buffer: usize,
+
+    /// Retries each successful upstream forward earns for later failures
+    pub upstream_retry_ratio: f32,
+
+    /// Identifier this server appends to `via` of relayed samples
+    pub relay_id: String,
+
+    /// Reject samples whose checksum does not match their contents (reloadable)
+    pub verify_checksum: bool,
+
+    /// Seconds a sample's idempotency key is remembered to skip retried duplicates; 0 disables (reloadable)
+    pub idempotency_ttl_secs: u64,
+
+    /// Periodic export of the fleet to S3-compatible storage (`s3-export` feature)
+    pub s3_export: Option<S3Export>,
+}
+
+/// Destination of the periodic fleet export, from the TAILMON_S3_* variables
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "s3-export"), allow(dead_code))]
+pub struct S3Export {
+    pub bucket: String,
+
+    /// Prepended to every object key (e.g. "tailmon/")
+    pub prefix: String,
+    pub region: String,
+
+    /// Endpoint of a non-AWS service such as MinIO; addressed path-style
+    pub endpoint: Option<String>,
+
+    /// Static credentials; without them the AWS environment, profile or instance role is used
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+
+    /// Seconds between exports
+    pub interval_secs: u64,
+
+    /// Also export every device's retained history
+    pub include_history: bool,
+}
+
+/// Policy the server is running with, served at `GET /api/config`. Secrets (keys, webhook
+/// and upstream URLs) are only reported as set or not; file paths are left out.
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub alert_defaults: Thresholds,
+    pub alert_mode: AlertMode,
+    pub alert_digest_secs: u64,
+    pub alert_webhook_set: bool,
+    pub change_webhook: bool,
+    pub offline_webhook: bool,
+    pub offline_webhook_after_secs: u64,
+    pub health_weights: HealthWeights,
+    pub stale_after_secs: i64,
+    pub offline_after_secs: i64,
+    pub history_retention_secs: u64,
+    pub downsample_after_secs: Option<u64>,
+    pub downsample_bucket_secs: u64,
+    pub history_delta_pct: Option<f64>,
+    pub history_min_interval_secs: u64,
+    pub max_gap_fill_secs: Option<u64>,
+    pub gap_fill_mode: GapFillMode,
+    pub max_history_limit: Option<usize>,
+    pub time_source: TimeSource,
+    pub series: Vec<EffectiveSeries>,
+    pub retain_fields: Option<Vec<RetainField>>,
+    pub retain_rules: Vec<EffectiveRetainRule>,
+    pub device_classes: Vec<DeviceClass>,
+    pub raw_max_bytes: usize,
+    pub snapshot_enabled: bool,
+    pub snapshot_interval_secs: u64,
+    pub s3_export_enabled: bool,
+    pub s3_export_interval_secs: Option<u64>,
+    pub ingest_queue_size: usize,
+    pub max_concurrent: Option<usize>,
+    pub max_batch_size: usize,
+    pub max_interval_secs: u64,
+    pub ingest_warn_bytes: u64,
+    pub verify_checksum: bool,
+    pub idempotency_ttl_secs: u64,
+    pub api_key_set: bool,
+    pub read_api_key_set: bool,
+    pub hmac_key_ids: Vec<String>,
+    pub command_devices: Option<Vec<String>>,
+    pub ws_ping_secs: u64,
+    pub tls: bool,
+    pub require_client_cert: bool,
+    pub geoip: bool,
+    pub transform: bool,
+    pub transform_timeout_ms: u64,
+    pub reverse_dns: bool,
+    pub self_monitor: bool,
+    pub upstream_set: bool,
+    pub upstream_signed: bool,
+    pub timezone: String,
+    pub static_cache_secs: u64,
+}
+
+/// One entry of TAILMON_SERIES
+#[derive(Debug, Serialize)]
+pub struct EffectiveSeries {
+    pub metric: &'static str,
+    pub retention_secs: u64,
+    pub resolution_secs: u64,
+}
+
+/// One entry of TAILMON_RETAIN_RULES, with the selector as written
+#[derive(Debug, Serialize)]
+pub struct EffectiveRetainRule {
+    pub selector: String,
+    pub fields: Vec<RetainField>,
+}
+
+impl Config {
+    /// Read TAILMON_CONFIG_FILE (if set) and build the configuration from it and the environment
+    pub fn load() -> Result<Self, String> {
+        let values = match env::var("TAILMON_CONFIG_FILE").ok().filter(|path| !path.trim().is_empty()) {
+            Some(path) => {
+                let contents = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+                Some(parse_config_file(&contents).map_err(|e| format!("invalid {}: {}", path, e))?)
+            }
+            None => None,
+        };
+        *FILE_VALUES.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = values;
+        Ok(Config::from_env())
+    }
+
+    /// The configuration with every setting at its default, whatever the environment holds
+    #[cfg(test)]
+    pub fn defaults() -> Self {
+        DEFAULTS_ONLY.with(|defaults_only| defaults_only.set(true));
+        let config = Config::from_env();
+        DEFAULTS_ONLY.with(|defaults_only| defaults_only.set(false));
+        config
+    }
+
+    /// The policy part of the configuration, with secrets redacted
+    pub fn effective(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            alert_defaults: self.alert_defaults,
+            alert_mode: self.alert_mode,
+            alert_digest_secs: self.alert_digest_secs,
+            alert_webhook_set: self.alert_webhook_url.is_some(),
+            change_webhook: self.change_webhook,
+            offline_webhook: self.offline_webhook,
+            offline_webhook_after_secs: self.offline_webhook_after_secs,
+            health_weights: self.health_weights,
+            stale_after_secs: STALE_AFTER_SECS,
+            offline_after_secs: OFFLINE_AFTER_SECS,
+            history_retention_secs: self.history_retention_secs,
+            downsample_after_secs: self.downsample_after_secs,
+            downsample_bucket_secs: self.downsample_bucket_secs,
+            history_delta_pct: self.history_delta_pct,
+            history_min_interval_secs: self.history_min_interval_secs,
+            max_gap_fill_secs: self.max_gap_fill_secs,
+            max_history_limit: self.max_history_limit,
+            time_source: self.time_source,
+            gap_fill_mode: self.gap_fill_mode,
+            series: self.series.iter()
+                .map(|spec| EffectiveSeries { metric: spec.metric.name(), retention_secs: spec.retention_secs, resolution_secs: spec.resolution_secs })
+                .collect(),
+            retain_fields: self.retain_fields.clone(),
+            retain_rules: self.retain_rules.iter()
+                .map(|rule| EffectiveRetainRule { selector: rule.selector.to_string(), fields: rule.fields.clone() })
+                .collect(),
+            device_classes: self.device_classes.clone(),
+            raw_max_bytes: self.raw_max_bytes,
+            snapshot_enabled: self.snapshot_path.is_some(),
+            snapshot_interval_secs: self.snapshot_interval_secs,
+            s3_export_enabled: self.s3_export.is_some(),
+            s3_export_interval_secs: self.s3_export.as_ref().map(|export| export.interval_secs),
+            ingest_queue_size: self.ingest_queue_size,
+            max_concurrent: self.max_concurrent,
+            max_batch_size: MAX_BATCH_SIZE,
+            max_interval_secs: MAX_INTERVAL_SECS,
+            ingest_warn_bytes: self.ingest_warn_bytes,
+            verify_checksum: self.verify_checksum,
+            idempotency_ttl_secs: self.idempotency_ttl_secs,
+            api_key_set: self.api_key.is_some(),
+            read_api_key_set: self.read_api_key.is_some(),
+            hmac_key_ids: self.hmac_secrets.iter().filter_map(|key| key.key_id.clone()).collect(),
+            command_devices: self.command_devices.clone(),
+            ws_ping_secs: self.ws_ping_secs,
+            tls: self.tls_cert_path.is_some() && self.tls_key_path.is_some(),
+            require_client_cert: self.require_client_cert,
+            geoip: self.geoip_db_path.is_some(),
+            transform: self.transform_script.is_some(),
+            transform_timeout_ms: self.transform_timeout_ms,
+            reverse_dns: self.reverse_dns,
+            self_monitor: self.self_monitor,
+            upstream_set: self.upstream_url.is_some(),
+            upstream_signed: self.upstream_hmac.is_some(),
+            timezone: self.timezone.name().to_string(),
+            static_cache_secs: self.static_cache_secs,
+        }
+    }
+
+    /// Optional fields the store keeps of each device
+    pub fn retention(&self) -> Retention {
+        Retention { rules: self.retain_rules.clone(), default: self.retain_fields.clone() }
+    }
+
+    /// Take the reloadable settings from a freshly loaded configuration, keeping
+    /// everything fixed at startup (bind address, TLS, paths, queues, background tasks)
+    pub fn reload(&mut self, fresh: Config) {
+        self.health_weights = fresh.health_weights;
+        self.alert_defaults = fresh.alert_defaults;
+        self.api_key = fresh.api_key;
+        self.read_api_key = fresh.read_api_key;
+        self.hmac_secrets = fresh.hmac_secrets;
+        self.command_devices = fresh.command_devices;
+        self.ws_ping_secs = fresh.ws_ping_secs;
+        self.static_cache_secs = fresh.static_cache_secs;
+        self.history_delta_pct = fresh.history_delta_pct;
+        self.history_min_interval_secs = fresh.history_min_interval_secs;
+        self.max_gap_fill_secs = fresh.max_gap_fill_secs;
+        self.gap_fill_mode = fresh.gap_fill_mode;
+        self.max_history_limit = fresh.max_history_limit;
+        self.ingest_warn_bytes = fresh.ingest_warn_bytes;
+        self.retain_fields = fresh.retain_fields;
+        self.retain_rules = fresh.retain_rules;
+        self.device_classes = fresh.device_classes;
+        self.raw_max_bytes = fresh.raw_max_bytes;
+        self.verify_checksum = fresh.verify_checksum;
+        self.idempotency_ttl_secs = fresh.idempotency_ttl_secs;
+    }
+
+    /// Build the configuration from the config file values and environment, falling back to defaults
+    fn from_env() -> Self {
+        Config {
+            base_path: get_base_path(),
+            static_cache_secs: env_u64("TAILMON_STATIC_CACHE_SECS").unwrap_or(300),
+            history_retention_secs: env_u64("TAILMON_HISTORY_RETENTION_SECS").unwrap_or(3600),
+            downsample_after_secs: env_u64("TAILMON_DOWNSAMPLE_AFTER_SECS"),
+            downsample_bucket_secs: env_u64("TAILMON_DOWNSAMPLE_BUCKET_SECS").unwrap_or(60).max(1),
+            history_delta_pct: env_f64("TAILMON_HISTORY_DELTA_PCT"),
+            history_min_interval_secs: env_u64("TAILMON_HISTORY_MIN_INTERVAL").unwrap_or(60),
+            max_gap_fill_secs: env_u64("TAILMON_MAX_GAP_FILL").filter(|secs| *secs > 0),
+            gap_fill_mode: get_gap_fill_mode(),
+            max_history_limit: env_u64("TAILMON_MAX_HISTORY_LIMIT").filter(|max| *max > 0).map(|max| max as usize),
+            time_source: get_time_source(),
+            series: get_series(),
+            tls_cert_path: env_string("TAILMON_TLS_CERT"),
+            tls_key_path: env_string("TAILMON_TLS_KEY"),
+            client_ca_path: env_string("TAILMON_CLIENT_CA"),
+            require_client_cert: env_flag("TAILMON_REQUIRE_CLIENT_CERT"),
+            geoip_db_path: env_string("TAILMON_GEOIP_DB"),
+            transform_script: env_string("TAILMON_TRANSFORM_SCRIPT"),
+            transform_timeout_ms: env_u64("TAILMON_TRANSFORM_TIMEOUT_MS").unwrap_or(50).max(1),
+            api_key: env_string("TAILMON_API_KEY"),
+            read_api_key: env_string("TAILMON_READ_API_KEY"),
+            hmac_secrets: get_hmac_secrets(),
+            command_devices: env_string("TAILMON_COMMAND_DEVICES")
+                .map(|raw| raw.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect()),
+            ws_ping_secs: env_u64("TAILMON_WS_PING_SECS").unwrap_or(30),
+            ingest_queue_size: env_u64("TAILMON_INGEST_QUEUE_SIZE").unwrap_or(1024).max(1) as usize,
+            health_weights: get_health_weights(),
+            alert_defaults: get_alert_defaults(),
+            alert_webhook_url: env_string("TAILMON_ALERT_WEBHOOK_URL"),
+            alert_mode: get_alert_mode(),
+            alert_digest_secs: env_u64("TAILMON_ALERT_DIGEST_SECS").unwrap_or(300).max(1),
+            change_webhook: env_flag("TAILMON_CHANGE_WEBHOOK"),
+            offline_webhook: env_flag("TAILMON_OFFLINE_WEBHOOK"),
+            offline_webhook_after_secs: env_u64("TAILMON_OFFLINE_WEBHOOK_AFTER_SECS").unwrap_or(OFFLINE_AFTER_SECS as u64).max(1),
+            snapshot_path: env_string("TAILMON_SNAPSHOT_PATH"),
+            snapshot_interval_secs: env_u64("TAILMON_SNAPSHOT_INTERVAL_SECS").unwrap_or(300).max(1),
+            ingest_warn_bytes: env_u64("TAILMON_INGEST_WARN_BYTES").unwrap_or(65536),
+            retain_fields: get_retain_fields(),
+            retain_rules: get_retain_rules(),
+            device_classes: get_device_classes(),
+            reverse_dns: env_flag("TAILMON_REVERSE_DNS"),
+            reverse_dns_ttl_secs: env_u64("TAILMON_REVERSE_DNS_TTL_SECS").unwrap_or(3600).max(1),
+            raw_max_bytes: env_u64("TAILMON_RAW_MAX_BYTES").unwrap_or(16384) as usize,
+            self_monitor: env_flag("TAILMON_SELF_MONITOR"),
+            self_monitor_interval_secs: env_u64("TAILMON_SELF_MONITOR_INTERVAL_SECS").unwrap_or(5).max(1),
+            max_concurrent: env_u64("TAILMON_MAX_CONCURRENT").filter(|max| *max > 0).map(|max| max as usize),
+            timezone: get_timezone(),
+            upstream_url: env_string("TAILMON_UPSTREAM_URL").map(|url| url.trim().trim_end_matches('/').to_string()),
+            upstream_api_key: env_string("TAILMON_UPSTREAM_API_KEY"),
+            upstream_hmac: env_string("TAILMON_UPSTREAM_HMAC_SECRET")
+                .map(|secret| SigningKey { key_id: env_string("TAILMON_UPSTREAM_HMAC_KEY_ID"), secret }),
+            upstream_buffer: env_u64("TAILMON_UPSTREAM_BUFFER").unwrap_or(1000).max(1) as usize,
+            upstream_retry_ratio: env_f64("TAILMON_UPSTREAM_RETRY_RATIO").unwrap_or(0.2).clamp(0.0, 1000.0) as f32,
+            relay_id: env_string("TAILMON_RELAY_ID").unwrap_or_else(common::collector::host_name),
+            verify_checksum: env_flag("TAILMON_VERIFY_CHECKSUM"),
+            idempotency_ttl_secs: env_u64("TAILMON_IDEMPOTENCY_TTL_SECS").unwrap_or(600),
+            s3_export: get_s3_export(),
+        }
+    }
+}
+
+/// Get the fleet export destination; `None` unless TAILMON_S3_BUCKET is set
+fn get_s3_export() -> Option<S3Export> {
+    let bucket = env_string("TAILMON_S3_BUCKET")?;
+    Some(S3Export {
+        bucket,
+        prefix: env_string("TAILMON_S3_PREFIX").unwrap_or_else(|| "tailmon/".to_string()),
+        region: env_string("TAILMON_S3_REGION").unwrap_or_else(|| "us-east-1".to_string()),
+        endpoint: env_string("TAILMON_S3_ENDPOINT").map(|endpoint| endpoint.trim().trim_end_matches('/').to_string()),
+        access_key: env_string("TAILMON_S3_ACCESS_KEY"),
+        secret_key: env_string("TAILMON_S3_SECRET_KEY"),
+        interval_secs: env_u64("TAILMON_S3_INTERVAL_SECS").unwrap_or(3600).max(1),
+        include_history: env_flag("TAILMON_S3_INCLUDE_HISTORY"),
+    })
+}
+
+/// Get the reverse-proxy path prefix from TAILMON_BASE_PATH (e.g. "/tailmon").
+/// Returns an empty string when the server is hosted at the root.
+fn get_base_path() -> String {
+    let base_path = var("TAILMON_BASE_PATH").unwrap_or_default();
+    let trimmed = base_path.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Get the health score weights from TAILMON_HEALTH_WEIGHT_{CPU,RAM,SWAP,STALENESS}
+fn get_health_weights() -> HealthWeights {
+    let defaults = HealthWeights::default();
+    HealthWeights {
+        cpu: env_f64("TAILMON_HEALTH_WEIGHT_CPU").unwrap_or(defaults.cpu),
+        ram: env_f64("TAILMON_HEALTH_WEIGHT_RAM").unwrap_or(defaults.ram),
+        swap: env_f64("TAILMON_HEALTH_WEIGHT_SWAP").unwrap_or(defaults.swap),
+        staleness: env_f64("TAILMON_HEALTH_WEIGHT_STALENESS").unwrap_or(defaults.staleness),
+    }
+}
+
+/// Get the global alert thresholds from TAILMON_ALERT_CPU_MAX and TAILMON_ALERT_RAM_PCT_MAX
+fn get_alert_defaults() -> Thresholds {
+    let defaults = Thresholds::default();
+    Thresholds {
+        cpu_max: env_f64("TAILMON_ALERT_CPU_MAX").unwrap_or(defaults.cpu_max),
+        ram_pct_max: env_f64("TAILMON_ALERT_RAM_PCT_MAX").unwrap_or(defaults.ram_pct_max),
+    }
+}
+
+/// Get the alert delivery mode from TAILMON_ALERT_MODE ("immediate" or "digest")
+fn get_alert_mode() -> AlertMode {
+    match env_string("TAILMON_ALERT_MODE").as_deref().map(str::trim) {
+        None | Some("immediate") => AlertMode::Immediate,
+        Some("digest") => AlertMode::Digest,
+        Some(other) => {
+            warn!("Ignoring invalid value for TAILMON_ALERT_MODE: {}", other);
+            AlertMode::Immediate
+        }
+    }
+}
+
+/// Get the authoritative clock from TAILMON_TIME_SOURCE ("agent" or "server")
+fn get_time_source() -> TimeSource {
+    match env_string("TAILMON_TIME_SOURCE").as_deref().map(str::trim) {
+        None | Some("agent") => TimeSource::Agent,
+        Some("server") => TimeSource::Server,
+        Some(other) => {
+            warn!("Ignoring invalid value for TAILMON_TIME_SOURCE: {}", other);
+            TimeSource::Agent
+        }
+    }
+}
+
+/// Get the gap filling mode from TAILMON_GAP_FILL_MODE ("linear" or "previous")
+fn get_gap_fill_mode() -> GapFillMode {
+    match env_string("TAILMON_GAP_FILL_MODE").as_deref().map(str::trim) {
+        None | Some("linear") => GapFillMode::Linear,
+        Some("previous") => GapFillMode::Previous,
+        Some(other) => {
+            warn!("Ignoring invalid value for TAILMON_GAP_FILL_MODE: {}", other);
+            GapFillMode::Linear
+        }
+    }
+}
+
+/// Parse the per-metric series from TAILMON_SERIES, a comma-separated list of
+/// `metric:retention_secs:resolution_secs` (e.g. "cpu_usage:3600:5,ram_total_mb:604800:3600")
+fn get_series() -> Vec<SeriesSpec> {
+    let raw = var("TAILMON_SERIES").unwrap_or_default();
+    let mut series: Vec<SeriesSpec> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let parts: Vec<&str> = entry.split(':').map(str::trim).collect();
+        let spec = match parts.as_slice() {
+            [metric, retention, resolution] => Metric::parse(metric)
+                .zip(retention.parse().ok().filter(|secs| *secs > 0))
+                .zip(resolution.parse().ok().filter(|secs| *secs > 0))
+                .map(|((metric, retention_secs), resolution_secs)| SeriesSpec { metric, retention_secs, resolution_secs }),
+            _ => None,
+        };
+        match spec {
+            Some(spec) if series.iter().any(|existing| existing.metric == spec.metric) => {
+                warn!("Ignoring duplicate TAILMON_SERIES entry: {}", entry);
+            }
+            Some(spec) => series.push(spec),
+            None => warn!("Ignoring invalid TAILMON_SERIES entry: {}", entry),
+        }
+    }
+    series
+}
+
+/// Parse the comma-separated list of optional fields to keep from TAILMON_RETAIN_FIELDS.
+/// Unset keeps everything; an empty value keeps only the core fields.
+fn get_retain_fields() -> Option<Vec<RetainField>> {
+    let raw = var("TAILMON_RETAIN_FIELDS")?;
+    let fields = raw.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let field = RetainField::parse(name);
+            if field.is_none() {
+                warn!("Ignoring unknown TAILMON_RETAIN_FIELDS field: {}", name);
+            }
+            field
+        })
+        .collect();
+    Some(fields)
+}
+
+/// Parse per-group retention rules from TAILMON_RETAIN_RULES
+/// (semicolon-separated `selector:fields`, e.g. "laptop-*:;role=server:swap,net_rates")
+fn get_retain_rules() -> Vec<RetainRule> {
+    let raw = var("TAILMON_RETAIN_RULES").unwrap_or_default();
+    raw.split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let rule = RetainRule::parse(entry);
+            if rule.is_none() {
+                warn!("Ignoring invalid TAILMON_RETAIN_RULES entry: {}", entry);
+            }
+            rule
+        })
+        .collect()
+}
+
+/// Parse the device classes from TAILMON_DEVICE_CLASSES, a comma-separated list of
+/// `name:max_ram_mb:max_cpus` (default: "tiny:2048:2,standard:32768:16,large")
+fn get_device_classes() -> Vec<DeviceClass> {
+    let raw = var("TAILMON_DEVICE_CLASSES").unwrap_or_else(|| DEFAULT_DEVICE_CLASSES.to_string());
+    let mut classes: Vec<DeviceClass> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match DeviceClass::parse(entry) {
+            Some(class) if classes.iter().any(|existing| existing.name == class.name) => {
+                warn!("Ignoring duplicate TAILMON_DEVICE_CLASSES entry: {}", entry);
+            }
+            Some(class) => classes.push(class),
+            None => warn!("Ignoring invalid TAILMON_DEVICE_CLASSES entry: {}", entry),
+        }
+    }
+    classes
+}
+
+/// Get the signing secrets from TAILMON_HMAC_SECRETS, comma-separated `key_id:secret` entries
+fn get_hmac_secrets() -> Vec<SigningKey> {
+    let Some(raw) = var("TAILMON_HMAC_SECRETS") else {
+        return Vec::new();
+    };
+    let mut keys: Vec<SigningKey> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once(':').map(|(key_id, secret)| (key_id.trim(), secret.trim())) {
+            Some((key_id, secret)) if !key_id.is_empty() && !secret.is_empty() => {
+                if keys.iter().any(|key| key.key_id.as_deref() == Some(key_id)) {
+                    warn!("Ignoring duplicate TAILMON_HMAC_SECRETS key id: {}", key_id);
+                    continue;
+                }
+                keys.push(SigningKey { key_id: Some(key_id.to_string()), secret: secret.to_string() });
+            }
+            // The entry holds a secret, so only its key id (if any) is logged
+            _ => warn!("Ignoring invalid TAILMON_HMAC_SECRETS entry {} (expected key_id:secret)",
+                entry.split_once(':').map_or("without a key id", |(key_id, _)| key_id)),
+        }
+    }
+    keys
+}
+
+/// Get the display zone from TAILMON_TZ (e.g. "Europe/Berlin"), falling back to UTC
+fn get_timezone() -> Tz {
+    let Some(name) = env_string("TAILMON_TZ") else {
+        return Tz::UTC;
+    };
+    match name.trim().parse() {
+        Ok(timezone) => timezone,
+        Err(_) => {
+            warn!("Ignoring unknown TAILMON_TZ zone {}, using UTC", name);
+            Tz::UTC
+        }
+    }
+}
+
+/// Parse `KEY=VALUE` lines; blank lines and lines starting with `#` are ignored
+fn parse_config_file(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut values = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| format!("line {}: expected KEY=VALUE", number + 1))?;
+        values.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Ok(values)
+}
+
+/// Look up a setting, preferring the config file over the environment
+fn var(name: &str) -> Option<String> {
+    #[cfg(test)]
+    if DEFAULTS_ONLY.with(|defaults_only| defaults_only.get()) {
+        return None;
+    }
+    let file_values = FILE_VALUES.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match file_values.as_ref().and_then(|values| values.get(name)) {
+        Some(value) => Some(value.clone()),
+        None => env::var(name).ok(),
+    }
+}
+
+/// Read a non-empty string variable
+fn env_string(name: &str) -> Option<String> {
+    var(name).filter(|value| !value.trim().is_empty())
+}
+
+/// Read a boolean flag ("1" or "true")
+fn env_flag(name: &str) -> bool {
+    var(name).is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Read an unsigned integer variable, warning about (and ignoring) invalid values
+fn env_u64(name: &str) -> Option<u64> {
+    let value = var(name)?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            warn!("Ignoring invalid value for {}: {}", name, value);
+            None
+        }
+    }
+}
+
+/// Read a floating point variable, warning about (and ignoring) invalid values
+fn env_f64(name: &str) -> Option<f64> {
+    let value = var(name)?;
+    match value.trim().parse::<f64>() {
+        Ok(parsed) if parsed.is_finite() => Some(parsed),
+        _ => {
+            warn!("Ignoring invalid value for {}: {}", name, value);
+            None
+        }
+    }
+}
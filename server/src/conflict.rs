@@ -0,0 +1,69 @@
+use chrono::{DateTime, Duration, Utc};
+use common::humanize::format_duration;
+use common::SystemInfo;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use tracing::warn;
+
+/// Window in which repeated changes of a device's stable fields are considered implausible
+const CONFLICT_WINDOW_SECS: i64 = 600;
+
+/// Changes of the stable fields within the window that flag a device. One change is an
+/// upgrade or a resize; a second one soon after (typically back again) means two machines.
+const CONFLICT_CHANGES: usize = 2;
+
+/// Fields that stay the same between samples of one machine
+fn fingerprint(info: &SystemInfo) -> (String, u64, u64) {
+    (info.os_info.clone(), info.ram_total_mb, info.swap_total_mb)
+}
+
+/// Stable fields of a device's last sample and when they recently changed
+struct DeviceFingerprint {
+    last: (String, u64, u64),
+    changes: VecDeque<DateTime<Utc>>,
+    conflicted: bool,
+}
+
+/// Detection of several machines reporting under one device_id (e.g. cloned VMs),
+/// whose samples alternate between different hardware and OS
+pub struct Conflicts {
+    devices: DashMap<String, DeviceFingerprint>,
+}
+
+impl Conflicts {
+    pub fn new() -> Self {
+        Conflicts { devices: DashMap::new() }
+    }
+
+    /// Note a sample and return whether its device_id currently looks shared by several machines.
+    /// The flag clears once the stable fields have not changed for the whole window.
+    pub fn observe(&self, info: &SystemInfo, now: DateTime<Utc>) -> bool {
+        let current = fingerprint(info);
+        let mut device = self.devices.entry(info.device_id.clone()).or_insert_with(|| DeviceFingerprint {
+            last: current.clone(),
+            changes: VecDeque::new(),
+            conflicted: false,
+        });
+        if device.last != current {
+            device.last = current;
+            device.changes.push_back(now);
+        }
+        let cutoff = now - Duration::seconds(CONFLICT_WINDOW_SECS);
+        while device.changes.front().is_some_and(|changed| *changed < cutoff) {
+            device.changes.pop_front();
+        }
+
+        let conflicted = device.changes.len() >= CONFLICT_CHANGES;
+        if conflicted && !device.conflicted {
+            warn!("Device {} changed its OS or memory size {} times within {}: several machines may share this device_id",
+                info.device_id, device.changes.len(), format_duration(CONFLICT_WINDOW_SECS as u64));
+        }
+        device.conflicted = conflicted;
+        conflicted
+    }
+
+    /// Drop the state of a device that no longer exists under this name
+    pub fn forget(&self, device_id: &str) {
+        self.devices.remove(device_id);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::config::S3Export;
+use crate::history::HistoryPoint;
+use crate::record::DeviceRecord;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use common::humanize::format_duration;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Contents of one exported object
+#[derive(Serialize)]
+struct FleetExport {
+    exported_at: DateTime<Utc>,
+    devices: Vec<DeviceRecord>,
+
+    /// Retained history per device (only with TAILMON_S3_INCLUDE_HISTORY)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<BTreeMap<String, VecDeque<HistoryPoint>>>,
+}
+
+/// Open the configured bucket. Credentials not given explicitly are looked up the way the
+/// AWS tools do, which may query the instance metadata service, so this runs off the runtime.
+async fn open_bucket(export: &S3Export) -> Result<Box<Bucket>, String> {
+    let (access_key, secret_key) = (export.access_key.clone(), export.secret_key.clone());
+    let credentials = tokio::task::spawn_blocking(move || Credentials::new(access_key.as_deref(), secret_key.as_deref(), None, None, None))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("no credentials: {}", e))?;
+    let region = match &export.endpoint {
+        Some(endpoint) => Region::Custom { region: export.region.clone(), endpoint: endpoint.clone() },
+        None => export.region.parse().map_err(|e| format!("invalid region {}: {}", export.region, e))?,
+    };
+    let bucket = Bucket::new(&export.bucket, region, credentials).map_err(|e| e.to_string())?;
+    Ok(if export.endpoint.is_some() { bucket.with_path_style() } else { bucket })
+}
+
+/// Serialize the current fleet state and upload it as `<prefix>fleet-<timestamp>.json`
+async fn upload(state: &AppState, bucket: &Bucket, export: &S3Export) -> Result<String, String> {
+    let exported_at = Utc::now();
+    let contents = FleetExport {
+        exported_at,
+        devices: state.store.all(),
+        history: export.include_history.then(|| state.store.all_history().into_iter().collect()),
+    };
+    let body = serde_json::to_vec(&contents).map_err(|e| e.to_string())?;
+    let key = format!("{}fleet-{}.json", export.prefix, exported_at.format("%Y%m%dT%H%M%SZ"));
+    let response = bucket.put_object_with_content_type(&key, &body, "application/json").await.map_err(|e| e.to_string())?;
+    match response.status_code() {
+        200..=299 => Ok(key),
+        status => Err(format!("HTTP {}: {}", status, String::from_utf8_lossy(response.as_slice()).trim())),
+    }
+}
+
+/// Upload the fleet state to S3-compatible storage every TAILMON_S3_INTERVAL_SECS
+/// (no-op unless TAILMON_S3_BUCKET is set). The first export happens one interval after startup.
+pub async fn run(state: Arc<AppState>) {
+    let Some(export) = state.config().s3_export.clone() else {
+        return;
+    };
+    let bucket = match open_bucket(&export).await {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            warn!("S3 export disabled: failed to open bucket {}: {}", export.bucket, e);
+            return;
+        }
+    };
+
+    info!("Exporting the fleet to s3://{}/{} every {}", export.bucket, export.prefix, format_duration(export.interval_secs));
+    let mut interval = tokio::time::interval(Duration::from_secs(export.interval_secs));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        match upload(&state, &bucket, &export).await {
+            Ok(key) => info!("Exported the fleet to s3://{}/{}", export.bucket, key),
+            Err(e) => warn!("Failed to export the fleet to bucket {}: {}", export.bucket, e),
+        }
+    }
+}
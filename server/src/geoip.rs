@@ -0,0 +1,41 @@
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// Approximate location of a device
+#[derive(Debug, Serialize, Clone)]
+pub struct Location {
+    /// ISO 3166-1 country code (e.g. "DE")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+
+    /// English city name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+}
+
+/// Lookups against a local MaxMind GeoLite2/GeoIP2 City database
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// Open the database at `path`
+    pub fn open(path: &str) -> Result<Self, String> {
+        let reader = Reader::open_readfile(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+        Ok(GeoIp { reader })
+    }
+
+    /// Locate an address; private or unknown addresses yield `None`
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let city: geoip2::City = self.reader.lookup(ip).ok()?;
+        let location = Location {
+            country: city.country.and_then(|country| country.iso_code).map(str::to_string),
+            city: city.city
+                .and_then(|city| city.names)
+                .and_then(|names| names.get("en").copied())
+                .map(str::to_string),
+        };
+        (location.country.is_some() || location.city.is_some()).then_some(location)
+    }
+}
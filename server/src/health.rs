@@ -0,0 +1,52 @@
+use crate::{ram_pct, OFFLINE_AFTER_SECS};
+use chrono::{DateTime, Utc};
+use common::SystemInfo;
+use serde::Serialize;
+
+/// Relative weights of each pressure signal in the health score
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct HealthWeights {
+    pub cpu: f64,
+    pub ram: f64,
+    pub swap: f64,
+    pub staleness: f64,
+}
+
+impl Default for HealthWeights {
+    fn default() -> Self {
+        HealthWeights { cpu: 0.4, ram: 0.3, swap: 0.1, staleness: 0.2 }
+    }
+}
+
+/// Compute a 0–100 health score (100 = idle and fresh).
+///
+/// Each signal is a pressure percentage: CPU usage, RAM usage, swap usage and
+/// staleness (sample age relative to the offline threshold, capped at 100).
+/// The score is `100 - weighted average of the pressures`, rounded to one decimal.
+pub fn health_score(info: &SystemInfo, weights: &HealthWeights, now: DateTime<Utc>) -> f64 {
+    let swap_pct = if info.swap_total_mb == 0 {
+        0.0
+    } else {
+        info.swap_used_mb as f64 / info.swap_total_mb as f64 * 100.0
+    };
+    let staleness_pct = DateTime::parse_from_rfc3339(&info.last_seen)
+        .map(|last_seen| {
+            let age_secs = (now - last_seen.with_timezone(&Utc)).num_seconds().max(0) as f64;
+            (age_secs / OFFLINE_AFTER_SECS as f64 * 100.0).min(100.0)
+        })
+        .unwrap_or(100.0);
+
+    let signals = [
+        (weights.cpu, (info.cpu_usage as f64).clamp(0.0, 100.0)),
+        (weights.ram, ram_pct(info).clamp(0.0, 100.0)),
+        (weights.swap, swap_pct.clamp(0.0, 100.0)),
+        (weights.staleness, staleness_pct),
+    ];
+    let total_weight: f64 = signals.iter().map(|(weight, _)| weight.max(0.0)).sum();
+    if total_weight <= 0.0 {
+        return 100.0;
+    }
+    let pressure: f64 = signals.iter().map(|(weight, pct)| weight.max(0.0) * pct).sum::<f64>() / total_weight;
+
+    ((100.0 - pressure) * 10.0).round() / 10.0
+}
@@ -0,0 +1,293 @@
+use crate::ram_pct;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use common::SystemInfo;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// A single point in a device's history buffer
+#[derive(Debug, Serialize, Clone)]
+pub struct HistoryPoint {
+    /// Sample time (bucket start for downsampled points)
+    pub timestamp: DateTime<Utc>,
+
+    /// Number of raw samples folded into this point (1 for raw samples)
+    pub samples: u32,
+
+    /// Filled in by the read path for a missed sample, never stored
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub synthetic: bool,
+
+    /// The sample itself; numeric metrics are averages for downsampled points
+    #[serde(flatten)]
+    pub info: SystemInfo,
+}
+
+impl HistoryPoint {
+    /// Create a raw history point, timestamped from the sample's last_seen
+    pub fn new(info: SystemInfo) -> Self {
+        HistoryPoint { timestamp: sample_time(&info), samples: 1, synthetic: false, info }
+    }
+
+    /// Fold another point into this one, weighting averages by sample count.
+    /// Non-numeric fields are taken from whichever point is newer.
+    fn merge(&mut self, other: HistoryPoint) {
+        let (own, theirs) = (self.samples as f64, other.samples as f64);
+        let total = own + theirs;
+        let average = |a: f64, b: f64| (a * own + b * theirs) / total;
+        let average_opt = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => Some(average(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        let cpu_usage = average(self.info.cpu_usage as f64, other.info.cpu_usage as f64) as f32;
+        let ram_used_mb = average(self.info.ram_used_mb as f64, other.info.ram_used_mb as f64).round() as u64;
+        let net_rx = average_opt(self.info.net_rx_rate_bytes_per_sec, other.info.net_rx_rate_bytes_per_sec);
+        let net_tx = average_opt(self.info.net_tx_rate_bytes_per_sec, other.info.net_tx_rate_bytes_per_sec);
+
+        if other.info.last_seen >= self.info.last_seen {
+            self.info = other.info;
+        }
+        self.info.cpu_usage = cpu_usage;
+        self.info.ram_used_mb = ram_used_mb;
+        self.info.net_rx_rate_bytes_per_sec = net_rx;
+        self.info.net_tx_rate_bytes_per_sec = net_tx;
+        self.samples += other.samples;
+    }
+}
+
+/// Time of a sample: its last_seen, or now when that does not parse
+pub fn sample_time(info: &SystemInfo) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&info.last_seen)
+        .map(|ts| ts.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+/// Change filter that keeps a new point only when a metric moved by at least
+/// `delta_pct` percentage points, or at least every `min_interval_secs`
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeFilter {
+    pub delta_pct: f64,
+    pub min_interval_secs: u64,
+}
+
+impl ChangeFilter {
+    /// Whether `point` is worth storing after `last` (the newest stored point)
+    pub fn should_record(&self, last: Option<&HistoryPoint>, point: &HistoryPoint) -> bool {
+        let Some(last) = last else {
+            return true;
+        };
+        if point.timestamp - last.timestamp >= Duration::seconds(self.min_interval_secs as i64) {
+            return true;
+        }
+        let cpu_delta = (point.info.cpu_usage as f64 - last.info.cpu_usage as f64).abs();
+        let ram_delta = (ram_pct(&point.info) - ram_pct(&last.info)).abs();
+        cpu_delta >= self.delta_pct || ram_delta >= self.delta_pct
+    }
+}
+
+/// Insert a point into a time-ordered buffer after every point not newer than it,
+/// e.g. a sample that arrived after newer ones
+pub fn insert_ordered(buffer: &mut VecDeque<HistoryPoint>, point: HistoryPoint) {
+    let position = buffer.partition_point(|existing| existing.timestamp <= point.timestamp);
+    buffer.insert(position, point);
+}
+
+/// Which clock orders samples and sets their last_seen (TAILMON_TIME_SOURCE)
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// The last_seen reported by the agent
+    Agent,
+
+    /// The time the server received the sample, immune to agent clock skew
+    Server,
+}
+
+/// Replace the last_seen of samples received together at `received_at` with server time.
+/// A batch keeps its spacing: every sample moves by the offset between its newest sample
+/// and the receive time. The agent's checksum no longer matches and is dropped.
+pub fn restamp(samples: &mut [SystemInfo], received_at: DateTime<Utc>) {
+    let parse = |info: &SystemInfo| DateTime::parse_from_rfc3339(&info.last_seen).ok().map(|ts| ts.with_timezone(&Utc));
+    let skew = samples.iter().filter_map(parse).max().map_or(Duration::zero(), |newest| received_at - newest);
+    for info in samples {
+        let stamped = parse(info).map_or(received_at, |ts| ts + skew);
+        info.last_seen = stamped.to_rfc3339();
+        info.checksum = None;
+    }
+}
+
+/// Drop points that have fallen out of the retention window
+pub fn trim(buffer: &mut VecDeque<HistoryPoint>, retention_secs: u64, now: DateTime<Utc>) {
+    let cutoff = now - Duration::seconds(retention_secs as i64);
+    while buffer.front().is_some_and(|point| point.timestamp < cutoff) {
+        buffer.pop_front();
+    }
+}
+
+/// Aggregates old history points into fixed-width buckets of averages,
+/// bounding memory for long retention while keeping long-term trends
+#[derive(Debug, Clone, Copy)]
+pub struct Downsampler {
+    /// Points older than this are folded into buckets
+    pub after_secs: u64,
+
+    /// Bucket width in seconds
+    pub bucket_secs: u64,
+}
+
+impl Downsampler {
+    /// Downsample every point older than `after_secs` in an ordered buffer
+    pub fn run(&self, buffer: &mut VecDeque<HistoryPoint>, now: DateTime<Utc>) {
+        let cutoff = now - Duration::seconds(self.after_secs as i64);
+        let old_len = buffer.iter().take_while(|point| point.timestamp < cutoff).count();
+        if old_len == 0 {
+            return;
+        }
+
+        let mut reduced: VecDeque<HistoryPoint> = VecDeque::with_capacity(old_len);
+        for mut point in buffer.drain(..old_len) {
+            let bucket_start = self.bucket_start(point.timestamp);
+            match reduced.back_mut() {
+                Some(last) if last.timestamp == bucket_start => last.merge(point),
+                _ => {
+                    point.timestamp = bucket_start;
+                    reduced.push_back(point);
+                }
+            }
+        }
+
+        // Re-attach the untouched recent points after the reduced ones
+        reduced.append(buffer);
+        *buffer = reduced;
+    }
+
+    /// Start of the bucket containing `timestamp`
+    fn bucket_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let bucket = self.bucket_secs as i64;
+        let secs = timestamp.timestamp().div_euclid(bucket) * bucket;
+        Utc.timestamp_opt(secs, 0).single().unwrap_or(timestamp)
+    }
+}
+
+/// How missed samples are filled in a history response (TAILMON_GAP_FILL_MODE)
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GapFillMode {
+    /// Linear interpolation of CPU, RAM and network rates between the neighbours
+    Linear,
+
+    /// Repeat the point before the gap
+    Previous,
+}
+
+/// Inserts synthetic points into gaps of a history response, for gaps up to
+/// `max_gap_secs` (TAILMON_MAX_GAP_FILL); longer outages stay visible as gaps
+#[derive(Debug, Clone, Copy)]
+pub struct GapFiller {
+    pub max_gap_secs: u64,
+    pub mode: GapFillMode,
+}
+
+impl GapFiller {
+    /// Fill the gaps of ordered points at the usual spacing between them. Synthetic points
+    /// are made as the result is read, so a long window is not copied with its fillers.
+    pub fn fill(self, points: Vec<HistoryPoint>) -> impl Iterator<Item = HistoryPoint> {
+        let step = typical_spacing(&points);
+        let max_gap = Duration::seconds(self.max_gap_secs as i64);
+        let mut points = points.into_iter().peekable();
+
+        // Point before the gap being filled, and the time of the next synthetic point
+        let mut gap: Option<(HistoryPoint, DateTime<Utc>)> = None;
+        std::iter::from_fn(move || {
+            if let (Some((before, timestamp)), Some(step), Some(next)) = (gap.as_mut(), step, points.peek()) {
+                if next.timestamp - *timestamp >= step / 2 {
+                    let point = self.synthesize(before, next, *timestamp);
+                    *timestamp += step;
+                    return Some(point);
+                }
+            }
+            gap = None;
+
+            // Anything below one and a half steps is jitter, not a missed sample
+            let point = points.next()?;
+            if let (Some(step), Some(next)) = (step, points.peek()) {
+                let spacing = next.timestamp - point.timestamp;
+                if spacing <= max_gap && spacing * 2 >= step * 3 {
+                    gap = Some((point.clone(), point.timestamp + step));
+                }
+            }
+            Some(point)
+        })
+    }
+
+    /// Point at `timestamp` between `before` and `after`
+    fn synthesize(&self, before: &HistoryPoint, after: &HistoryPoint, timestamp: DateTime<Utc>) -> HistoryPoint {
+        let mut point = HistoryPoint { timestamp, samples: 0, synthetic: true, info: before.info.clone() };
+        if self.mode == GapFillMode::Linear {
+            let span = (after.timestamp - before.timestamp).num_milliseconds() as f64;
+            let t = (timestamp - before.timestamp).num_milliseconds() as f64 / span;
+            let lerp = |a: f64, b: f64| a + (b - a) * t;
+            let lerp_opt = |a: Option<f64>, b: Option<f64>| a.zip(b).map(|(a, b)| lerp(a, b)).or(a);
+
+            point.info.cpu_usage = lerp(before.info.cpu_usage as f64, after.info.cpu_usage as f64) as f32;
+            point.info.ram_used_mb = lerp(before.info.ram_used_mb as f64, after.info.ram_used_mb as f64).round() as u64;
+            point.info.net_rx_rate_bytes_per_sec = lerp_opt(before.info.net_rx_rate_bytes_per_sec, after.info.net_rx_rate_bytes_per_sec);
+            point.info.net_tx_rate_bytes_per_sec = lerp_opt(before.info.net_tx_rate_bytes_per_sec, after.info.net_tx_rate_bytes_per_sec);
+        }
+        point.info.last_seen = timestamp.to_rfc3339();
+        point
+    }
+}
+
+/// Median spacing between consecutive points (None with fewer than two points)
+fn typical_spacing(points: &[HistoryPoint]) -> Option<Duration> {
+    let mut spacings: Vec<Duration> = points.windows(2)
+        .map(|pair| pair[1].timestamp - pair[0].timestamp)
+        .filter(|spacing| *spacing > Duration::zero())
+        .collect();
+    if spacings.is_empty() {
+        return None;
+    }
+    spacings.sort();
+    Some(spacings[spacings.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(secs: i64, cpu_usage: f32) -> HistoryPoint {
+        let mut info = common::test_util::sample();
+        info.last_seen = Utc.timestamp_opt(1_767_225_600 + secs, 0).unwrap().to_rfc3339();
+        info.cpu_usage = cpu_usage;
+        HistoryPoint::new(info)
+    }
+
+    fn offsets_and_cpu(points: impl Iterator<Item = HistoryPoint>) -> Vec<(i64, f32, bool)> {
+        points.map(|point| (point.timestamp.timestamp() - 1_767_225_600, point.info.cpu_usage, point.synthetic)).collect()
+    }
+
+    #[test]
+    fn gaps_are_filled_up_to_the_max_gap() {
+        let points = vec![point(0, 10.0), point(5, 10.0), point(10, 10.0), point(25, 40.0), point(30, 40.0), point(90, 40.0)];
+        let filler = GapFiller { max_gap_secs: 30, mode: GapFillMode::Linear };
+        assert_eq!(offsets_and_cpu(filler.fill(points.clone())), vec![
+            (0, 10.0, false), (5, 10.0, false), (10, 10.0, false),
+            (15, 20.0, true), (20, 30.0, true),
+            (25, 40.0, false), (30, 40.0, false), (90, 40.0, false),
+        ]);
+
+        let filler = GapFiller { max_gap_secs: 30, mode: GapFillMode::Previous };
+        let filled = offsets_and_cpu(filler.fill(points));
+        assert_eq!(filled[3..5], [(15, 10.0, true), (20, 10.0, true)]);
+    }
+
+    #[test]
+    fn jitter_and_short_windows_are_left_alone() {
+        let filler = GapFiller { max_gap_secs: 60, mode: GapFillMode::Linear };
+        assert!(filler.fill(Vec::new()).next().is_none());
+        assert_eq!(offsets_and_cpu(filler.fill(vec![point(0, 1.0)])), vec![(0, 1.0, false)]);
+        let jittery = vec![point(0, 1.0), point(5, 1.0), point(12, 1.0), point(17, 1.0)];
+        assert_eq!(filler.fill(jittery).filter(|point| point.synthetic).count(), 0);
+    }
+}
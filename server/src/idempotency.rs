@@ -0,0 +1,38 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// Idempotency keys of recently received samples (TAILMON_IDEMPOTENCY_TTL_SECS), so a sample
+/// an agent retries after losing the response is stored and counted only once
+#[derive(Default)]
+pub struct IdempotencyKeys {
+    /// When each (device_id, key) was first received
+    seen: DashMap<(String, String), Instant>,
+}
+
+impl IdempotencyKeys {
+    /// Record a device's key, returning `false` when it was already received within `ttl`
+    pub fn claim(&self, device_id: &str, key: &str, ttl: Duration) -> bool {
+        match self.seen.entry((device_id.to_string(), key.to_string())) {
+            Entry::Occupied(entry) if entry.get().elapsed() < ttl => false,
+            Entry::Occupied(mut entry) => {
+                entry.insert(Instant::now());
+                true
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Forget a claimed key whose sample was not queued, so the agent's retry is stored
+    pub fn release(&self, device_id: &str, key: &str) {
+        self.seen.remove(&(device_id.to_string(), key.to_string()));
+    }
+
+    /// Drop keys received more than `ttl` ago
+    pub fn purge(&self, ttl: Duration) {
+        self.seen.retain(|_, received| received.elapsed() < ttl);
+    }
+}
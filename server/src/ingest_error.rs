@@ -0,0 +1,87 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json as JsonResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+
+/// Structured error body of the ingest endpoints, e.g.
+/// `{"error":"invalid json","detail":"...","line":1,"column":42}`,
+/// so authors of third-party agents can locate the problem in their payload
+#[derive(Debug, Serialize)]
+pub struct IngestError {
+    #[serde(skip)]
+    status: StatusCode,
+
+    /// Short category: "invalid json", "invalid sample", "invalid protobuf" or "unsupported media type"
+    error: &'static str,
+    detail: String,
+
+    /// Parse location (1-based); inside the offending sample for batches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+
+    /// Field the value was rejected at, e.g. "cpu_usage" or "ui_meta.icon"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+
+    /// Index of the offending sample in a batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample: Option<usize>,
+}
+
+impl IngestError {
+    fn new(status: StatusCode, error: &'static str, detail: String) -> Self {
+        IngestError { status, error, detail, line: None, column: None, path: None, sample: None }
+    }
+
+    /// 415 for a body in a format the endpoint does not accept
+    pub fn unsupported_media_type(detail: &str) -> Self {
+        IngestError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported media type", detail.to_string())
+    }
+
+    /// 400 for a protobuf body that cannot be decoded
+    #[cfg(feature = "protobuf")]
+    pub fn invalid_protobuf(detail: String) -> Self {
+        IngestError::new(StatusCode::BAD_REQUEST, "invalid protobuf", detail)
+    }
+
+    /// Attribute the error to the sample at `index` of a batch
+    pub fn in_sample(mut self, index: usize) -> Self {
+        self.sample = Some(index);
+        self
+    }
+}
+
+impl IntoResponse for IngestError {
+    fn into_response(self) -> Response {
+        (self.status, JsonResponse(self)).into_response()
+    }
+}
+
+/// Deserialize a JSON ingest body. Malformed JSON is a 400 ("invalid json"),
+/// well-formed JSON that does not fit `T` a 422 ("invalid sample").
+pub fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, IngestError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        json_error(e.into_inner(), (path != "." && path != "?").then_some(path))
+    })?;
+    // Trailing characters after the value are a syntax error, as with serde_json::from_slice
+    deserializer.end().map_err(|e| json_error(e, None))?;
+    Ok(value)
+}
+
+fn json_error(e: serde_json::Error, path: Option<String>) -> IngestError {
+    let (status, error) = match e.classify() {
+        Category::Data => (StatusCode::UNPROCESSABLE_ENTITY, "invalid sample"),
+        Category::Syntax | Category::Eof | Category::Io => (StatusCode::BAD_REQUEST, "invalid json"),
+    };
+    IngestError {
+        line: (e.line() > 0).then_some(e.line()),
+        column: (e.line() > 0).then_some(e.column()),
+        path,
+        ..IngestError::new(status, error, e.to_string())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::record::DeviceRecord;
+use crate::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// A device as an asset: the fields a CMDB reconciles on, which only change when the
+/// machine does. Every key is always present so records have a fixed shape.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct InventoryRecord {
+    pub device_id: String,
+
+    /// Machine UUID reported by the agent (null for agents without hardware info)
+    pub uuid: Option<String>,
+    pub os_info: String,
+    pub arch: Option<String>,
+    pub cpu_brand: Option<String>,
+    pub cpu_count: Option<u32>,
+    pub ram_total_mb: u64,
+    pub first_seen: DateTime<Utc>,
+
+    /// The agent's TAILMON_META labels
+    pub labels: BTreeMap<String, String>,
+}
+
+impl InventoryRecord {
+    pub fn from_record(record: &DeviceRecord) -> Self {
+        let hardware = record.info.hardware.as_ref();
+        InventoryRecord {
+            device_id: record.info.device_id.clone(),
+            uuid: hardware.and_then(|hardware| hardware.uuid.clone()),
+            os_info: record.info.os_info.clone(),
+            arch: hardware.map(|hardware| hardware.arch.clone()),
+            cpu_brand: hardware.and_then(|hardware| hardware.cpu_brand.clone()),
+            cpu_count: hardware.and_then(|hardware| hardware.cpu_count),
+            ram_total_mb: record.info.ram_total_mb,
+            first_seen: record.first_seen,
+            labels: record.info.ui_meta.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+        }
+    }
+
+    /// Hash of the serialized record; labels are ordered, so equal records hash equally
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self).unwrap_or_default().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// When each device's inventory record last changed. Kept in memory, so after a restart
+/// every device counts as changed at its first report.
+#[derive(Default)]
+pub struct Inventory {
+    /// Fingerprint of each device's record and when it took that value
+    changed: DashMap<String, (u64, DateTime<Utc>)>,
+
+    /// Devices that left the inventory by being renamed, and when
+    removed: DashMap<String, DateTime<Utc>>,
+}
+
+impl Inventory {
+    /// Note a device's current record, returning when it last changed
+    pub fn observe(&self, record: &InventoryRecord) -> DateTime<Utc> {
+        let fingerprint = record.fingerprint();
+        self.removed.remove(&record.device_id);
+        let mut entry = self.changed.entry(record.device_id.clone()).or_insert((fingerprint, Utc::now()));
+        if entry.0 != fingerprint {
+            *entry = (fingerprint, Utc::now());
+        }
+        entry.1
+    }
+
+    /// Note that a device left the inventory
+    pub fn remove(&self, device_id: &str) {
+        if self.changed.remove(device_id).is_some() {
+            self.removed.insert(device_id.to_string(), Utc::now());
+        }
+    }
+}
+
+/// Query parameters for the inventory endpoint
+#[derive(Debug, Deserialize)]
+pub struct InventoryQuery {
+    /// Only devices changed or removed after this RFC 3339 time, for incremental syncs
+    since: Option<String>,
+}
+
+/// One device of the inventory with its last change
+#[derive(Debug, Serialize)]
+pub struct InventoryEntry {
+    #[serde(flatten)]
+    record: InventoryRecord,
+    changed_at: DateTime<Utc>,
+}
+
+/// A device renamed away since the requested time
+#[derive(Debug, Serialize)]
+pub struct RemovedEntry {
+    device_id: String,
+    removed_at: DateTime<Utc>,
+}
+
+/// Response of the inventory endpoint
+#[derive(Debug, Serialize)]
+pub struct InventoryResponse {
+    /// Latest change of the whole inventory (null while it is empty); also sent as Last-Modified
+    last_modified: Option<DateTime<Utc>>,
+
+    /// Sorted by device_id
+    devices: Vec<InventoryEntry>,
+    removed: Vec<RemovedEntry>,
+}
+
+/// `Thu, 15 Oct 2026 04:35:05 GMT`, the date format of Last-Modified
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Handler function to get the device inventory for asset management. Answers 304 when
+/// If-Modified-Since is not older than the latest change; `?since=` limits the response
+/// to devices changed or removed after that time.
+pub async fn get_inventory(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<InventoryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let since = match query.since.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(since)) => Some(since.with_timezone(&Utc)),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, format!("Invalid 'since' (expected RFC 3339): {}", e)).into_response(),
+        None => None,
+    };
+
+    let mut devices: Vec<InventoryEntry> = state.store.all().iter()
+        .map(|record| {
+            let record = InventoryRecord::from_record(record);
+            let changed_at = state.inventory.observe(&record);
+            InventoryEntry { record, changed_at }
+        })
+        .collect();
+    devices.sort_by(|a, b| a.record.device_id.cmp(&b.record.device_id));
+    let mut removed: Vec<RemovedEntry> = state.inventory.removed.iter()
+        .map(|entry| RemovedEntry { device_id: entry.key().clone(), removed_at: *entry.value() })
+        .collect();
+    removed.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+
+    let last_modified = devices.iter().map(|entry| entry.changed_at)
+        .chain(removed.iter().map(|entry| entry.removed_at))
+        .max();
+    let Some(last_modified) = last_modified else {
+        return JsonResponse(InventoryResponse { last_modified: None, devices, removed }).into_response();
+    };
+
+    // HTTP dates have whole seconds, so the change is compared at that resolution
+    let not_modified = headers.get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp());
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::LAST_MODIFIED, http_date(last_modified))]).into_response();
+    }
+
+    if let Some(since) = since {
+        devices.retain(|entry| entry.changed_at > since);
+        removed.retain(|entry| entry.removed_at > since);
+    }
+    (
+        [(header::LAST_MODIFIED, http_date(last_modified))],
+        JsonResponse(InventoryResponse { last_modified: Some(last_modified), devices, removed }),
+    ).into_response()
+}
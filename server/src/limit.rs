@@ -0,0 +1,74 @@
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Bounds the number of ingest requests handled at once (TAILMON_MAX_CONCURRENT)
+/// and counts the requests currently in flight
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    /// `None` when unlimited
+    permits: Option<Semaphore>,
+    in_flight: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max_concurrent: Option<usize>) -> Self {
+        ConcurrencyLimit {
+            permits: max_concurrent.map(Semaphore::new),
+            in_flight: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Ingest requests currently being handled
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Requests rejected because the limit was reached
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in-flight count when the request finishes (or is dropped)
+struct InFlight<'a>(&'a AtomicU64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware answering 503 with Retry-After once TAILMON_MAX_CONCURRENT ingest requests are in flight
+pub async fn limit_ingest(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let limit = &state.ingest_limit;
+    let _permit = match &limit.permits {
+        Some(permits) => match permits.try_acquire() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                limit.rejected.fetch_add(1, Ordering::Relaxed);
+                warn!("Concurrency limit reached, rejected {} {}", request.method(), request.uri().path());
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, "1")],
+                    "Too many concurrent requests, retry later",
+                ).into_response();
+            }
+        },
+        None => None,
+    };
+
+    limit.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(&limit.in_flight);
+    next.run(request).await
+}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::sync::OnceLock;
+use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
+
+/// Zone used to render timestamps for humans (TAILMON_TZ); stored and API values stay UTC
+static TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+/// Set the display zone once the configuration is loaded
+pub fn set_timezone(timezone: Tz) {
+    let _ = TIMEZONE.set(timezone);
+}
+
+fn timezone() -> Tz {
+    TIMEZONE.get().copied().unwrap_or(Tz::UTC)
+}
+
+/// Render a UTC timestamp in the display zone
+pub fn render(timestamp: DateTime<Utc>) -> String {
+    timestamp.with_timezone(&timezone()).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Render an RFC 3339 timestamp (e.g. a device's `last_seen`) in the display zone,
+/// leaving unparseable values as they are
+pub fn render_rfc3339(timestamp: &str) -> String {
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(timestamp) => render(timestamp.with_timezone(&Utc)),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+/// Log line timer writing the current time in the display zone
+pub struct LogTimer;
+
+impl FormatTime for LogTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        write!(w, "{}", render(Utc::now()))
+    }
+}
@@ -0,0 +1,143 @@
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::response::Json as JsonResponse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Log lines kept in memory; the oldest are dropped first
+const MAX_LOG_LINES: usize = 1000;
+
+/// Lines returned when the request sets no limit
+const DEFAULT_LIMIT: usize = 200;
+
+/// Replacement for redacted values
+const REDACTED: &str = "[redacted]";
+
+/// One captured log line
+#[derive(Debug, Serialize, Clone)]
+pub struct LogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub message: String,
+}
+
+/// Ring buffer of the server's recent log lines, filled by `LogLayer`
+pub struct LogBuffer {
+    lines: Mutex<VecDeque<LogLine>>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        LogBuffer { lines: Mutex::new(VecDeque::new()) }
+    }
+
+    fn push(&self, line: LogLine) {
+        let mut lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if lines.len() == MAX_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The most recent `limit` lines, oldest first like a log file
+    pub fn recent(&self, limit: usize) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        lines.iter().skip(lines.len().saturating_sub(limit)).cloned().collect()
+    }
+}
+
+/// Tracing layer copying every event that passes the log filter into a `LogBuffer`
+pub struct LogLayer {
+    buffer: Arc<LogBuffer>,
+}
+
+impl LogLayer {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        LogLayer { buffer }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        self.buffer.push(LogLine {
+            timestamp: Utc::now(),
+            level: event.metadata().level().to_string(),
+            message: message.0,
+        });
+    }
+}
+
+/// Formats an event's message followed by its other fields as `name=value`
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+}
+
+/// Remove secrets from a log line: the configured keys and webhook URL, bearer tokens,
+/// and credentials embedded in URLs
+fn redact(message: &str, secrets: &[&str]) -> String {
+    let mut redacted = message.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        redacted = redacted.replace(secret, REDACTED);
+    }
+    redacted = redact_after(&redacted, "Bearer ", |rest| rest.find(char::is_whitespace).unwrap_or(rest.len()));
+    redact_after(&redacted, "://", |rest| {
+        let authority = rest.find(['/', ' ']).unwrap_or(rest.len());
+        rest[..authority].rfind('@').unwrap_or(0)
+    })
+}
+
+/// Replace the `secret_len(rest)` bytes following each `marker` with the redaction placeholder
+fn redact_after(message: &str, marker: &str, secret_len: impl Fn(&str) -> usize) -> String {
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(position) = rest.find(marker) {
+        let (head, tail) = rest.split_at(position + marker.len());
+        redacted.push_str(head);
+        let len = secret_len(tail);
+        if len > 0 {
+            redacted.push_str(REDACTED);
+        }
+        rest = &tail[len..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Query parameters for the logs endpoint
+#[derive(Debug, Deserialize)]
+pub struct LogsQuery {
+    /// Number of lines to return (default 200)
+    limit: Option<usize>,
+}
+
+/// Handler function to get the server's recent log lines, oldest first, with secrets redacted
+pub async fn get_logs(State(state): State<Arc<AppState>>, Query(query): Query<LogsQuery>) -> JsonResponse<Vec<LogLine>> {
+    let config = state.config();
+    let s3_secret_key = config.s3_export.as_ref().and_then(|export| export.secret_key.as_deref());
+    let secrets: Vec<&str> = [&config.api_key, &config.read_api_key, &config.upstream_api_key, &config.alert_webhook_url]
+        .into_iter()
+        .filter_map(|secret| secret.as_deref())
+        .chain(s3_secret_key)
+        .chain(config.hmac_secrets.iter().chain(&config.upstream_hmac).map(|key| key.secret.as_str()))
+        .collect();
+    let lines = state.logs.recent(query.limit.unwrap_or(DEFAULT_LIMIT)).into_iter()
+        .map(|line| LogLine { message: redact(&line.message, &secrets), ..line })
+        .collect();
+    JsonResponse(lines)
+}
@@ -1,15 +1,99 @@
+mod activity;
+mod admin;
+mod alerts;
+mod annotations;
+mod auth;
+mod changes;
+mod classes;
+mod commands;
+mod config;
+mod conflict;
+#[cfg(feature = "s3-export")]
+mod export;
+mod geoip;
+mod health;
+mod history;
+mod idempotency;
+mod ingest_error;
+mod inventory;
+mod limit;
+mod localtime;
+mod logs;
+mod metrics;
+mod offline;
+mod payload;
+mod percentiles;
+#[cfg(feature = "protobuf")]
+mod proto;
+mod query;
+mod rdns;
+mod record;
+mod report;
+mod retain;
+mod runstats;
+mod schema;
+mod self_monitor;
+mod series;
+mod signing;
+mod snapshot;
+mod store;
+mod tls;
+mod transform;
+mod upstream;
+mod webhook;
+
 use axum::{
-    extract::{Json, State},
-    http::{StatusCode, Uri},
-    response::{Html, Json as JsonResponse, Response, IntoResponse},
-    routing::{get, post},
+    async_trait,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, FromRequestParts, Json, OriginalUri, Path, Query, State},
+    http::request::Parts,
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{Html, Json as JsonResponse, Redirect, Response, IntoResponse},
+    middleware,
+    routing::{get, patch, post, put},
     Router,
 };
-use common::SystemInfo;
+use chrono::{DateTime, Utc};
+use common::humanize::{format_duration, format_mb};
+use common::{IngestResponse, SystemInfo, SystemInfoPatch, MAX_BATCH_SIZE, MAX_CAPABILITIES, MAX_CAPABILITY_LEN, MAX_IDEMPOTENCY_KEY_LEN, MAX_PROCESS_NAME_LEN, MAX_RELAY_ID_LEN, MAX_UI_META_ENTRIES, MAX_UI_META_LEN, MAX_VIA_HOPS, MAX_WATCHED_PROCESSES};
+use activity::{Activity, ActivityKind};
+use alerts::Alerts;
+use annotations::Annotation;
+use changes::Changes;
+use config::Config;
+use conflict::Conflicts;
 use dashmap::DashMap;
-use rust_embed::RustEmbed;
-use std::sync::Arc;
-use tracing::info;
+use geoip::GeoIp;
+use history::{ChangeFilter, Downsampler, GapFiller, HistoryPoint, TimeSource};
+use ingest_error::IngestError;
+use inventory::InventoryRecord;
+use logs::{LogBuffer, LogLayer};
+use rdns::ReverseDns;
+use record::{DeviceRecord, RawPayload};
+use runstats::RunStats;
+use rust_embed::{EmbeddedFile, RustEmbed};
+use series::{DeviceSeries, Metric, SeriesPoint};
+use store::{InMemoryStore, MetricsStore};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use tls::ClientIdentity;
+use transform::Transform;
+use upstream::Upstream;
+use query::Expr;
+use tokio::sync::mpsc;
+use tower_http::catch_panic::CatchPanicLayer;
+use tracing::{error, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 // use tower_http::services::ServeDir; // removed unused import
 
 /// Embed static files into the binary
@@ -17,51 +101,1276 @@ use tracing::info;
 #[folder = "static/"]
 struct Assets;
 
+/// A device is considered stale after this many seconds without a report
+const STALE_AFTER_SECS: i64 = 30;
+
+/// A device is considered offline after this many seconds without a report
+const OFFLINE_AFTER_SECS: i64 = 120;
+
+/// Agents whose collection takes at least this long are logged as slow
+const SLOW_COLLECT_MS: u64 = 1000;
+
 /// Application state to store metrics from all devices
 struct AppState {
-    metrics: DashMap<String, SystemInfo>,
+    /// Swapped on SIGHUP when reloadable settings change
+    config: RwLock<Arc<Config>>,
+    
+    /// Current record and retained history of every device
+    store: Box<dyn MetricsStore>,
+    
+    /// Per-metric series with their own retention and resolution (TAILMON_SERIES)
+    series: DashMap<String, DeviceSeries>,
+    
+    /// CPU usage quantile estimators over the retention window
+    percentiles: DashMap<String, percentiles::RollingQuantiles>,
+    
+    /// First report of each device_id, kept across updates (and restarts with a snapshot)
+    first_seen: DashMap<String, DateTime<Utc>>,
+    
+    /// Original body of each device's last sample (see TAILMON_RAW_MAX_BYTES)
+    raw: DashMap<String, RawPayload>,
+    geoip: Option<GeoIp>,
+    rdns: Option<ReverseDns>,
+    transform: Option<Transform>,
+    alerts: Alerts,
+    
+    /// Log of OS and RAM changes between a device's samples
+    changes: Changes,
+    
+    /// Detection of device_ids shared by several machines
+    conflicts: Conflicts,
+    
+    /// Feed of new devices, changes, alerts and status transitions
+    activity: Activity,
+    
+    /// Reporting intervals pushed to agents, by device_id
+    intervals: DashMap<String, u64>,
+    
+    /// Commands from the dashboard waiting for their device's next report
+    commands: commands::CommandQueue,
+    
+    /// Open command connections
+    command_connections: AtomicU64,
+    
+    /// When each device's inventory record last changed
+    inventory: inventory::Inventory,
+    
+    /// Idempotency keys of recently received samples
+    idempotency: idempotency::IdempotencyKeys,
+    
+    /// Timeline notes (e.g. deploys) included in history responses
+    annotations: annotations::Annotations,
+    
+    ingest_tx: mpsc::Sender<IngestItem>,
+    
+    /// Samples rejected with 503 because the ingest queue was full
+    ingest_dropped: AtomicU64,
+    
+    /// Verified signed ingest requests by key id, to tell when a rotated-out secret is unused
+    signed_requests: DashMap<String, u64>,
+    
+    /// Content-Length of ingest requests
+    ingest_bytes: payload::SizeHistogram,
+    
+    /// In-flight ingest requests and their optional limit
+    ingest_limit: limit::ConcurrencyLimit,
+    
+    /// Relay to an upstream server (TAILMON_UPSTREAM_URL)
+    upstream: Option<Upstream>,
+    
+    /// Recent log lines served at /api/logs
+    logs: Arc<LogBuffer>,
+    
+    /// Totals of this run, logged on graceful shutdown
+    run_stats: RunStats,
 }
 
-/// Handler function to receive metrics from agents
-async fn receive_metrics(
-    State(state): State<Arc<AppState>>,
-    Json(system_info): Json<SystemInfo>,
-) -> (StatusCode, &'static str) {
+impl AppState {
+    /// The current configuration
+    fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// Connection details of the client submitting a request
+struct Peer {
+    /// TLS client certificate identity (only when serving TLS)
+    identity: Option<ClientIdentity>,
+    
+    /// Remote socket address of the connection
+    addr: Option<SocketAddr>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Peer {
+    type Rejection = Infallible;
+    
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Peer {
+            identity: parts.extensions.get::<ClientIdentity>().cloned(),
+            addr: parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr),
+        })
+    }
+}
+
+/// Reporting status of a device derived from its last_seen timestamp
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum DeviceStatus {
+    Online,
+    Stale,
+    Offline,
+}
+
+/// Compute the status of a device from its last_seen timestamp.
+/// Unparseable timestamps are treated as offline.
+fn device_status(last_seen: &str, now: DateTime<Utc>) -> DeviceStatus {
+    let Ok(last_seen) = DateTime::parse_from_rfc3339(last_seen) else {
+        return DeviceStatus::Offline;
+    };
+    let age_secs = (now - last_seen.with_timezone(&Utc)).num_seconds();
+    if age_secs >= OFFLINE_AFTER_SECS {
+        DeviceStatus::Offline
+    } else if age_secs >= STALE_AFTER_SECS {
+        DeviceStatus::Stale
+    } else {
+        DeviceStatus::Online
+    }
+}
+
+/// Validate an incoming payload before it is stored
+fn validate_metrics(system_info: &SystemInfo) -> Result<(), String> {
+    if let Some((field, value)) = system_info.non_finite_fields().first() {
+        return Err(format!("{} must be a finite number, got {}", field, value));
+    }
+    if system_info.ui_meta.len() > MAX_UI_META_ENTRIES {
+        return Err(format!("ui_meta has more than {} entries", MAX_UI_META_ENTRIES));
+    }
+    if let Some((key, _)) = system_info.ui_meta.iter()
+        .find(|(key, value)| key.len() > MAX_UI_META_LEN || value.len() > MAX_UI_META_LEN) {
+        return Err(format!("ui_meta entry '{}' exceeds {} bytes", key, MAX_UI_META_LEN));
+    }
+    if system_info.capabilities.len() > MAX_CAPABILITIES {
+        return Err(format!("capabilities has more than {} entries", MAX_CAPABILITIES));
+    }
+    if let Some(capability) = system_info.capabilities.iter().find(|capability| capability.len() > MAX_CAPABILITY_LEN) {
+        return Err(format!("capability '{}' exceeds {} bytes", capability, MAX_CAPABILITY_LEN));
+    }
+    if system_info.via.len() > MAX_VIA_HOPS {
+        return Err(format!("via has more than {} relays, possible relay loop", MAX_VIA_HOPS));
+    }
+    if let Some(relay) = system_info.via.iter().find(|relay| relay.len() > MAX_RELAY_ID_LEN) {
+        return Err(format!("via entry '{}' exceeds {} bytes", relay, MAX_RELAY_ID_LEN));
+    }
+    if system_info.watched_processes.len() > MAX_WATCHED_PROCESSES {
+        return Err(format!("watched_processes has more than {} entries", MAX_WATCHED_PROCESSES));
+    }
+    if let Some(name) = system_info.watched_processes.keys().find(|name| name.len() > MAX_PROCESS_NAME_LEN) {
+        return Err(format!("watched process '{}' exceeds {} bytes", name, MAX_PROCESS_NAME_LEN));
+    }
+    if system_info.idempotency_key.as_ref().is_some_and(|key| key.len() > MAX_IDEMPOTENCY_KEY_LEN) {
+        return Err(format!("idempotency_key exceeds {} bytes", MAX_IDEMPOTENCY_KEY_LEN));
+    }
+    Ok(())
+}
+
+/// Check that the TLS client (when mTLS is required) may report for `device_id`
+fn authorize_device(state: &AppState, peer: &Peer, device_id: &str) -> Result<(), (StatusCode, String)> {
+    // With mTLS the certificate CN is the device identity
+    if !state.config().require_client_cert {
+        return Ok(());
+    }
+    match peer.identity.as_ref().and_then(|ClientIdentity(common_name)| common_name.as_deref()) {
+        None => Err((StatusCode::UNAUTHORIZED, "Client certificate required".to_string())),
+        Some(common_name) if common_name != device_id => {
+            warn!("Rejected metrics for device {} from client certificate CN {}", device_id, common_name);
+            Err((StatusCode::FORBIDDEN, "Client certificate does not match device_id".to_string()))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Contents of a queued ingest item
+enum Sample {
+    /// A complete sample replacing the stored record
+    Full(SystemInfo),
+    
+    /// Fields merged into the device's stored record when the item is applied,
+    /// so consecutive patches never overwrite each other
+    Patch { device_id: String, patch: SystemInfoPatch },
+}
+
+/// A validated sample waiting in the ingest queue
+struct IngestItem {
+    sample: Sample,
+    
+    /// Source address of the submitting connection
+    addr: Option<SocketAddr>,
+    
+    /// The sample as sent, unless it exceeded TAILMON_RAW_MAX_BYTES
+    raw: Option<RawPayload>,
+}
+
+/// Authorize and validate one sample before it is queued
+fn check_sample(state: &AppState, peer: &Peer, system_info: &SystemInfo) -> Result<(), (StatusCode, String)> {
+    authorize_device(state, peer, &system_info.device_id)?;
+    check_contents(state.config().verify_checksum, system_info)
+}
+
+/// Validate a sample's fields and, with TAILMON_VERIFY_CHECKSUM, its checksum
+fn check_contents(verify_checksum: bool, system_info: &SystemInfo) -> Result<(), (StatusCode, String)> {
+    if let Err(reason) = validate_metrics(system_info) {
+        warn!("Rejected metrics from device {}: {}", system_info.device_id, reason);
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, reason));
+    }
+    
+    // Samples without a checksum (older agents, patches) are accepted
+    if verify_checksum && system_info.verify_checksum() == Some(false) {
+        warn!("Rejected metrics from device {}: checksum mismatch", system_info.device_id);
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "checksum does not match the sample".to_string()));
+    }
+    Ok(())
+}
+
+/// Claim a sample's idempotency key, returning `false` for a sample already received.
+/// Samples without a key, and every sample with TAILMON_IDEMPOTENCY_TTL_SECS=0, are new.
+fn claim_key(state: &AppState, system_info: &SystemInfo) -> bool {
+    let ttl_secs = state.config().idempotency_ttl_secs;
+    match system_info.idempotency_key.as_deref() {
+        Some(key) if ttl_secs > 0 => {
+            let new = state.idempotency.claim(&system_info.device_id, key, std::time::Duration::from_secs(ttl_secs));
+            if !new {
+                info!("Skipping duplicate sample {} of device {}", key, system_info.device_id);
+            }
+            new
+        }
+        _ => true,
+    }
+}
+
+/// Release the idempotency key of a sample that could not be queued
+fn release_key(state: &AppState, system_info: &SystemInfo) {
+    if let Some(key) = system_info.idempotency_key.as_deref() {
+        state.idempotency.release(&system_info.device_id, key);
+    }
+}
+
+/// Forward a sample upstream with this server appended to its relay chain.
+/// Samples that already passed through this server or have a full chain are not forwarded.
+fn relay(upstream: &Upstream, relay_id: &str, system_info: &SystemInfo) {
+    if system_info.via.iter().any(|relay| relay == relay_id) {
+        warn!("Not forwarding sample of device {}: relay loop via {}", system_info.device_id, system_info.via.join(" → "));
+        return;
+    }
+    if system_info.via.len() >= MAX_VIA_HOPS {
+        warn!("Not forwarding sample of device {}: relay chain exceeds {} hops", system_info.device_id, MAX_VIA_HOPS);
+        return;
+    }
+    let mut relayed = system_info.clone();
+    relayed.via.push(relay_id.to_string());
+    upstream.forward(relayed);
+}
+
+/// Add a sample's CPU usage to its device's quantile estimator
+fn record_percentiles(state: &AppState, system_info: &SystemInfo) {
+    let window_secs = state.config().history_retention_secs;
+    state.percentiles.entry(system_info.device_id.clone()).or_default()
+        .record(system_info.cpu_usage as f64, history::sample_time(system_info), window_secs, Utc::now());
+}
+
+/// Store one validated sample (runs on the ingest worker)
+fn apply_sample(state: &Arc<AppState>, item: IngestItem) {
+    let IngestItem { sample, addr, raw } = item;
+    let mut system_info = match sample {
+        Sample::Full(system_info) => system_info,
+        Sample::Patch { device_id, patch } => {
+            let Some(existing) = state.store.get(&device_id).map(|record| record.info) else {
+                warn!("Dropping patch for device {} which has no stored record", device_id);
+                return;
+            };
+            patch.apply(existing, Utc::now().to_rfc3339())
+        }
+    };
+    
+    // Only a newer sample replaces the current record. An older one, e.g. a retried batch
+    // arriving after fresh samples, is only filed into history at its place in time.
+    if let Some(existing) = state.store.get(&system_info.device_id) {
+        if history::sample_time(&system_info) < history::sample_time(&existing.info) {
+            info!("Filing out-of-order sample of device {} from {} into history", system_info.device_id, system_info.last_seen);
+            record_percentiles(state, &system_info);
+            let retention_secs = state.config().history_retention_secs;
+            let device_id = system_info.device_id.clone();
+            state.store.add_history(&device_id, HistoryPoint::new(system_info), &mut |buffer, point| {
+                history::insert_ordered(buffer, point);
+                history::trim(buffer, retention_secs, Utc::now());
+            });
+            state.run_stats.record_sample(state.store.len());
+            return;
+        }
+    }
+    
     info!("Received metrics from device: {}", system_info.device_id);
-    info!("OS: {}, CPU: {:.1}%, RAM: {}/{} MB", 
+    info!("OS: {}, CPU: {:.1}%, RAM: {} / {}", 
         system_info.os_info, 
         system_info.cpu_usage, 
-        system_info.ram_used_mb, 
-        system_info.ram_total_mb
+        format_mb(system_info.ram_used_mb), 
+        format_mb(system_info.ram_total_mb)
     );
-    info!("Last seen: {}", system_info.last_seen);
+    info!("Last seen: {}", localtime::render_rfc3339(&system_info.last_seen));
     info!("---");
+    if system_info.collect_duration_ms >= SLOW_COLLECT_MS {
+        warn!("Device {} is collecting slowly: {} ms", system_info.device_id, system_info.collect_duration_ms);
+    }
+    
+    // Derived custom fields are added before anything else looks at the sample
+    if let Some(transform) = &state.transform {
+        transform.apply(&mut system_info);
+    }
+    
+    // Health, alerts and the device class see the full sample; the store trims its copy (TAILMON_RETAIN_RULES)
+    let health_score = health::health_score(&system_info, &state.config().health_weights, Utc::now());
+    let device_class = classes::classify(&state.config().device_classes, &system_info);
+    let alert_events = state.alerts.evaluate(&system_info);
+    state.activity.extend(alert_events.into_iter().map(Into::into));
+    let conflicted = state.conflicts.observe(&system_info, Utc::now());
+    
+    // Relay the full sample upstream; the self-monitor device would collide between servers
+    if let Some(upstream) = &state.upstream {
+        if system_info.device_id != self_monitor::SELF_DEVICE_ID {
+            relay(upstream, &state.config().relay_id, &system_info);
+        }
+    }
+    
+    // Metric series are configured explicitly, so they see the full sample too
+    if !state.config().series.is_empty() {
+        let mut series = state.series.entry(system_info.device_id.clone()).or_default();
+        series::record(&state.config().series, &mut series, &system_info, history::sample_time(&system_info));
+    }
+    record_percentiles(state, &system_info);
+    
+    // Append to the device's history (unless the change filter skips it), dropping samples past retention
+    {
+        let config = state.config();
+        let filter = config.history_delta_pct.map(|delta_pct| ChangeFilter {
+            delta_pct,
+            min_interval_secs: config.history_min_interval_secs,
+        });
+        state.store.add_history(&system_info.device_id, HistoryPoint::new(system_info.clone()), &mut |buffer, point| {
+            if filter.is_none_or(|filter| filter.should_record(buffer.back(), &point)) {
+                buffer.push_back(point);
+            }
+            history::trim(buffer, config.history_retention_secs, Utc::now());
+        });
+    }
+    
+    // Locate the device once, on its first report; later updates keep the location.
+    // Later reports are also diffed against the stored record for configuration changes.
+    let location = match state.store.get(&system_info.device_id) {
+        Some(existing) => {
+            let change_events = state.changes.diff(&existing.info, &system_info);
+            state.activity.extend(change_events.into_iter().map(Into::into));
+            existing.location.clone()
+        }
+        None => state.geoip.as_ref()
+            .zip(addr)
+            .and_then(|(geoip, addr)| geoip.locate(addr.ip())),
+    };
+    
+    // Reuse the cached name of the source address, refreshing it in the background when due
+    let resolved_name = match (&state.rdns, addr) {
+        (Some(rdns), Some(addr)) => {
+            let (name, due) = rdns.cached(addr.ip());
+            if due {
+                tokio::spawn(resolve_name(state.clone(), system_info.device_id.clone(), addr.ip()));
+            }
+            name
+        }
+        _ => None,
+    };
+    
+    // Keep the original payload next to the record; an oversized one only clears the stale copy
+    match raw {
+        Some(raw) => {
+            state.raw.insert(system_info.device_id.clone(), raw);
+        }
+        None => {
+            state.raw.remove(&system_info.device_id);
+        }
+    }
+    
+    // The first report of a device_id fixes its first_seen for good
+    let first_seen = *state.first_seen.entry(system_info.device_id.clone()).or_insert_with(|| {
+        state.activity.record(&system_info.device_id, ActivityKind::NewDevice);
+        Utc::now()
+    });
     
     // Store or update the metrics in memory
-    state.metrics.insert(system_info.device_id.clone(), system_info);
+    let device_id = system_info.device_id.clone();
+    let record = DeviceRecord { info: system_info, first_seen, health_score, location, resolved_name, conflicted, device_class };
+    state.store.upsert(record);
+    
+    // The inventory sees the record as stored, without the fields the device does not retain
+    if let Some(record) = state.store.get(&device_id) {
+        state.inventory.observe(&InventoryRecord::from_record(&record));
+    }
+    state.run_stats.record_sample(state.store.len());
+}
+
+/// Reverse-resolve a device's source address and attach the name to its record
+async fn resolve_name(state: Arc<AppState>, device_id: String, ip: IpAddr) {
+    let Some(rdns) = &state.rdns else {
+        return;
+    };
+    let name = rdns.lookup(ip).await;
+    state.store.update(&device_id, &mut |record| record.resolved_name = name.clone());
+}
+
+/// Apply queued samples in arrival order. With a transform script a sample is applied on the
+/// blocking pool, since the script may run up to its time limit.
+async fn run_ingest_worker(state: Arc<AppState>, mut ingest_rx: mpsc::Receiver<IngestItem>) {
+    while let Some(item) = ingest_rx.recv().await {
+        if state.transform.is_none() {
+            apply_sample(&state, item);
+            continue;
+        }
+        let state = state.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || apply_sample(&state, item)).await {
+            error!("Failed to apply sample: {}", e);
+        }
+    }
+}
+
+/// 503 response telling agents to back off because the ingest queue is full
+fn queue_full(state: &AppState, dropped: usize) -> Response {
+    state.ingest_dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+    warn!("Ingest queue full, dropped {} samples", dropped);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "1")],
+        "Ingest queue full, retry later",
+    ).into_response()
+}
+
+/// Content type of a JSON request body, or `None` when the body is not declared as JSON
+fn json_content_type(headers: &HeaderMap) -> Option<&str> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    (essence == "application/json" || essence.ends_with("+json")).then_some(content_type)
+}
+
+/// 415 response for ingest bodies that are not declared as JSON
+fn unsupported_media_type() -> Response {
+    IngestError::unsupported_media_type("Expected request with `Content-Type: application/json`").into_response()
+}
+
+/// Decode a single sample body: JSON, or protobuf (proto/tailmon.proto) when built with the
+/// `protobuf` feature. Returns the sample with the declared content type.
+fn decode_sample<'a>(headers: &'a HeaderMap, body: &Bytes) -> Result<(SystemInfo, &'a str), IngestError> {
+    #[cfg(feature = "protobuf")]
+    if let Some(content_type) = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|content_type| proto::is_protobuf(content_type))
+    {
+        return match proto::decode(body) {
+            Ok(system_info) => Ok((system_info, content_type)),
+            Err(e) => Err(IngestError::invalid_protobuf(e)),
+        };
+    }
+
+    let Some(content_type) = json_content_type(headers) else {
+        let expected = if cfg!(feature = "protobuf") {
+            "Expected request with `Content-Type: application/json` or `Content-Type: application/x-protobuf`"
+        } else {
+            "Expected request with `Content-Type: application/json`"
+        };
+        return Err(IngestError::unsupported_media_type(expected));
+    };
+    let system_info = ingest_error::parse_json(body)?;
+    Ok((system_info, content_type))
+}
+
+/// Keep a sample's original body for the raw endpoint, unless it is larger than TAILMON_RAW_MAX_BYTES
+fn raw_payload(state: &AppState, content_type: &str, body: Bytes) -> Option<RawPayload> {
+    (body.len() <= state.config().raw_max_bytes && state.config().raw_max_bytes > 0)
+        .then(|| RawPayload { content_type: content_type.to_string(), body })
+}
+
+/// Handler function to receive metrics from agents.
+/// Samples are validated, queued and applied asynchronously (202 Accepted).
+/// A sample whose idempotency key was already received is acknowledged with 200 and not stored again.
+async fn receive_metrics(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let (mut system_info, content_type) = match decode_sample(&headers, &body) {
+        Ok(decoded) => decoded,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if let Err(rejection) = check_sample(&state, &peer, &system_info) {
+        return rejection.into_response();
+    }
+    if !claim_key(&state, &system_info) {
+        return accepted(&state, &system_info.device_id, 0, 1);
+    }
+    if state.config().time_source == TimeSource::Server {
+        history::restamp(std::slice::from_mut(&mut system_info), Utc::now());
+    }
+    
+    let device_id = system_info.device_id.clone();
+    let raw = raw_payload(&state, content_type, body);
+    match state.ingest_tx.try_send(IngestItem { sample: Sample::Full(system_info), addr: peer.addr, raw }) {
+        Ok(()) => accepted(&state, &device_id, 1, 0),
+        Err(e) => {
+            if let Sample::Full(system_info) = &e.into_inner().sample {
+                release_key(&state, system_info);
+            }
+            queue_full(&state, 1)
+        }
+    }
+}
+
+/// Handler function to receive several samples at once (e.g. an agent's offline buffer).
+/// The whole batch is validated and queued atomically, preserving its order.
+/// Each sample's own JSON text is kept as its raw payload. Samples whose idempotency key
+/// was already received are skipped; a batch of only such samples is answered with 200.
+async fn receive_metrics_batch(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(content_type) = json_content_type(&headers) else {
+        return unsupported_media_type();
+    };
+    let raw_batch = match ingest_error::parse_json::<Vec<Box<RawValue>>>(&body) {
+        Ok(raw_batch) => raw_batch,
+        Err(e) => return e.into_response(),
+    };
+    if raw_batch.len() > MAX_BATCH_SIZE {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("batch exceeds {} samples", MAX_BATCH_SIZE)).into_response();
+    }
+    let mut batch = Vec::with_capacity(raw_batch.len());
+    for (index, raw) in raw_batch.iter().enumerate() {
+        match ingest_error::parse_json::<SystemInfo>(raw.get().as_bytes()) {
+            Ok(system_info) => batch.push(system_info),
+            Err(e) => return e.in_sample(index).into_response(),
+        }
+    }
+    if let Some(rejection) = batch.iter().find_map(|system_info| check_sample(&state, &peer, system_info).err()) {
+        return rejection.into_response();
+    }
+    let device_id = batch.last().map(|system_info| system_info.device_id.clone()).unwrap_or_default();
+    let received = batch.len();
+    let (mut batch, raw_batch): (Vec<_>, Vec<_>) = batch.into_iter().zip(raw_batch)
+        .filter(|(system_info, _)| claim_key(&state, system_info))
+        .unzip();
+    let duplicates = received - batch.len();
+    if batch.is_empty() && duplicates > 0 {
+        return accepted(&state, &device_id, 0, duplicates);
+    }
+    if state.config().time_source == TimeSource::Server {
+        history::restamp(&mut batch, Utc::now());
+    }
+    
+    let count = batch.len();
+    let permits = match state.ingest_tx.try_reserve_many(count) {
+        Ok(permits) => permits,
+        Err(_) => {
+            for system_info in &batch {
+                release_key(&state, system_info);
+            }
+            return queue_full(&state, count);
+        }
+    };
+    for (permit, (system_info, raw)) in permits.zip(batch.into_iter().zip(raw_batch)) {
+        let raw = raw_payload(&state, content_type, Bytes::copy_from_slice(raw.get().as_bytes()));
+        permit.send(IngestItem { sample: Sample::Full(system_info), addr: peer.addr, raw });
+    }
+    accepted(&state, &device_id, count, duplicates)
+}
+
+/// Handler function to merge a partial sample into a device's stored record.
+/// Omitted fields keep their stored values; the device must have reported a full sample before.
+async fn patch_metrics(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    Path(device_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(content_type) = json_content_type(&headers) else {
+        return unsupported_media_type();
+    };
+    let mut patch = match ingest_error::parse_json::<SystemInfoPatch>(&body) {
+        Ok(patch) => patch,
+        Err(e) => return e.into_response(),
+    };
+    if state.config().time_source == TimeSource::Server {
+        patch.last_seen = Some(Utc::now().to_rfc3339());
+    }
+    
+    // Validate the merge against the current record; the worker merges again when the patch is applied
+    let Some(existing) = state.store.get(&device_id).map(|record| record.info) else {
+        return (StatusCode::NOT_FOUND, format!("Unknown device: {}", device_id)).into_response();
+    };
+    let merged = patch.clone().apply(existing, Utc::now().to_rfc3339());
+    if let Err(rejection) = check_sample(&state, &peer, &merged) {
+        return rejection.into_response();
+    }
     
-    (StatusCode::OK, "Veri Alındı")
+    let raw = raw_payload(&state, content_type, body);
+    let sample = Sample::Patch { device_id: device_id.clone(), patch };
+    match state.ingest_tx.try_send(IngestItem { sample, addr: peer.addr, raw }) {
+        Ok(()) => accepted(&state, &device_id, 1, 0),
+        Err(_) => queue_full(&state, 1),
+    }
 }
 
-/// Handler function to get all metrics
-async fn get_all_metrics(State(state): State<Arc<AppState>>) -> JsonResponse<Vec<SystemInfo>> {
-    let metrics: Vec<SystemInfo> = state.metrics.iter().map(|entry| entry.value().clone()).collect();
-    JsonResponse(metrics)
+/// 202 response carrying the device's configured reporting interval, if any, and the
+/// commands queued for it (which are handed out once). 200 when only duplicates were received.
+fn accepted(state: &AppState, device_id: &str, count: usize, duplicates: usize) -> Response {
+    let interval_secs = state.intervals.get(device_id).map(|interval| *interval);
+    let commands = state.commands.take(device_id);
+    let status = if count == 0 && duplicates > 0 { StatusCode::OK } else { StatusCode::ACCEPTED };
+    (status, JsonResponse(IngestResponse { accepted: count, duplicates, interval_secs, commands })).into_response()
+}
+
+/// Longest reporting interval that can be pushed to an agent
+const MAX_INTERVAL_SECS: u64 = 86400;
+
+/// Request body of the interval endpoint; `null` clears the override
+#[derive(Debug, Deserialize)]
+struct IntervalRequest {
+    interval_secs: Option<u64>,
+}
+
+/// Set (or clear with `None`) the reporting interval pushed to a device
+fn set_interval(state: &AppState, device_id: &str, interval_secs: Option<u64>) -> Result<String, String> {
+    match interval_secs {
+        Some(secs) if !(1..=MAX_INTERVAL_SECS).contains(&secs) => {
+            Err(format!("interval_secs must be between 1 and {}", MAX_INTERVAL_SECS))
+        }
+        Some(secs) => {
+            info!("Reporting interval for {} set to {}", device_id, format_duration(secs));
+            state.intervals.insert(device_id.to_string(), secs);
+            Ok(format!("Interval of {} set to {}", device_id, format_duration(secs)))
+        }
+        None => {
+            info!("Reporting interval override for {} cleared", device_id);
+            state.intervals.remove(device_id);
+            Ok(format!("Interval override of {} cleared", device_id))
+        }
+    }
+}
+
+/// Handler function to set (or clear) the reporting interval pushed to a device
+async fn put_interval(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Json(request): Json<IntervalRequest>,
+) -> Result<String, (StatusCode, String)> {
+    set_interval(&state, &device_id, request.interval_secs).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))
+}
+
+/// Handler function to expose Prometheus metrics (OpenMetrics when the Accept header asks for it)
+async fn get_prometheus_metrics(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok());
+    let format = metrics::Format::from_accept(accept);
+    (
+        [(header::CONTENT_TYPE, format.content_type())],
+        metrics::render(&state, format),
+    ).into_response()
+}
+
+/// Query parameters for the all metrics endpoint
+#[derive(Debug, Deserialize)]
+struct AllMetricsQuery {
+    /// Only devices currently in this status
+    status: Option<DeviceStatus>,
+    
+    /// Only devices of this class (TAILMON_DEVICE_CLASSES)
+    class: Option<String>,
+    
+    /// Comma-separated keys to return per device (e.g. "device_id,cpu_usage")
+    fields: Option<String>,
+
+    /// Filter expression (e.g. `cpu_usage > 80 AND os_info ~ "Ubuntu"`)
+    q: Option<String>,
+}
+
+/// Handler function to get all metrics, optionally only those of one status or
+/// matching a filter expression, and reduced to the requested fields
+async fn get_all_metrics(State(state): State<Arc<AppState>>, Query(query): Query<AllMetricsQuery>) -> Result<Response, (StatusCode, String)> {
+    let fields: Option<Vec<&str>> = query.fields.as_deref().map(|raw| raw.split(',').map(str::trim).filter(|field| !field.is_empty()).collect());
+    if let Some(fields) = &fields {
+        if fields.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "'fields' must name at least one field".to_string()));
+        }
+        if let Some(unknown) = fields.iter().find(|field| !DeviceRecord::FIELDS.contains(field)) {
+            return Err((StatusCode::BAD_REQUEST,
+                format!("Unknown field '{}', expected one of: {}", unknown, DeviceRecord::FIELDS.join(", "))));
+        }
+    }
+    
+    let filter = match query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        Some(q) => Some(Expr::parse(q).map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid query: {}", e)))?),
+        None => None,
+    };
+
+    let now = Utc::now();
+    let metrics = state.store.all().into_iter()
+        .filter(|record| query.status.is_none_or(|status| device_status(&record.info.last_seen, now) == status))
+        .filter(|record| query.class.is_none() || record.device_class == query.class)
+        .filter(|record| filter.as_ref().is_none_or(|filter| filter.matches(&serde_json::Value::Object(record.to_json()))));
+    let response = match &fields {
+        Some(fields) => JsonResponse(metrics.map(|record| record.project(fields)).collect::<Vec<_>>()).into_response(),
+        None => JsonResponse(metrics.collect::<Vec<_>>()).into_response(),
+    };
+    Ok(response)
+}
+
+/// Most device IDs accepted by one bulk query
+const MAX_QUERY_DEVICES: usize = 1000;
+
+/// Request body of the bulk query endpoint
+#[derive(Debug, Deserialize)]
+struct MetricsQuery {
+    device_ids: Vec<String>,
+}
+
+/// Response body of the bulk query endpoint
+#[derive(Debug, Serialize)]
+struct MetricsQueryResponse {
+    /// Records of the known devices, in request order
+    devices: Vec<DeviceRecord>,
+
+    /// Requested IDs without a record
+    unknown: Vec<String>,
+}
+
+/// Handler function to get the records of a list of devices in one request
+async fn query_metrics(State(state): State<Arc<AppState>>, Json(query): Json<MetricsQuery>) -> Result<JsonResponse<MetricsQueryResponse>, (StatusCode, String)> {
+    if query.device_ids.len() > MAX_QUERY_DEVICES {
+        return Err((StatusCode::BAD_REQUEST, format!("At most {} device_ids per query", MAX_QUERY_DEVICES)));
+    }
+    let mut response = MetricsQueryResponse { devices: Vec::new(), unknown: Vec::new() };
+    let mut seen = HashSet::new();
+    for device_id in query.device_ids.into_iter().filter(|device_id| seen.insert(device_id.clone())) {
+        match state.store.get(&device_id) {
+            Some(record) => response.devices.push(record),
+            None => response.unknown.push(device_id),
+        }
+    }
+    Ok(JsonResponse(response))
+}
+
+/// Metric used to rank devices
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SortMetric {
+    Cpu,
+    RamUsed,
+    RamPct,
+}
+
+impl SortMetric {
+    /// Value of this metric for a device
+    fn value(self, info: &SystemInfo) -> f64 {
+        match self {
+            SortMetric::Cpu => info.cpu_usage as f64,
+            SortMetric::RamUsed => info.ram_used_mb as f64,
+            SortMetric::RamPct => ram_pct(info),
+        }
+    }
+}
+
+/// RAM usage as a percentage of total (0 when total is unknown)
+fn ram_pct(info: &SystemInfo) -> f64 {
+    if info.ram_total_mb == 0 {
+        0.0
+    } else {
+        info.ram_used_mb as f64 / info.ram_total_mb as f64 * 100.0
+    }
+}
+
+/// Sort devices by a metric, highest first (ties broken by device_id for stable output)
+fn sort_devices(devices: &mut [DeviceRecord], metric: SortMetric) {
+    devices.sort_by(|a, b| {
+        metric.value(&b.info).total_cmp(&metric.value(&a.info))
+            .then_with(|| a.info.device_id.cmp(&b.info.device_id))
+    });
+}
+
+/// Query parameters for the top endpoint
+#[derive(Debug, Deserialize)]
+struct TopQuery {
+    metric: SortMetric,
+    
+    /// Number of devices to return (default 10)
+    n: Option<usize>,
+}
+
+/// Handler function to get the N devices with the highest value of a metric
+async fn get_top(State(state): State<Arc<AppState>>, Query(query): Query<TopQuery>) -> JsonResponse<Vec<DeviceRecord>> {
+    let mut devices: Vec<DeviceRecord> = state.store.all();
+    sort_devices(&mut devices, query.metric);
+    devices.truncate(query.n.unwrap_or(10));
+    JsonResponse(devices)
+}
+
+/// Query parameters for the compare endpoint
+#[derive(Debug, Deserialize)]
+struct CompareQuery {
+    a: String,
+    b: String,
+}
+
+/// Differences between two devices (`a` minus `b`)
+#[derive(Debug, Serialize)]
+struct CompareDiff {
+    cpu_usage: f32,
+    ram_used_mb: i64,
+    ram_total_mb: i64,
+    ram_pct: f64,
+}
+
+/// Response body of the compare endpoint
+#[derive(Debug, Serialize)]
+struct CompareResponse {
+    a: DeviceRecord,
+    b: DeviceRecord,
+    diff: CompareDiff,
+}
+
+/// Handler function to compare the current metrics of two devices side by side
+async fn get_compare(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CompareQuery>,
+) -> Result<JsonResponse<CompareResponse>, (StatusCode, String)> {
+    let lookup = |device_id: &str| {
+        state.store.get(device_id)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown device: {}", device_id)))
+    };
+    let (a, b) = (lookup(&query.a)?, lookup(&query.b)?);
+    
+    let diff = CompareDiff {
+        cpu_usage: a.info.cpu_usage - b.info.cpu_usage,
+        ram_used_mb: a.info.ram_used_mb as i64 - b.info.ram_used_mb as i64,
+        ram_total_mb: a.info.ram_total_mb as i64 - b.info.ram_total_mb as i64,
+        ram_pct: ram_pct(&a.info) - ram_pct(&b.info),
+    };
+    Ok(JsonResponse(CompareResponse { a, b, diff }))
+}
+
+/// Handler function to return the last sample of a device exactly as the agent sent it
+async fn get_raw_metrics(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let raw = state.raw.get(&device_id).ok_or((StatusCode::NOT_FOUND, "No raw payload stored for this device"))?;
+    Ok(([(header::CONTENT_TYPE, raw.content_type.clone())], raw.body.clone()).into_response())
+}
+
+/// Query parameters for the history endpoint
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// Return only the most recent N points
+    limit: Option<usize>,
+    
+    /// Skip this many of the most recent points first, to page back through the history
+    offset: Option<usize>,
+    
+    /// Return this metric's own series (TAILMON_SERIES) instead of full samples
+    metric: Option<String>,
+}
+
+/// Resolution metadata for a history response
+#[derive(Debug, Serialize)]
+struct HistoryResolution {
+    /// Points older than this are downsampled (None when downsampling is off)
+    downsample_after_secs: Option<u64>,
+    
+    /// Width of a downsampled bucket (None when downsampling is off)
+    bucket_secs: Option<u64>,
+}
+
+/// Response body of the history endpoint, followed by its streamed `points`
+#[derive(Debug, Serialize)]
+struct HistoryResponse {
+    device_id: String,
+    retention_secs: u64,
+    resolution: HistoryResolution,
+    
+    /// Annotations of the device and of all devices within the points' time span
+    annotations: Vec<Annotation>,
+}
+
+/// Response body of the history endpoint for a single metric series, followed by its streamed `points`
+#[derive(Debug, Serialize)]
+struct SeriesResponse {
+    device_id: String,
+    metric: &'static str,
+    retention_secs: u64,
+    resolution_secs: u64,
+    annotations: Vec<Annotation>,
+}
+
+/// Header telling the client its history `limit` was lowered to TAILMON_MAX_HISTORY_LIMIT
+const LIMIT_CLAMPED_HEADER: &str = "x-history-limit-clamped";
+
+/// Points of a history window selected by `limit` and `offset`
+struct HistoryWindow {
+    /// Index range into the buffer, oldest first
+    range: std::ops::Range<usize>,
+    
+    /// The lowered limit, when TAILMON_MAX_HISTORY_LIMIT cut the window short
+    clamped_to: Option<usize>,
+}
+
+impl HistoryWindow {
+    /// The `limit` points before the newest `offset` ones of a `len`-point buffer,
+    /// with `limit` capped at `max` (no limit asks for everything)
+    fn select(len: usize, limit: Option<usize>, offset: Option<usize>, max: Option<usize>) -> Self {
+        let end = len.saturating_sub(offset.unwrap_or(0));
+        let wanted = limit.unwrap_or(end).min(end);
+        let count = max.map_or(wanted, |max| wanted.min(max));
+        HistoryWindow {
+            range: end - count..end,
+            clamped_to: (count < wanted).then_some(count),
+        }
+    }
+    
+    /// Add the clamping header to a response when the window was cut short
+    fn mark(&self, mut response: Response) -> Response {
+        if let Some(limit) = self.clamped_to {
+            response.headers_mut().insert(LIMIT_CLAMPED_HEADER, HeaderValue::from(limit));
+        }
+        response
+    }
+}
+
+/// Points serialized into one chunk of a streamed history response
+const HISTORY_CHUNK_POINTS: usize = 256;
+
+/// Stream `head` with its points appended as a `points` array, serializing one chunk of points
+/// at a time as the client reads, so a large window is never held as one JSON string
+fn stream_points<I>(head: &impl Serialize, points: I) -> Response
+where
+    I: IntoIterator,
+    I::Item: Serialize + Send + 'static,
+    I::IntoIter: Send + 'static,
+{
+    let mut head = serde_json::to_string(head).unwrap_or_else(|_| "{}".to_string());
+    head.pop(); // Reopen the object after its last field
+    head.push_str(",\"points\":[");
+    
+    let mut head = Some(head.into_bytes());
+    let mut points = points.into_iter().peekable();
+    let mut first = true;
+    let mut done = false;
+    let chunks = std::iter::from_fn(move || {
+        if let Some(head) = head.take() {
+            return Some(head);
+        }
+        if done {
+            return None;
+        }
+        let mut chunk = Vec::new();
+        for point in points.by_ref().take(HISTORY_CHUNK_POINTS) {
+            match serde_json::to_vec(&point) {
+                // The separator goes before a point, so a skipped last point leaves no trailing comma
+                Ok(json) => {
+                    if !std::mem::take(&mut first) {
+                        chunk.push(b',');
+                    }
+                    chunk.extend_from_slice(&json);
+                }
+                Err(e) => warn!("Skipping a history point that cannot be serialized: {}", e),
+            }
+        }
+        if points.peek().is_none() {
+            chunk.extend_from_slice(b"]}");
+            done = true;
+        }
+        Some(chunk)
+    });
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        Body::from_stream(futures_util::stream::iter(chunks.map(Ok::<_, Infallible>))),
+    ).into_response()
+}
+
+/// Handler function to get the retained history of one device, or of one of its metrics with `?metric=`
+async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    if let Some(metric) = &query.metric {
+        return get_series_history(&state, device_id, metric, &query);
+    }
+    let max_limit = state.config().max_history_limit;
+    let select = |len| HistoryWindow::select(len, query.limit, query.offset, max_limit);
+    let (len, points) = state.store.history(&device_id, &|len| select(len).range).ok_or((StatusCode::NOT_FOUND, "Unknown device"))?;
+    let window = select(len);
+    let newest = window.range.end == len;
+    let span = points.first().zip(points.last()).map(|(first, last)| (first.timestamp, last.timestamp));
+    
+    // Missed samples are filled on the way out; stored history keeps its gaps
+    let points: Box<dyn Iterator<Item = HistoryPoint> + Send> = match state.config().max_gap_fill_secs {
+        Some(max_gap_secs) => Box::new(GapFiller { max_gap_secs, mode: state.config().gap_fill_mode }.fill(points)),
+        None => Box::new(points.into_iter()),
+    };
+    
+    let downsampler = state.config().downsample_after_secs;
+    Ok(window.mark(stream_points(&HistoryResponse {
+        annotations: window_annotations(&state, &device_id, span, newest),
+        device_id,
+        retention_secs: state.config().history_retention_secs,
+        resolution: HistoryResolution {
+            downsample_after_secs: downsampler,
+            bucket_secs: downsampler.map(|_| state.config().downsample_bucket_secs),
+        },
+    }, points)))
+}
+
+/// Annotations overlapping the `(first, last)` timestamps of a history window's points.
+/// The newest window reaches up to now, so an annotation shows before the next sample does.
+fn window_annotations(state: &AppState, device_id: &str, span: Option<(DateTime<Utc>, DateTime<Utc>)>, newest: bool) -> Vec<Annotation> {
+    match span {
+        Some((first, last)) => state.annotations.between(device_id, first, if newest { last.max(Utc::now()) } else { last }),
+        None => Vec::new(),
+    }
+}
+
+/// History of one metric series of a device, oldest first
+fn get_series_history(
+    state: &AppState,
+    device_id: String,
+    metric: &str,
+    query: &HistoryQuery,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let metric = Metric::parse(metric).ok_or((StatusCode::BAD_REQUEST, "Unknown metric"))?;
+    let config = state.config();
+    let spec = config.series.iter().find(|spec| spec.metric == metric)
+        .ok_or((StatusCode::NOT_FOUND, "Metric is not kept as a series (see TAILMON_SERIES)"))?;
+    let select = |len| HistoryWindow::select(len, query.limit, query.offset, config.max_history_limit);
+    let (len, points): (usize, Vec<SeriesPoint>) = match state.series.get(&device_id) {
+        Some(series) => series.get(&metric)
+            .map(|buffer| (buffer.len(), buffer.range(select(buffer.len()).range).copied().collect()))
+            .unwrap_or_default(),
+        None if state.store.contains(&device_id) => (0, Vec::new()),
+        None => return Err((StatusCode::NOT_FOUND, "Unknown device")),
+    };
+    let window = select(len);
+    let newest = window.range.end == len;
+    let span = points.first().zip(points.last()).map(|(first, last)| (first.timestamp, last.timestamp));
+    Ok(window.mark(stream_points(&SeriesResponse {
+        annotations: window_annotations(state, &device_id, span, newest),
+        device_id,
+        metric: metric.name(),
+        retention_secs: spec.retention_secs,
+        resolution_secs: spec.resolution_secs,
+    }, points)))
+}
+
+/// Periodically trim every history buffer to retention and downsample old points
+async fn run_history_maintenance(state: Arc<AppState>) {
+    let downsampler = state.config().downsample_after_secs.map(|after_secs| Downsampler {
+        after_secs,
+        bucket_secs: state.config().downsample_bucket_secs,
+    });
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(state.config().downsample_bucket_secs));
+    
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let retention_secs = state.config().history_retention_secs;
+        state.store.evict(&mut |buffer| {
+            history::trim(buffer, retention_secs, now);
+            if let Some(downsampler) = &downsampler {
+                downsampler.run(buffer, now);
+            }
+        });
+        for mut series in state.series.iter_mut() {
+            series::trim(&state.config().series, &mut series, now);
+        }
+        state.series.retain(|_, series| !series.is_empty());
+        state.percentiles.retain(|_, rolling| {
+            rolling.trim(retention_secs, now);
+            !rolling.is_empty()
+        });
+        // Metric series may be kept longer than the history
+        let series_secs = state.config().series.iter().map(|spec| spec.retention_secs).max().unwrap_or(0);
+        state.annotations.trim(retention_secs.max(series_secs), now);
+        state.idempotency.purge(std::time::Duration::from_secs(state.config().idempotency_ttl_secs));
+    }
+}
+
+/// Handler function to get the effective, secret-redacted configuration
+async fn get_config(State(state): State<Arc<AppState>>) -> JsonResponse<config::EffectiveConfig> {
+    JsonResponse(state.config().effective())
+}
+
+/// Whether the request's If-None-Match lists `etag`, so the client's copy is current
+fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"))
+}
+
+/// Handler function to get a compact status map for cheap polling.
+/// Supports ETag/If-None-Match so unchanged fleets cost a 304.
+async fn get_status(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let now = Utc::now();
+    // BTreeMap keeps the serialized order (and therefore the ETag) stable
+    let statuses: BTreeMap<String, DeviceStatus> = state.store.all().into_iter()
+        .map(|record| (record.info.device_id, device_status(&record.info.last_seen, now)))
+        .collect();
+    
+    let body = match serde_json::to_vec(&statuses) {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    
+    if matches_etag(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    
+    (
+        [(header::CONTENT_TYPE, "application/json".to_string()), (header::ETAG, etag)],
+        body,
+    ).into_response()
+}
+
+/// Method, path and description of the API endpoints, listed in the startup log and the JSON service banner
+const ENDPOINTS: &[(&str, &str, &str)] = &[
+    ("POST", "/api/metrics", "Receive metrics from agents"),
+    ("POST", "/api/metrics/batch", "Receive several samples at once"),
+    ("PATCH", "/api/metrics/:device_id", "Merge a partial sample into a device's record"),
+    ("GET", "/api/all_metrics?fields=device_id,cpu_usage", "Get all stored metrics"),
+    ("POST", "/api/metrics/query", "Get the metrics of a list of devices"),
+    ("POST", "/api/admin/rename", "Move a device to a new device_id (API key)"),
+    ("GET", "/api/status", "Get online/stale/offline status per device"),
+    ("GET", "/api/top?metric=cpu&n=10", "Get the busiest devices"),
+    ("GET", "/api/compare?a=<id>&b=<id>", "Compare two devices"),
+    ("GET", "/api/metrics/:device_id/raw", "Get a device's last sample as sent"),
+    ("GET", "/api/history/:device_id", "Get retained history of a device"),
+    ("GET", "/api/percentiles/:device_id", "Get CPU usage percentiles of a device over the retained window"),
+    ("GET", "/api/inventory", "Get the device inventory for asset management"),
+    ("GET", "/api/alerts", "Get global and per-device alert thresholds"),
+    ("PUT", "/api/alerts/:device_id", "Set a device's alert thresholds (API key)"),
+    ("PUT", "/api/interval/:device_id", "Set a device's reporting interval (API key)"),
+    ("POST", "/api/annotations", "Annotate a device's timeline, or every device's (API key)"),
+    ("GET", "/api/commands", "WebSocket for sending commands to agents (API key)"),
+    ("GET", "/api/events", "Get detected OS and RAM changes"),
+    ("GET", "/api/activity", "Get the activity feed of all devices"),
+    ("GET", "/api/report", "Aggregate a metric per device over a time range"),
+    ("GET", "/api/schema/ui", "Get units, display names and thresholds of dashboard metrics"),
+    ("GET", "/api/logs?limit=200", "Get recent server log lines"),
+    ("GET", "/api/config", "Get the effective thresholds, retention and limits"),
+    ("GET", "/metrics", "Prometheus metrics"),
+];
+
+/// JSON answer of the root path for non-browser clients
+#[derive(Debug, Serialize)]
+struct ServiceBanner {
+    service: &'static str,
+    version: &'static str,
+    endpoints: Vec<BannerEndpoint>,
+}
+
+#[derive(Debug, Serialize)]
+struct BannerEndpoint {
+    method: &'static str,
+    path: String,
+    description: &'static str,
+}
+
+/// Whether the client asked for JSON rather than a page (browsers always accept text/html)
+fn wants_json(headers: &HeaderMap) -> bool {
+    let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    accept.contains("application/json") && !accept.contains("text/html")
+}
+
+/// Handler function for the root path: the embedded dashboard page, or a JSON
+/// service banner for clients sending `Accept: application/json`
+async fn index_handler(State(state): State<Arc<AppState>>, headers: HeaderMap, OriginalUri(original_uri): OriginalUri) -> Response {
+    if wants_json(&headers) {
+        // The root is requested at the reverse-proxy prefix, if any
+        let base_path = original_uri.path().trim_end_matches('/');
+        return JsonResponse(ServiceBanner {
+            service: "tailmon",
+            version: env!("CARGO_PKG_VERSION"),
+            endpoints: ENDPOINTS.iter()
+                .map(|(method, path, description)| BannerEndpoint { method, path: format!("{}{}", base_path, path), description })
+                .collect(),
+        }).into_response();
+    }
+    
+    match Assets::get("index.html") {
+        Some(content) => asset_response("index.html", content, "text/html; charset=utf-8", &headers, state.config().static_cache_secs),
+        None => (StatusCode::NOT_FOUND, "File not found").into_response()
+    }
+}
+
+/// Year-long caching for assets whose file name carries a content hash
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Whether a file name carries a content hash before its extension, e.g. `app.3f2a9c1b.js`
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let mut segments = name.rsplit('.').skip(1);
+    let hash = segments.next().unwrap_or_default();
+    segments.next().is_some() && hash.len() >= 8 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Response for an embedded asset with an ETag from its content hash (answering a matching
+/// If-None-Match with 304) and a Cache-Control policy: fingerprinted files are cached for good,
+/// index.html is revalidated on every load and other assets are cached for `cache_secs`
+fn asset_response(path: &str, content: EmbeddedFile, content_type: &str, headers: &HeaderMap, cache_secs: u64) -> Response {
+    let hash = content.metadata.sha256_hash();
+    let etag = format!("\"{}\"", hash[..8].iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+    let cache_control = if is_fingerprinted(path) {
+        IMMUTABLE_CACHE_CONTROL.to_string()
+    } else if path == "index.html" || cache_secs == 0 {
+        "no-cache".to_string()
+    } else {
+        format!("public, max-age={}", cache_secs)
+    };
+    
+    if matches_etag(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)]).into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, content_type.to_string()), (header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        content.data.into_owned(),
+    ).into_response()
 }
 
 /// Handler function to serve embedded static files
-async fn static_handler(uri: Uri) -> Response {
+async fn static_handler(
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    headers: HeaderMap,
+    OriginalUri(original_uri): OriginalUri,
+) -> Response {
     let path = uri.path().trim_start_matches('/');
     
+    // Behind a path prefix the dashboard must be loaded with a trailing slash,
+    // otherwise its relative asset and API URLs resolve against the parent path
+    if path.is_empty() && !original_uri.path().ends_with('/') {
+        return Redirect::permanent(&format!("{}/", original_uri.path())).into_response();
+    }
+    
     if path.is_empty() || path == "index.html" {
-        // Serve index.html for root path
-        match Assets::get("index.html") {
-            Some(content) => {
-                let html = String::from_utf8_lossy(&content.data);
-                Html(html.to_string()).into_response()
-            }
-            None => (StatusCode::NOT_FOUND, "File not found").into_response()
-        }
+        // Serve index.html (or the service banner) for root path
+        index_handler(State(state), headers, OriginalUri(original_uri)).await
     } else {
         // Serve other static files
         match Assets::get(path) {
@@ -70,52 +1379,529 @@ async fn static_handler(uri: Uri) -> Response {
                     "text/css"
                 } else if path.ends_with(".js") {
                     "application/javascript"
+                } else if path.ends_with(".html") {
+                    "text/html"
+                } else if path.ends_with(".ico") {
+                    "image/x-icon"
                 } else {
                     "text/plain"
                 };
-                
-                Response::builder()
-                    .header("Content-Type", mime_type)
-                    .body(axum::body::Body::from(content.data.to_vec()))
-                    .unwrap()
+                asset_response(path, content, mime_type, &headers, state.config().static_cache_secs)
             }
-            None => (StatusCode::NOT_FOUND, "File not found").into_response()
+            None => not_found(path)
+        }
+    }
+}
+
+/// Unknown paths get a JSON error under api/ and the embedded 404 page elsewhere
+fn not_found(path: &str) -> Response {
+    if path.starts_with("api/") {
+        return (StatusCode::NOT_FOUND, JsonResponse(serde_json::json!({ "error": "not found" }))).into_response();
+    }
+    match Assets::get("404.html") {
+        Some(content) => {
+            let html = String::from_utf8_lossy(&content.data);
+            (StatusCode::NOT_FOUND, Html(html.to_string())).into_response()
         }
+        None => (StatusCode::NOT_FOUND, "File not found").into_response()
     }
 }
 
+/// Convert a panic caught by CatchPanicLayer into a 500 response.
+/// The backtrace itself is logged by the panic hook installed in `main`.
+fn handle_panic(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic.downcast_ref::<String>().map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    error!("Request handler panicked: {}", message);
+    (StatusCode::INTERNAL_SERVER_ERROR, JsonResponse(serde_json::json!({"error": "internal server error"}))).into_response()
+}
+
+/// Build the TLS configuration when a certificate and key are configured
+fn load_tls_config(config: &Config) -> Result<Option<tokio_rustls::rustls::ServerConfig>, String> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) else {
+        if config.require_client_cert {
+            return Err("TAILMON_REQUIRE_CLIENT_CERT needs TAILMON_TLS_CERT and TAILMON_TLS_KEY".to_string());
+        }
+        return Ok(None);
+    };
+    
+    let client_ca_path = if config.require_client_cert {
+        let ca_path = config.client_ca_path.as_deref()
+            .ok_or("TAILMON_REQUIRE_CLIENT_CERT needs TAILMON_CLIENT_CA")?;
+        Some(ca_path)
+    } else {
+        None
+    };
+    
+    tls::load_server_config(cert_path, key_path, client_ca_path).map(Some)
+}
+
 #[tokio::main]
 async fn main() {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt()
-        .with_env_filter("server=info")
-        .with_target(false)
-        .with_thread_ids(true)
-        .with_thread_names(true)
+    // Initialize tracing subscriber (log times follow TAILMON_TZ once the configuration is loaded),
+    // keeping recent lines for the dashboard as well
+    let logs = Arc::new(LogBuffer::new());
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new("server=info"))
+        .with(tracing_subscriber::fmt::layer()
+            .with_timer(localtime::LogTimer)
+            .with_target(false)
+            .with_thread_ids(true)
+            .with_thread_names(true))
+        .with(LogLayer::new(logs.clone()))
         .init();
     
+    // Log panics with a backtrace captured at the panic site
+    std::panic::set_hook(Box::new(|panic_info| {
+        error!("Panic: {}\n{}", panic_info, Backtrace::force_capture());
+    }));
+    
     info!("Server starting on 0.0.0.0:3000...");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    localtime::set_timezone(config.timezone);
+    if let Some(retained) = &config.retain_fields {
+        info!("Retaining only these optional fields: {:?}", retained);
+    }
+    for rule in &config.retain_rules {
+        info!("Retaining only {:?} for devices matching {:?}", rule.fields, rule.selector);
+    }
+    let base_path = config.base_path.clone();
+    let tls_config = match load_tls_config(&config) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            error!("Invalid TLS configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    if config.api_key.is_none() {
+        warn!("TAILMON_API_KEY is not set: ingest is open to anyone and admin endpoints are disabled");
+    }
+    if config.read_api_key.is_none() {
+        warn!("TAILMON_READ_API_KEY is not set: fleet data under /api is readable by anyone");
+    }
+    
+    // Optional GeoIP enrichment; a broken database only disables the feature
+    let geoip = config.geoip_db_path.as_deref().and_then(|path| match GeoIp::open(path) {
+        Ok(geoip) => {
+            info!("GeoIP enrichment enabled using {}", path);
+            Some(geoip)
+        }
+        Err(e) => {
+            warn!("GeoIP enrichment disabled: {}", e);
+            None
+        }
+    });
+    
+    // Optional transform script; a broken script only disables the feature
+    let transform = config.transform_script.as_deref().and_then(|path| {
+        match Transform::open(path, std::time::Duration::from_millis(config.transform_timeout_ms)) {
+            Ok(transform) => {
+                info!("Transform script enabled using {} (time limit {} ms)", path, config.transform_timeout_ms);
+                Some(transform)
+            }
+            Err(e) => {
+                warn!("Transform script disabled: {}", e);
+                None
+            }
+        }
+    });
+    
+    // Optional reverse DNS of device addresses; an unusable resolver only disables the feature
+    let rdns = if config.reverse_dns {
+        match ReverseDns::from_system_conf(std::time::Duration::from_secs(config.reverse_dns_ttl_secs)) {
+            Ok(rdns) => {
+                info!("Reverse DNS enabled (cache TTL {})", format_duration(config.reverse_dns_ttl_secs));
+                Some(rdns)
+            }
+            Err(e) => {
+                warn!("Reverse DNS disabled: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    
+    // Optional relay to an upstream server; a broken setup only disables forwarding
+    let upstream = config.upstream_url.clone().and_then(|url| {
+        match Upstream::spawn(url, config.upstream_api_key.clone(), config.upstream_hmac.clone(), config.upstream_buffer, config.upstream_retry_ratio) {
+            Ok(upstream) => Some(upstream),
+            Err(e) => {
+                warn!("Upstream forwarding disabled: {}", e);
+                None
+            }
+        }
+    });
+    
+    let ingest_limit = limit::ConcurrencyLimit::new(config.max_concurrent);
+    if let Some(max_concurrent) = config.max_concurrent {
+        info!("Handling at most {} ingest requests at once", max_concurrent);
+    }
+    
+    // Bounded queue decoupling request latency from storage work
+    let (ingest_tx, ingest_rx) = mpsc::channel(config.ingest_queue_size);
     
     // Create application state
+    let alerts = Alerts::new(config.alert_defaults, config.alert_webhook_url.is_some());
+    let changes = Changes::new(config.alert_webhook_url.is_some() && config.change_webhook);
+    let store = InMemoryStore::new(config.retention());
     let state = Arc::new(AppState {
-        metrics: DashMap::new(),
+        config: RwLock::new(Arc::new(config)),
+        store: Box::new(store),
+        series: DashMap::new(),
+        percentiles: DashMap::new(),
+        first_seen: DashMap::new(),
+        raw: DashMap::new(),
+        geoip,
+        rdns,
+        transform,
+        alerts,
+        changes,
+        conflicts: Conflicts::new(),
+        activity: Activity::new(),
+        intervals: DashMap::new(),
+        commands: commands::CommandQueue::default(),
+        command_connections: AtomicU64::new(0),
+        inventory: inventory::Inventory::default(),
+        idempotency: idempotency::IdempotencyKeys::default(),
+        annotations: annotations::Annotations::default(),
+        ingest_tx,
+        ingest_dropped: AtomicU64::new(0),
+        signed_requests: DashMap::new(),
+        ingest_bytes: payload::SizeHistogram::default(),
+        ingest_limit,
+        upstream,
+        logs,
+        run_stats: RunStats::new(),
     });
     
+    // Bring back history from the last snapshot before accepting new samples
+    snapshot::restore(&state).await;
+    tokio::spawn(snapshot::run(state.clone()));
+    #[cfg(feature = "s3-export")]
+    tokio::spawn(export::run(state.clone()));
+    #[cfg(not(feature = "s3-export"))]
+    if state.config().s3_export.is_some() {
+        warn!("TAILMON_S3_BUCKET is ignored: this server was built without the s3-export feature");
+    }
+    
+    tokio::spawn(run_ingest_worker(state.clone(), ingest_rx));
+    
+    // Deliver alert transitions to the webhook, if one is configured
+    tokio::spawn(webhook::run(state.clone()));
+    tokio::spawn(changes::run_webhook(state.clone()));
+    tokio::spawn(offline::run_webhook(state.clone()));
+    
+    // Feed devices going offline and coming back into the activity log
+    tokio::spawn(activity::run_status_watch(state.clone()));
+    
+    // Optionally report the server host itself as a device
+    tokio::spawn(self_monitor::run(state.clone()));
+    
+    // Apply reloadable settings on SIGHUP
+    tokio::spawn(run_config_reload(state.clone()));
+    
+    // Keep history buffers bounded in the background
+    tokio::spawn(run_history_maintenance(state.clone()));
+    
     // Create the router with the metrics endpoints and static file serving
-    let app = Router::new()
+    let ingest_routes = Router::new()
         .route("/api/metrics", post(receive_metrics))
+        .route("/api/metrics/batch", post(receive_metrics_batch))
+        .route("/api/metrics/:device_id", patch(patch_metrics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), signing::require_signature))
+        .route_layer(middleware::from_fn_with_state(state.clone(), runstats::count_rejections))
+        .route_layer(middleware::from_fn_with_state(state.clone(), payload::record_ingest_size))
+        .route_layer(middleware::from_fn_with_state(state.clone(), limit::limit_ingest))
+        .route("/api/alerts/:device_id", put(alerts::put_alert_rule))
+        .route("/api/interval/:device_id", put(put_interval))
+        .route("/api/annotations", post(annotations::post_annotation))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_api_key));
+    let admin_routes = Router::new()
+        .route("/api/admin/rename", post(admin::rename_device))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_admin_key))
+        // Authenticates itself, since browsers cannot set headers on a websocket
+        .route("/api/commands", get(commands::command_socket));
+    let read_routes = Router::new()
         .route("/api/all_metrics", get(get_all_metrics))
+        .route("/api/metrics/query", post(query_metrics))
+        .route("/api/status", get(get_status))
+        .route("/api/top", get(get_top))
+        .route("/api/compare", get(get_compare))
+        .route("/api/metrics/:device_id/raw", get(get_raw_metrics))
+        .route("/api/history/:device_id", get(get_history))
+        .route("/api/percentiles/:device_id", get(percentiles::get_percentiles))
+        .route("/api/inventory", get(inventory::get_inventory))
+        .route("/api/alerts", get(alerts::get_alerts))
+        .route("/api/events", get(changes::get_events))
+        .route("/api/activity", get(activity::get_activity))
+        .route("/api/report", get(report::get_report))
+        .route("/api/schema/ui", get(schema::get_ui_schema))
+        .route("/api/logs", get(logs::get_logs))
+        .route("/api/config", get(get_config))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_read_key));
+    
+    let routes = Router::new()
+        .merge(ingest_routes)
+        .merge(admin_routes)
+        .merge(read_routes)
+        .route("/metrics", get(get_prometheus_metrics))
         .route("/", get(static_handler)) // Serve index.html at root
         .route("/*path", get(static_handler)) // Serve all other static files
-        .with_state(state);
+        .with_state(state.clone());
+    
+    // Nest everything under the reverse-proxy prefix when one is configured
+    let app = if base_path.is_empty() {
+        routes
+    } else {
+        Router::new()
+            .route(&format!("{}/", base_path), get(index_handler))
+            .with_state(state.clone())
+            .nest(&base_path, routes)
+    };
+    
+    // Turn panics in any handler into 500 responses instead of dropping the connection
+    let app = app.layer(CatchPanicLayer::custom(handle_panic));
     
     // Start the server
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    info!("Server is running on http://0.0.0.0:3000");
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    info!("Server is running on {}://0.0.0.0:3000{}/", scheme, base_path);
     info!("Available endpoints:");
-    info!("  POST /api/metrics - Receive metrics from agents");
-    info!("  GET  /api/all_metrics - Get all stored metrics");
-    info!("  GET  /static/* - Serve static files (e.g., index.html, styles.css, script.js)");
+    for (method, path, description) in ENDPOINTS {
+        info!("  {:<4} {}{} - {}", method, base_path, path, description);
+    }
+    info!("  GET  {}/static/* - Serve static files (e.g., index.html, styles.css, script.js)", base_path);
     
-    axum::serve(listener, app).await.unwrap();
-} 
\ No newline at end of file
+    match tls_config {
+        Some(tls_config) => tls::serve(listener, app, tls_config, shutdown_signal()).await,
+        None => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .unwrap(),
+    }
+    
+    // Keep the latest history across the restart
+    info!("Shutting down");
+    snapshot::save(&state).await;
+    state.run_stats.log_summary(&state);
+}
+
+/// Re-read the configuration on every SIGHUP and apply its reloadable settings
+/// (see `Config::reload`); an invalid config file keeps the current settings
+#[cfg(unix)]
+async fn run_config_reload(state: Arc<AppState>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, configuration reload disabled: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        let fresh = match Config::load() {
+            Ok(fresh) => fresh,
+            Err(e) => {
+                error!("Configuration reload failed, keeping the current settings: {}", e);
+                continue;
+            }
+        };
+        let mut config = (*state.config()).clone();
+        config.reload(fresh);
+        state.alerts.set_defaults(config.alert_defaults);
+        state.store.set_retention(config.retention());
+        *state.config.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(config);
+        info!("Configuration reloaded");
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_config_reload(_state: Arc<AppState>) {}
+
+/// Resolve on Ctrl+C or (on Unix) SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::test_util::sample;
+
+    /// State as `main` builds it with the default configuration, with the optional integrations off
+    pub(crate) fn test_state() -> Arc<AppState> {
+        let config = Config::defaults();
+        let (ingest_tx, _) = mpsc::channel(1);
+        Arc::new(AppState {
+            store: Box::new(InMemoryStore::new(config.retention())),
+            series: DashMap::new(),
+            percentiles: DashMap::new(),
+            first_seen: DashMap::new(),
+            raw: DashMap::new(),
+            geoip: None,
+            rdns: None,
+            transform: None,
+            alerts: Alerts::new(config.alert_defaults, false),
+            changes: Changes::new(false),
+            conflicts: Conflicts::new(),
+            activity: Activity::new(),
+            intervals: DashMap::new(),
+            ingest_tx,
+            ingest_dropped: AtomicU64::new(0),
+            signed_requests: DashMap::new(),
+            ingest_bytes: payload::SizeHistogram::default(),
+            ingest_limit: limit::ConcurrencyLimit::new(None),
+            upstream: None,
+            logs: Arc::new(LogBuffer::new()),
+            run_stats: RunStats::new(),
+            commands: commands::CommandQueue::default(),
+            command_connections: AtomicU64::new(0),
+            inventory: inventory::Inventory::default(),
+            idempotency: idempotency::IdempotencyKeys::default(),
+            annotations: annotations::Annotations::default(),
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Decode a body like POST /api/metrics does and return the record it stores
+    pub(crate) fn ingest(content_type: &'static str, body: Vec<u8>) -> serde_json::Map<String, serde_json::Value> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        let (system_info, _) = decode_sample(&headers, &Bytes::from(body)).unwrap();
+        assert_eq!(system_info.verify_checksum(), Some(true));
+
+        let state = test_state();
+        apply_sample(&state, IngestItem { sample: Sample::Full(system_info), addr: None, raw: None });
+        let mut record = state.store.get("web-1").unwrap().to_json();
+        // Set from the clock when each test state first saw the device
+        record.remove("first_seen");
+        record
+    }
+
+    #[test]
+    fn json_sample_is_stored_as_sent() {
+        let mut info = sample();
+        info.seal();
+        let record = ingest("application/json", serde_json::to_vec(&info).unwrap());
+        for (key, value) in serde_json::to_value(&info).unwrap().as_object().unwrap() {
+            assert_eq!(record.get(key), Some(value), "{}", key);
+        }
+    }
+
+    /// History point standing in for one that cannot be serialized
+    enum Point {
+        Value(u32),
+        Broken,
+    }
+
+    impl Serialize for Point {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                Point::Value(value) => serializer.serialize_u32(*value),
+                Point::Broken => Err(serde::ser::Error::custom("cannot serialize")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_points_stay_valid_json_when_some_are_skipped() {
+        let broken_last_in_chunk = (0..HISTORY_CHUNK_POINTS as u32 * 2).map(|value| if value == HISTORY_CHUNK_POINTS as u32 - 1 { None } else { Some(value) });
+        let cases: Vec<Vec<Option<u32>>> = vec![
+            vec![],
+            vec![None],
+            vec![Some(1), None],
+            vec![None, Some(1)],
+            vec![Some(1), None, Some(2), None],
+            broken_last_in_chunk.collect(),
+        ];
+        for case in cases {
+            let expected: Vec<u32> = case.iter().flatten().copied().collect();
+            let points: Vec<Point> = case.into_iter().map(|value| value.map_or(Point::Broken, Point::Value)).collect();
+            let response = stream_points(&serde_json::json!({"device_id": "web-1"}), points);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body, serde_json::json!({"device_id": "web-1", "points": expected}));
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch_is_unprocessable() {
+        let mut info = sample();
+        info.seal();
+        assert!(check_contents(true, &info).is_ok());
+
+        info.cpu_usage = 99.0;
+        let (status, reason) = check_contents(true, &info).unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(reason, "checksum does not match the sample");
+
+        // Only checked with TAILMON_VERIFY_CHECKSUM
+        assert!(check_contents(false, &info).is_ok());
+    }
+
+    #[test]
+    fn sample_without_checksum_is_accepted() {
+        let info = sample();
+        assert!(info.checksum.is_none());
+        assert!(check_contents(false, &info).is_ok());
+        assert!(check_contents(true, &info).is_ok());
+    }
+
+    #[test]
+    fn non_finite_values_are_unprocessable() {
+        let with = |change: fn(&mut SystemInfo)| {
+            let mut info = sample();
+            change(&mut info);
+            info
+        };
+        let cases = [
+            (with(|info| info.cpu_usage = f32::NAN), "cpu_usage must be a finite number, got NaN"),
+            (with(|info| info.cpu_usage = f32::INFINITY), "cpu_usage must be a finite number, got inf"),
+            (with(|info| info.cpu_usage = f32::NEG_INFINITY), "cpu_usage must be a finite number, got -inf"),
+            (with(|info| info.net_rx_rate_bytes_per_sec = Some(f64::NAN)), "net_rx_rate_bytes_per_sec must be a finite number, got NaN"),
+            (with(|info| info.net_tx_rate_bytes_per_sec = Some(f64::INFINITY)), "net_tx_rate_bytes_per_sec must be a finite number, got inf"),
+            (with(|info| info.net_rx_rate_bytes_per_sec = Some(f64::NEG_INFINITY)), "net_rx_rate_bytes_per_sec must be a finite number, got -inf"),
+        ];
+        for (info, expected) in cases {
+            let (status, reason) = check_contents(false, &info).unwrap_err();
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(reason, expected);
+        }
+
+        let mut info = sample();
+        info.net_rx_rate_bytes_per_sec = Some(1500.0);
+        assert!(check_contents(false, &info).is_ok());
+    }
+}
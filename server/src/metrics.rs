@@ -0,0 +1,170 @@
+use crate::record::DeviceRecord;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+/// Exposition format negotiated from the scraper's Accept header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Prometheus text format 0.0.4
+    Prometheus,
+
+    /// OpenMetrics 1.0 text format, with per-device sample timestamps
+    OpenMetrics,
+}
+
+impl Format {
+    /// Pick OpenMetrics when the Accept header asks for it, Prometheus text otherwise
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept) if accept.contains("application/openmetrics-text") => Format::OpenMetrics,
+            _ => Format::Prometheus,
+        }
+    }
+
+    /// Content-Type of a response in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Builds a Prometheus or OpenMetrics text exposition
+struct Exposition {
+    format: Format,
+    out: String,
+}
+
+impl Exposition {
+    fn new(format: Format) -> Self {
+        Exposition { format, out: String::new() }
+    }
+
+    /// Start a metric family with its HELP and TYPE lines.
+    /// OpenMetrics names counter families without the `_total` suffix of their samples.
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let name = match self.format {
+            Format::OpenMetrics if kind == "counter" => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    /// Write one sample of the current family
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.sample_at(name, labels, value, None);
+    }
+
+    /// Write one sample, timestamped when emitting OpenMetrics
+    fn sample_at(&mut self, name: &str, labels: &[(&str, &str)], value: f64, timestamp: Option<DateTime<Utc>>) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = write!(self.out, " {}", value);
+        if let (Format::OpenMetrics, Some(timestamp)) = (self.format, timestamp) {
+            let _ = write!(self.out, " {:.3}", timestamp.timestamp_millis() as f64 / 1000.0);
+        }
+        self.out.push('\n');
+    }
+
+    /// Write a sample labeled with the device_id, timestamped with the device's last_seen
+    fn device_sample(&mut self, name: &str, record: &DeviceRecord, value: f64) {
+        let last_seen = DateTime::parse_from_rfc3339(&record.info.last_seen).ok().map(|ts| ts.with_timezone(&Utc));
+        self.sample_at(name, &[("device_id", &record.info.device_id)], value, last_seen);
+    }
+
+    /// Write a family holding a single unlabeled sample
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: f64) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    /// Finish the exposition (OpenMetrics requires a trailing EOF marker)
+    fn finish(mut self) -> String {
+        if self.format == Format::OpenMetrics {
+            self.out.push_str("# EOF\n");
+        }
+        self.out
+    }
+}
+
+/// Escape a label value per the exposition format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render per-device gauges and server self-metrics
+pub fn render(state: &AppState, format: Format) -> String {
+    let mut exposition = Exposition::new(format);
+
+    let mut records = state.store.all();
+    records.sort_by(|a, b| a.info.device_id.cmp(&b.info.device_id));
+
+    exposition.single("tailmon_devices", "gauge", "Number of devices known to the server", records.len() as f64);
+
+    exposition.family("tailmon_device_cpu_usage_percent", "gauge", "CPU usage reported by the device");
+    for record in &records {
+        exposition.device_sample("tailmon_device_cpu_usage_percent", record, record.info.cpu_usage as f64);
+    }
+    exposition.family("tailmon_device_ram_used_mb", "gauge", "Used RAM reported by the device in MB");
+    for record in &records {
+        exposition.device_sample("tailmon_device_ram_used_mb", record, record.info.ram_used_mb as f64);
+    }
+    exposition.family("tailmon_device_ram_total_mb", "gauge", "Total RAM reported by the device in MB");
+    for record in &records {
+        exposition.device_sample("tailmon_device_ram_total_mb", record, record.info.ram_total_mb as f64);
+    }
+
+    exposition.family("tailmon_device_collect_duration_ms", "gauge", "Time the agent spent collecting the last sample in ms");
+    for record in &records {
+        exposition.device_sample("tailmon_device_collect_duration_ms", record, record.info.collect_duration_ms as f64);
+    }
+    exposition.family("tailmon_device_health_score", "gauge", "Server-computed 0-100 health score of the device");
+    for record in &records {
+        exposition.device_sample("tailmon_device_health_score", record, record.health_score);
+    }
+
+    let capacity = state.ingest_tx.max_capacity();
+    exposition.single("tailmon_ingest_queue_depth", "gauge", "Samples waiting in the ingest queue",
+        (capacity - state.ingest_tx.capacity()) as f64);
+    exposition.single("tailmon_ingest_queue_capacity", "gauge", "Maximum number of queued samples", capacity as f64);
+    exposition.single("tailmon_ingest_dropped_total", "counter", "Samples rejected because the ingest queue was full",
+        state.ingest_dropped.load(Ordering::Relaxed) as f64);
+    exposition.single("tailmon_ingest_in_flight", "gauge", "Ingest requests currently being handled",
+        state.ingest_limit.in_flight() as f64);
+    exposition.single("tailmon_ingest_rejected_concurrency_total", "counter",
+        "Ingest requests rejected because TAILMON_MAX_CONCURRENT was reached", state.ingest_limit.rejected() as f64);
+    exposition.single("tailmon_command_connections", "gauge", "Open command connections (GET /api/commands)",
+        state.command_connections.load(Ordering::Relaxed) as f64);
+
+    let mut signed: Vec<(String, u64)> = state.signed_requests.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+    signed.sort();
+    exposition.family("tailmon_signed_requests_total", "counter", "Ingest requests verified with each TAILMON_HMAC_SECRETS key");
+    for (key_id, count) in &signed {
+        exposition.sample("tailmon_signed_requests_total", &[("key_id", key_id)], *count as f64);
+    }
+
+    if let Some(upstream) = &state.upstream {
+        exposition.single("tailmon_upstream_dropped_total", "counter",
+            "Samples not forwarded upstream because the buffer was full or upstream rejected them", upstream.dropped() as f64);
+    }
+
+    let (buckets, sum) = state.ingest_bytes.snapshot();
+    exposition.family("tailmon_ingest_bytes", "histogram", "Content-Length of ingest requests in bytes");
+    for (bound, count) in &buckets {
+        let le = bound.map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+        exposition.sample("tailmon_ingest_bytes_bucket", &[("le", &le)], *count as f64);
+    }
+    exposition.sample("tailmon_ingest_bytes_sum", &[], sum as f64);
+    exposition.sample("tailmon_ingest_bytes_count", &[], buckets.last().map_or(0, |(_, count)| *count) as f64);
+
+    exposition.finish()
+}
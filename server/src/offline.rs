@@ -0,0 +1,107 @@
+use crate::{webhook, AppState};
+use chrono::{DateTime, Utc};
+use common::humanize::format_duration;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Seconds between scans for devices that stopped reporting
+const SCAN_SECS: u64 = 10;
+
+/// Presence transition of a device
+#[derive(Debug, Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PresenceKind {
+    /// No report for TAILMON_OFFLINE_WEBHOOK_AFTER_SECS
+    DeviceOffline,
+
+    /// Reporting again after being offline
+    DeviceOnline,
+}
+
+/// Body of an offline or back-online webhook call
+#[derive(Debug, Serialize)]
+struct PresenceEvent {
+    event: PresenceKind,
+    device_id: String,
+
+    /// Last report before going offline, or the report that brought the device back
+    last_seen: DateTime<Utc>,
+
+    /// How long the device was silent (only when it comes back)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offline_secs: Option<i64>,
+    timestamp: DateTime<Utc>,
+}
+
+/// last_seen of every device; unparseable timestamps are left out
+fn last_seen_by_device(state: &AppState) -> HashMap<String, DateTime<Utc>> {
+    state.store.all().into_iter()
+        .filter_map(|record| {
+            let last_seen = DateTime::parse_from_rfc3339(&record.info.last_seen).ok()?.with_timezone(&Utc);
+            Some((record.info.device_id, last_seen))
+        })
+        .collect()
+}
+
+/// POST an event to TAILMON_ALERT_WEBHOOK_URL once when a device stops reporting and once when
+/// it comes back (no-op unless both the webhook and TAILMON_OFFLINE_WEBHOOK are set). Devices
+/// already offline when the server starts count as offline without an event.
+pub async fn run_webhook(state: Arc<AppState>) {
+    let config = state.config();
+    let (Some(url), true) = (config.alert_webhook_url.clone(), config.offline_webhook) else {
+        return;
+    };
+    let after_secs = config.offline_webhook_after_secs as i64;
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Offline webhook disabled: failed to create HTTP client: {}", e);
+            return;
+        }
+    };
+
+    info!("Sending device offline and back-online events to webhook {} (offline after {})", url, format_duration(after_secs as u64));
+    // Last report of every device currently considered offline
+    let mut offline: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut seeded = false;
+    let mut interval = tokio::time::interval(Duration::from_secs(SCAN_SECS));
+    loop {
+        interval.tick().await;
+        let now = Utc::now();
+        let devices = last_seen_by_device(&state);
+        // Renamed devices are gone from the store; their old name never comes back
+        offline.retain(|device_id, _| devices.contains_key(device_id));
+
+        let mut events = Vec::new();
+        for (device_id, last_seen) in devices {
+            let silent = (now - last_seen).num_seconds() >= after_secs;
+            match offline.get(&device_id).copied() {
+                None if silent => {
+                    if seeded {
+                        events.push(PresenceEvent { event: PresenceKind::DeviceOffline, device_id: device_id.clone(), last_seen, offline_secs: None, timestamp: now });
+                    }
+                    offline.insert(device_id, last_seen);
+                }
+                // Any new report brings it back, even one already older than the threshold
+                Some(previous) if last_seen > previous => {
+                    let offline_secs = Some((last_seen - previous).num_seconds());
+                    events.push(PresenceEvent { event: PresenceKind::DeviceOnline, device_id: device_id.clone(), last_seen, offline_secs, timestamp: now });
+                    offline.remove(&device_id);
+                }
+                _ => {}
+            }
+        }
+        seeded = true;
+
+        for event in events {
+            info!("Device {} is {}", event.device_id, match event.event {
+                PresenceKind::DeviceOffline => "offline",
+                PresenceKind::DeviceOnline => "back online",
+            });
+            webhook::post(&client, &url, &event).await;
+        }
+    }
+}
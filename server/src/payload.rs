@@ -0,0 +1,67 @@
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use common::humanize::format_bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Upper bounds (in bytes) of the ingest payload size histogram buckets
+pub const BUCKETS: [u64; 10] = [256, 512, 1024, 2048, 4096, 8192, 16384, 65536, 262144, 1048576];
+
+/// Cumulative histogram of ingest payload sizes
+#[derive(Debug, Default)]
+pub struct SizeHistogram {
+    /// Observations per bucket (not cumulative); the last slot counts sizes above every bound
+    counts: [AtomicU64; BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+impl SizeHistogram {
+    /// Record one payload of `bytes`
+    pub fn observe(&self, bytes: u64) {
+        let slot = BUCKETS.iter().position(|&bound| bytes <= bound).unwrap_or(BUCKETS.len());
+        self.counts[slot].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Cumulative count per bucket bound (`None` is +Inf), plus the sum of all sizes
+    pub fn snapshot(&self) -> (Vec<(Option<u64>, u64)>, u64) {
+        let mut cumulative = 0;
+        let buckets = self.counts.iter().enumerate()
+            .map(|(slot, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (BUCKETS.get(slot).copied(), cumulative)
+            })
+            .collect();
+        (buckets, self.sum.load(Ordering::Relaxed))
+    }
+}
+
+/// Middleware recording the Content-Length of ingest requests in the payload size histogram.
+/// Payloads above TAILMON_INGEST_WARN_BYTES are logged; requests without a length are not recorded.
+pub async fn record_ingest_size(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let length = request.headers().get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+
+    if let Some(length) = length {
+        state.ingest_bytes.observe(length);
+        if length > state.config().ingest_warn_bytes {
+            warn!("Oversized ingest payload on {} {}: {} (threshold {})",
+                method, path, format_bytes(length), format_bytes(state.config().ingest_warn_bytes));
+        }
+    }
+
+    let response = next.run(request).await;
+    match length {
+        Some(length) => debug!("{} {}: {} bytes -> {}", method, path, length, response.status()),
+        None => debug!("{} {}: unknown length -> {}", method, path, response.status()),
+    }
+    response
+}
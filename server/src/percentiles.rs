@@ -0,0 +1,188 @@
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use tdigest::TDigest;
+
+/// Buckets the window is split into; a bucket leaves the window as a whole
+const BUCKETS: i64 = 60;
+
+/// Values a bucket buffers before merging them into its digest
+const PENDING_MAX: usize = 32;
+
+/// Centroids kept per digest; more is more accurate in the tails and costs more memory
+const DIGEST_SIZE: usize = 100;
+
+/// Quantiles returned when the request names none
+const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.95, 0.99];
+
+/// Most quantiles one request may ask for
+const MAX_QUANTILES: usize = 16;
+
+/// Samples of one slice of the window, summarized as a t-digest
+#[derive(Clone)]
+struct Bucket {
+    /// Start of the bucket in Unix seconds
+    start: i64,
+    digest: TDigest,
+
+    /// Values not merged into the digest yet
+    pending: Vec<f64>,
+}
+
+impl Bucket {
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.digest = self.digest.merge_unsorted(std::mem::take(&mut self.pending));
+        }
+    }
+}
+
+/// CPU usage of one device over the retained window, as time-bucketed t-digests so quantiles
+/// are answered without scanning the history and old samples expire with their bucket
+#[derive(Clone, Default)]
+pub struct RollingQuantiles {
+    /// Oldest first
+    buckets: VecDeque<Bucket>,
+}
+
+/// Width of a bucket in seconds for a window
+fn bucket_secs(window_secs: u64) -> i64 {
+    (window_secs as i64 / BUCKETS).max(1)
+}
+
+impl RollingQuantiles {
+    /// Add a sample's value at its sample time; values already outside the window are ignored
+    pub fn record(&mut self, value: f64, at: DateTime<Utc>, window_secs: u64, now: DateTime<Utc>) {
+        if !value.is_finite() || at.timestamp() <= now.timestamp() - window_secs as i64 {
+            return;
+        }
+        let width = bucket_secs(window_secs);
+        let start = at.timestamp().div_euclid(width) * width;
+        let position = self.buckets.partition_point(|bucket| bucket.start < start);
+        if self.buckets.get(position).is_none_or(|bucket| bucket.start != start) {
+            let bucket = Bucket { start, digest: TDigest::new_with_size(DIGEST_SIZE), pending: Vec::new() };
+            self.buckets.insert(position, bucket);
+        }
+        let bucket = &mut self.buckets[position];
+        bucket.pending.push(value);
+        if bucket.pending.len() >= PENDING_MAX {
+            bucket.flush();
+        }
+        self.trim(window_secs, now);
+    }
+
+    /// Drop the buckets that ended before the window
+    pub fn trim(&mut self, window_secs: u64, now: DateTime<Utc>) {
+        let width = bucket_secs(window_secs);
+        let cutoff = now.timestamp() - window_secs as i64;
+        while self.buckets.front().is_some_and(|bucket| bucket.start + width <= cutoff) {
+            self.buckets.pop_front();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Fold another device's buckets into this one (after a rename)
+    pub fn merge(&mut self, other: RollingQuantiles) {
+        for mut bucket in other.buckets {
+            bucket.flush();
+            let position = self.buckets.partition_point(|existing| existing.start < bucket.start);
+            match self.buckets.get_mut(position) {
+                Some(existing) if existing.start == bucket.start => {
+                    existing.flush();
+                    existing.digest = TDigest::merge_digests(vec![existing.digest.clone(), bucket.digest]);
+                }
+                _ => self.buckets.insert(position, bucket),
+            }
+        }
+    }
+
+    /// The whole window as one digest
+    fn digest(&self) -> TDigest {
+        let digests = self.buckets.iter()
+            .map(|bucket| bucket.digest.merge_unsorted(bucket.pending.clone()))
+            .collect();
+        TDigest::merge_digests(digests)
+    }
+}
+
+/// Query parameters for the percentiles endpoint
+#[derive(Debug, Deserialize)]
+pub struct PercentilesQuery {
+    /// Comma-separated quantiles between 0 and 1 (default: 0.5,0.95,0.99)
+    quantiles: Option<String>,
+}
+
+/// Response of the percentiles endpoint
+#[derive(Debug, Serialize)]
+pub struct PercentilesResponse {
+    device_id: String,
+    window_secs: u64,
+
+    /// Samples in the window
+    samples: u64,
+
+    /// CPU usage at each quantile, keyed like "p95" (empty without samples)
+    cpu_usage: BTreeMap<String, f64>,
+}
+
+/// Parse `0.5,0.95,0.99` into quantiles
+fn parse_quantiles(raw: &str) -> Result<Vec<f64>, String> {
+    let quantiles = raw.split(',')
+        .map(str::trim)
+        .filter(|quantile| !quantile.is_empty())
+        .map(|quantile| match quantile.parse::<f64>() {
+            Ok(q) if (0.0..=1.0).contains(&q) => Ok(q),
+            _ => Err(format!("Invalid quantile '{}': expected a number between 0 and 1", quantile)),
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    match quantiles.len() {
+        0 => Err("No quantiles given".to_string()),
+        len if len > MAX_QUANTILES => Err(format!("At most {} quantiles can be requested", MAX_QUANTILES)),
+        _ => Ok(quantiles),
+    }
+}
+
+/// "p50", "p99.9"
+fn quantile_label(q: f64) -> String {
+    format!("p{}", (q * 100.0 * 1e6).round() / 1e6)
+}
+
+/// Handler function to get CPU usage percentiles of one device over the retained window
+pub async fn get_percentiles(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<PercentilesQuery>,
+) -> Result<JsonResponse<PercentilesResponse>, (StatusCode, String)> {
+    let quantiles = match query.quantiles.as_deref() {
+        Some(raw) => parse_quantiles(raw).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => DEFAULT_QUANTILES.to_vec(),
+    };
+    if state.store.get(&device_id).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("Unknown device: {}", device_id)));
+    }
+
+    let window_secs = state.config().history_retention_secs;
+    let digest = match state.percentiles.get_mut(&device_id) {
+        Some(mut rolling) => {
+            rolling.trim(window_secs, Utc::now());
+            rolling.digest()
+        }
+        None => TDigest::default(),
+    };
+    let cpu_usage = if digest.is_empty() {
+        BTreeMap::new()
+    } else {
+        quantiles.iter().map(|q| (quantile_label(*q), digest.estimate_quantile(*q))).collect()
+    };
+    Ok(JsonResponse(PercentilesResponse { device_id, window_secs, samples: digest.count() as u64, cpu_usage }))
+}
@@ -0,0 +1,206 @@
+use common::{BatteryInfo, HardwareInfo, ProcessUsage, SystemInfo};
+use std::collections::HashMap;
+
+/// Content type of protobuf ingest bodies
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// `tailmon.SystemInfo` from proto/tailmon.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SystemInfoProto {
+    #[prost(string, tag = "1")]
+    pub device_id: String,
+    #[prost(string, tag = "2")]
+    pub os_info: String,
+    #[prost(float, tag = "3")]
+    pub cpu_usage: f32,
+    #[prost(uint64, tag = "4")]
+    pub ram_used_mb: u64,
+    #[prost(uint64, tag = "5")]
+    pub ram_total_mb: u64,
+    #[prost(uint64, tag = "6")]
+    pub collect_duration_ms: u64,
+    #[prost(string, tag = "7")]
+    pub last_seen: String,
+    #[prost(uint64, tag = "8")]
+    pub swap_used_mb: u64,
+    #[prost(uint64, tag = "9")]
+    pub swap_total_mb: u64,
+    #[prost(double, optional, tag = "10")]
+    pub net_rx_rate_bytes_per_sec: Option<f64>,
+    #[prost(double, optional, tag = "11")]
+    pub net_tx_rate_bytes_per_sec: Option<f64>,
+    #[prost(map = "string, string", tag = "12")]
+    pub ui_meta: HashMap<String, String>,
+    /// JSON document per custom metric
+    #[prost(map = "string, string", tag = "13")]
+    pub custom: HashMap<String, String>,
+    #[prost(string, repeated, tag = "14")]
+    pub capabilities: Vec<String>,
+    #[prost(string, optional, tag = "15")]
+    pub checksum: Option<String>,
+    #[prost(string, repeated, tag = "16")]
+    pub via: Vec<String>,
+    #[prost(message, optional, tag = "17")]
+    pub battery: Option<BatteryProto>,
+    #[prost(map = "string, message", tag = "18")]
+    pub watched_processes: HashMap<String, ProcessUsageProto>,
+    #[prost(message, optional, tag = "19")]
+    pub hardware: Option<HardwareProto>,
+    #[prost(string, optional, tag = "20")]
+    pub idempotency_key: Option<String>,
+}
+
+/// `tailmon.Battery` from proto/tailmon.proto
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct BatteryProto {
+    #[prost(float, tag = "1")]
+    pub percent: f32,
+    #[prost(bool, tag = "2")]
+    pub charging: bool,
+}
+
+/// `tailmon.ProcessUsage` from proto/tailmon.proto
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct ProcessUsageProto {
+    #[prost(float, tag = "1")]
+    pub cpu_usage: f32,
+    #[prost(uint64, tag = "2")]
+    pub memory_mb: u64,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+
+/// `tailmon.Hardware` from proto/tailmon.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HardwareProto {
+    #[prost(string, optional, tag = "1")]
+    pub uuid: Option<String>,
+    #[prost(string, tag = "2")]
+    pub arch: String,
+    #[prost(string, optional, tag = "3")]
+    pub cpu_brand: Option<String>,
+    #[prost(uint32, optional, tag = "4")]
+    pub cpu_count: Option<u32>,
+}
+
+/// Content type is protobuf (parameters ignored)
+pub fn is_protobuf(content_type: &str) -> bool {
+    content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(PROTOBUF_CONTENT_TYPE)
+}
+
+/// Decode a protobuf body into the same SystemInfo the JSON path produces
+pub fn decode(body: &[u8]) -> Result<SystemInfo, String> {
+    let message = <SystemInfoProto as prost::Message>::decode(body).map_err(|e| format!("invalid protobuf body: {}", e))?;
+    let custom = message.custom.into_iter()
+        .map(|(key, json)| {
+            let value = serde_json::from_str(&json).map_err(|e| format!("custom metric {} is not valid JSON: {}", key, e))?;
+            Ok((key, value))
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(SystemInfo {
+        device_id: message.device_id,
+        os_info: message.os_info,
+        cpu_usage: message.cpu_usage,
+        ram_used_mb: message.ram_used_mb,
+        ram_total_mb: message.ram_total_mb,
+        collect_duration_ms: message.collect_duration_ms,
+        last_seen: message.last_seen,
+        swap_used_mb: message.swap_used_mb,
+        swap_total_mb: message.swap_total_mb,
+        net_rx_rate_bytes_per_sec: message.net_rx_rate_bytes_per_sec,
+        net_tx_rate_bytes_per_sec: message.net_tx_rate_bytes_per_sec,
+        ui_meta: message.ui_meta,
+        custom,
+        capabilities: message.capabilities,
+        checksum: message.checksum,
+        via: message.via,
+        battery: message.battery.map(|battery| BatteryInfo { percent: battery.percent, charging: battery.charging }),
+        watched_processes: message.watched_processes.into_iter()
+            .map(|(name, usage)| (name, ProcessUsage { cpu_usage: usage.cpu_usage, memory_mb: usage.memory_mb, count: usage.count }))
+            .collect(),
+        hardware: message.hardware.map(|hardware| HardwareInfo {
+            uuid: hardware.uuid,
+            arch: hardware.arch,
+            cpu_brand: hardware.cpu_brand,
+            cpu_count: hardware.cpu_count,
+        }),
+        idempotency_key: message.idempotency_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::ingest;
+    use prost::Message;
+    use serde_json::json;
+
+    /// The shared sample with every optional field set, sealed like an agent sends it
+    fn sample() -> SystemInfo {
+        let mut info = common::test_util::sample();
+        info.collect_duration_ms = 40;
+        info.swap_used_mb = 128;
+        info.swap_total_mb = 1024;
+        info.net_rx_rate_bytes_per_sec = Some(1500.25);
+        info.net_tx_rate_bytes_per_sec = Some(320.5);
+        info.ui_meta = HashMap::from([("icon".to_string(), "🖥️".to_string()), ("color".to_string(), "#4facfe".to_string())]);
+        info.custom = HashMap::from([("queue_depth".to_string(), json!(7)), ("build".to_string(), json!({"sha": "abc123"}))]);
+        info.capabilities = ["swap", "net_rates", "custom", "battery", "processes"].map(String::from).to_vec();
+        info.via = vec!["edge-1".to_string()];
+        info.battery = Some(BatteryInfo { percent: 81.5, charging: true });
+        info.watched_processes = HashMap::from([("nginx".to_string(), ProcessUsage { cpu_usage: 3.25, memory_mb: 96, count: 4 })]);
+        info.hardware = Some(HardwareInfo {
+            uuid: Some("4c4c4544-0042".to_string()),
+            arch: "x86_64".to_string(),
+            cpu_brand: Some("EPYC".to_string()),
+            cpu_count: Some(8),
+        });
+        info.idempotency_key = Some("1b4e28ba-2fa1-4d3b-a3f5-ef19b5a7633b".to_string());
+        info.seal();
+        info
+    }
+
+    /// Encode a sample the way an agent would for `Content-Type: application/x-protobuf`
+    fn encode(info: &SystemInfo) -> Vec<u8> {
+        SystemInfoProto {
+            device_id: info.device_id.clone(),
+            os_info: info.os_info.clone(),
+            cpu_usage: info.cpu_usage,
+            ram_used_mb: info.ram_used_mb,
+            ram_total_mb: info.ram_total_mb,
+            collect_duration_ms: info.collect_duration_ms,
+            last_seen: info.last_seen.clone(),
+            swap_used_mb: info.swap_used_mb,
+            swap_total_mb: info.swap_total_mb,
+            net_rx_rate_bytes_per_sec: info.net_rx_rate_bytes_per_sec,
+            net_tx_rate_bytes_per_sec: info.net_tx_rate_bytes_per_sec,
+            ui_meta: info.ui_meta.clone(),
+            custom: info.custom.iter().map(|(key, value)| (key.clone(), value.to_string())).collect(),
+            capabilities: info.capabilities.clone(),
+            checksum: info.checksum.clone(),
+            via: info.via.clone(),
+            battery: info.battery.map(|battery| BatteryProto { percent: battery.percent, charging: battery.charging }),
+            watched_processes: info.watched_processes.iter()
+                .map(|(name, usage)| (name.clone(), ProcessUsageProto { cpu_usage: usage.cpu_usage, memory_mb: usage.memory_mb, count: usage.count }))
+                .collect(),
+            hardware: info.hardware.clone().map(|hardware| HardwareProto {
+                uuid: hardware.uuid,
+                arch: hardware.arch,
+                cpu_brand: hardware.cpu_brand,
+                cpu_count: hardware.cpu_count,
+            }),
+            idempotency_key: info.idempotency_key.clone(),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn json_and_protobuf_store_identical_records() {
+        let info = sample();
+        let from_json = ingest("application/json", serde_json::to_vec(&info).unwrap());
+        let from_protobuf = ingest(PROTOBUF_CONTENT_TYPE, encode(&info));
+        assert_eq!(from_json, from_protobuf);
+        assert_eq!(from_json["custom"]["build"]["sha"], "abc123");
+    }
+}
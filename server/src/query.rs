@@ -0,0 +1,459 @@
+use crate::record::DeviceRecord;
+use serde_json::Value;
+use std::fmt;
+
+/// Longest expression accepted, so a query string cannot make the parser recurse without bound
+const MAX_EXPRESSION_LEN: usize = 1024;
+
+/// Comparison operator between a field and a literal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+
+    /// Case-insensitive substring match
+    Contains,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Contains => "~",
+        })
+    }
+}
+
+/// Literal on the right-hand side of a comparison
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+/// Parsed filter expression, e.g. `cpu_usage > 80 AND os_info ~ "Ubuntu"`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// `field op literal`; the field is a record key, optionally followed by `.key` segments
+    /// into objects such as `ui_meta`, `custom` or `location`
+    Compare { path: Vec<String>, op: Operator, literal: Literal },
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(Operator),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Text(text) => write!(f, "\"{}\"", text),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::And => f.write_str("AND"),
+            Token::Or => f.write_str("OR"),
+            Token::Not => f.write_str("NOT"),
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+        }
+    }
+}
+
+/// Split an expression into tokens, each with its character position for error messages
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => { i += 1; Token::Open }
+            ')' => { i += 1; Token::Close }
+            '~' => { i += 1; Token::Op(Operator::Contains) }
+            '>' | '<' | '=' | '!' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('>', Some('=')) => (Operator::Ge, 2),
+                    ('>', _) => (Operator::Gt, 1),
+                    ('<', Some('=')) => (Operator::Le, 2),
+                    ('<', _) => (Operator::Lt, 1),
+                    ('=', Some('=')) => (Operator::Eq, 2),
+                    ('=', _) => (Operator::Eq, 1),
+                    ('!', Some('=')) => (Operator::Ne, 2),
+                    _ => return Err(format!("unexpected '!' at position {} (use NOT or !=)", start)),
+                };
+                i += len;
+                Token::Op(op)
+            }
+            '"' | '\'' => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(format!("unterminated string starting at position {}", start)),
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&quote) if quote == c => {
+                            i += 1;
+                            break;
+                        }
+                        Some(&other) => {
+                            text.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Text(text)
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '.' | '-' | '+')) {
+                    i += 1;
+                }
+                let raw: String = chars[start..i].iter().collect();
+                match raw.parse::<f64>() {
+                    Ok(number) if number.is_finite() => Token::Number(number),
+                    _ => return Err(format!("invalid number '{}' at position {}", raw, start)),
+                }
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '.' | '-')) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.to_ascii_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Ident(word),
+                }
+            }
+            _ => return Err(format!("unexpected character '{}' at position {}", c, start)),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser over the token list. NOT binds tighter than AND, AND tighter than OR.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+
+    /// Length of the input, reported as the position of the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    /// Error for an unexpected token (or the end of the input) where `expected` should be
+    fn unexpected(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(token) => format!("expected {} at position {}, found {}", expected, self.position(), token),
+            None => format!("expected {} at position {}, found end of expression", expected, self.end),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.advance();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.advance();
+                let inner = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.unexpected("')'"));
+                }
+                self.advance();
+                Ok(inner)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let field_position = self.position();
+        let Some(Token::Ident(field)) = self.peek().cloned() else {
+            return Err(self.unexpected("a field name"));
+        };
+        self.advance();
+        let path: Vec<String> = field.split('.').map(str::to_string).collect();
+        if !DeviceRecord::FIELDS.contains(&path[0].as_str()) {
+            return Err(format!("unknown field '{}' at position {}, expected one of: {}",
+                path[0], field_position, DeviceRecord::FIELDS.join(", ")));
+        }
+        if path.iter().any(String::is_empty) {
+            return Err(format!("invalid field '{}' at position {}", field, field_position));
+        }
+
+        let Some(Token::Op(op)) = self.peek().cloned() else {
+            return Err(self.unexpected("an operator (==, !=, >, >=, <, <=, ~)"));
+        };
+        self.advance();
+
+        let literal_position = self.position();
+        let literal = match self.advance() {
+            Some(Token::Number(number)) => Literal::Number(number),
+            Some(Token::Text(text)) => Literal::Text(text),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("true") => Literal::Bool(true),
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("false") => Literal::Bool(false),
+            _ => {
+                self.next -= 1;
+                return Err(self.unexpected(&format!("a number or quoted string after '{}'", op)));
+            }
+        };
+        match (&literal, op) {
+            (Literal::Text(_), Operator::Eq | Operator::Ne | Operator::Contains) => {}
+            (Literal::Text(_), _) => {
+                return Err(format!("operator '{}' at position {} needs a number, not a string", op, literal_position));
+            }
+            (_, Operator::Contains) => {
+                return Err(format!("operator '~' needs a quoted string at position {}", literal_position));
+            }
+            (Literal::Bool(_), Operator::Eq | Operator::Ne) => {}
+            (Literal::Bool(_), _) => {
+                return Err(format!("operator '{}' at position {} cannot compare booleans", op, literal_position));
+            }
+            (Literal::Number(_), _) => {}
+        }
+        Ok(Expr::Compare { path, op, literal })
+    }
+}
+
+impl Expr {
+    /// Parse a filter expression, e.g. `cpu_usage > 80 AND os_info ~ "Ubuntu"`.
+    /// Errors name the problem and its character position.
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        if input.chars().count() > MAX_EXPRESSION_LEN {
+            return Err(format!("expression is longer than {} characters", MAX_EXPRESSION_LEN));
+        }
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, next: 0, end: input.chars().count() };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("AND, OR or the end of the expression"));
+        }
+        Ok(expr)
+    }
+
+    /// Whether the record (in its serialized form) matches. A comparison against a field the
+    /// record does not carry, or of a different type, does not match.
+    pub fn matches(&self, record: &Value) -> bool {
+        match self {
+            Expr::Compare { path, op, literal } => {
+                let value = path.iter().try_fold(record, |value, key| value.get(key));
+                value.is_some_and(|value| compare(value, *op, literal))
+            }
+            Expr::Not(inner) => !inner.matches(record),
+            Expr::And(left, right) => left.matches(record) && right.matches(record),
+            Expr::Or(left, right) => left.matches(record) || right.matches(record),
+        }
+    }
+}
+
+fn compare(value: &Value, op: Operator, literal: &Literal) -> bool {
+    match (value, literal) {
+        (Value::Number(number), Literal::Number(expected)) => {
+            let Some(actual) = number.as_f64() else {
+                return false;
+            };
+            match op {
+                Operator::Eq => actual == *expected,
+                Operator::Ne => actual != *expected,
+                Operator::Gt => actual > *expected,
+                Operator::Ge => actual >= *expected,
+                Operator::Lt => actual < *expected,
+                Operator::Le => actual <= *expected,
+                Operator::Contains => false,
+            }
+        }
+        (Value::String(actual), Literal::Text(expected)) => match op {
+            Operator::Eq => actual == expected,
+            Operator::Ne => actual != expected,
+            Operator::Contains => actual.to_lowercase().contains(&expected.to_lowercase()),
+            _ => false,
+        },
+        // `capabilities ~ "swap"`, `via ~ "relay"`
+        (Value::Array(items), Literal::Text(expected)) if op == Operator::Contains => items.iter()
+            .any(|item| item.as_str().is_some_and(|item| item.to_lowercase().contains(&expected.to_lowercase()))),
+        (Value::Bool(actual), Literal::Bool(expected)) => match op {
+            Operator::Eq => actual == expected,
+            Operator::Ne => actual != expected,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Value {
+        json!({
+            "device_id": "web-1",
+            "os_info": "Ubuntu 22.04",
+            "cpu_usage": 85.5,
+            "ram_used_mb": 2048,
+            "ui_meta": {"role": "db"},
+            "capabilities": ["swap", "net_rates"],
+            "conflicted": false,
+        })
+    }
+
+    fn matches(input: &str) -> bool {
+        Expr::parse(input).unwrap_or_else(|e| panic!("{}: {}", input, e)).matches(&record())
+    }
+
+    fn compare(field: &str, op: Operator, literal: Literal) -> Expr {
+        Expr::Compare { path: vec![field.to_string()], op, literal }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let number = |field: &str, value| compare(field, Operator::Gt, Literal::Number(value));
+        assert_eq!(Expr::parse("cpu_usage > 1 OR cpu_usage > 2 AND ram_used_mb > 3").unwrap(), Expr::Or(
+            Box::new(number("cpu_usage", 1.0)),
+            Box::new(Expr::And(Box::new(number("cpu_usage", 2.0)), Box::new(number("ram_used_mb", 3.0)))),
+        ));
+        assert!(matches("cpu_usage > 80 OR cpu_usage > 90 AND ram_used_mb > 4096"));
+        assert!(!matches("(cpu_usage > 80 OR cpu_usage > 90) AND ram_used_mb > 4096"));
+        assert!(matches("NOT cpu_usage < 50 and os_info ~ 'ubuntu'"));
+        assert!(!matches("NOT (cpu_usage > 50 or ram_used_mb > 0)"));
+    }
+
+    #[test]
+    fn every_operator_compares_numbers() {
+        let cases = [
+            ("cpu_usage == 85.5", true), ("cpu_usage = 85.5", true), ("cpu_usage != 85.5", false),
+            ("cpu_usage > 85", true), ("cpu_usage > 85.5", false), ("cpu_usage >= 85.5", true),
+            ("cpu_usage < 86", true), ("cpu_usage < 85.5", false), ("cpu_usage <= 85.5", true),
+            ("ram_used_mb == 2048", true), ("ram_used_mb > -1", true), ("ram_used_mb < 2e3", false),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(matches(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn strings_booleans_and_nested_fields() {
+        assert!(matches(r#"os_info == "Ubuntu 22.04""#));
+        assert!(matches("os_info != 'Debian'"));
+        assert!(matches(r#"device_id == "web\-1""#));
+        assert!(matches(r#"ui_meta.role == "db""#));
+        assert!(matches("conflicted == false"));
+        assert!(!matches("conflicted != FALSE"));
+
+        // Missing fields and mismatched types never match, not even with !=
+        assert!(!matches("ui_meta.zone == 'eu'"));
+        assert!(!matches("ui_meta.zone != 'eu'"));
+        assert!(!matches("os_info == 22"));
+    }
+
+    #[test]
+    fn contains_is_case_insensitive_and_searches_lists() {
+        assert!(matches("os_info ~ 'UBUNTU'"));
+        assert!(matches("os_info ~ \"22.0\""));
+        assert!(!matches("os_info ~ 'debian'"));
+        assert!(matches("capabilities ~ 'NET'"));
+        assert!(!matches("capabilities ~ 'battery'"));
+    }
+
+    #[test]
+    fn parse_errors_name_the_problem_and_position() {
+        let cases = [
+            ("", "expected a field name at position 0, found end of expression"),
+            ("cpu_usage >", "expected a number or quoted string after '>' at position 11, found end of expression"),
+            ("cpu_usage 80", "expected an operator (==, !=, >, >=, <, <=, ~) at position 10, found 80"),
+            ("cpu_usage > 80 os_info", "expected AND, OR or the end of the expression at position 15, found 'os_info'"),
+            ("(cpu_usage > 80", "expected ')' at position 15, found end of expression"),
+            ("os_info ~ 'ubuntu", "unterminated string starting at position 10"),
+            ("cpu_usage > 8x", "invalid number '8x' at position 12"),
+            ("cpu_usage ! 80", "unexpected '!' at position 10 (use NOT or !=)"),
+            ("cpu_usage > 80 & ram_used_mb > 1", "unexpected character '&' at position 15"),
+            ("os_info > 'a'", "operator '>' at position 10 needs a number, not a string"),
+            ("cpu_usage ~ 80", "operator '~' needs a quoted string at position 12"),
+            ("conflicted > true", "operator '>' at position 13 cannot compare booleans"),
+            ("ui_meta..role == 'db'", "invalid field 'ui_meta..role' at position 0"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(Expr::parse(input).unwrap_err(), expected, "{}", input);
+        }
+
+        let unknown = Expr::parse("hostname == 'web-1'").unwrap_err();
+        assert!(unknown.starts_with("unknown field 'hostname' at position 0, expected one of: device_id, "), "{}", unknown);
+    }
+
+    #[test]
+    fn overlong_expressions_are_rejected() {
+        let longest = format!("os_info ~ '{}'", "a".repeat(MAX_EXPRESSION_LEN - "os_info ~ ''".len()));
+        assert!(Expr::parse(&longest).is_ok());
+        assert_eq!(Expr::parse(&format!("{} ", longest)).unwrap_err(), format!("expression is longer than {} characters", MAX_EXPRESSION_LEN));
+
+        // Deep nesting within the limit parses without exhausting the stack
+        let nested = format!("{}cpu_usage > 1{}", "(".repeat(500), ")".repeat(500));
+        assert!(Expr::parse(&nested).is_ok());
+    }
+}
@@ -0,0 +1,54 @@
+use dashmap::DashMap;
+use hickory_resolver::TokioAsyncResolver;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// A cached reverse lookup result (negative results are cached too)
+#[derive(Debug, Clone)]
+struct CachedName {
+    name: Option<String>,
+    expires: Instant,
+}
+
+/// Reverse DNS lookups of device source addresses with a TTL cache
+pub struct ReverseDns {
+    resolver: TokioAsyncResolver,
+    cache: DashMap<IpAddr, CachedName>,
+    ttl: Duration,
+}
+
+impl ReverseDns {
+    /// Create a resolver from the system DNS configuration
+    pub fn from_system_conf(ttl: Duration) -> Result<Self, String> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| format!("failed to read system DNS configuration: {}", e))?;
+        Ok(ReverseDns { resolver, cache: DashMap::new(), ttl })
+    }
+
+    /// Cached name of `ip`, and whether a new lookup is due.
+    /// A due lookup is marked in flight so concurrent samples do not repeat it.
+    pub fn cached(&self, ip: IpAddr) -> (Option<String>, bool) {
+        let now = Instant::now();
+        let mut entry = self.cache.entry(ip).or_insert_with(|| CachedName { name: None, expires: now });
+        let name = entry.name.clone();
+        if entry.expires > now {
+            return (name, false);
+        }
+        entry.expires = now + self.ttl;
+        (name, true)
+    }
+
+    /// Resolve the PTR name of `ip` and cache it; `None` when the address has no name
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let name = match self.resolver.reverse_lookup(ip).await {
+            Ok(lookup) => lookup.iter().next().map(|name| name.to_utf8().trim_end_matches('.').to_string()),
+            Err(e) => {
+                debug!("Reverse lookup of {} failed: {}", ip, e);
+                None
+            }
+        };
+        self.cache.insert(ip, CachedName { name: name.clone(), expires: Instant::now() + self.ttl });
+        name
+    }
+}
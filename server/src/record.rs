@@ -0,0 +1,67 @@
+use crate::geoip::Location;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use common::SystemInfo;
+use serde::Serialize;
+
+/// A device's latest sample together with server-side enrichment.
+/// Serializes as the flat SystemInfo object plus the enrichment fields.
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceRecord {
+    #[serde(flatten)]
+    pub info: SystemInfo,
+
+    /// When the server first received a sample from this device_id
+    pub first_seen: DateTime<Utc>,
+
+    /// Server-computed 0–100 health summary (see `health::health_score`)
+    pub health_score: f64,
+
+    /// GeoIP location resolved from the source address of the first report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+
+    /// Reverse DNS name of the device's source address (TAILMON_REVERSE_DNS=1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_name: Option<String>,
+
+    /// The device's OS or memory size keeps flipping, as if several machines shared its device_id
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub conflicted: bool,
+
+    /// Resource profile the device fits (TAILMON_DEVICE_CLASSES), computed on ingest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+}
+
+impl DeviceRecord {
+    /// Every key of a serialized record, for validating `?fields=` projections
+    pub const FIELDS: &'static [&'static str] = &[
+        "device_id", "os_info", "cpu_usage", "ram_used_mb", "ram_total_mb", "collect_duration_ms", "last_seen",
+        "swap_used_mb", "swap_total_mb", "net_rx_rate_bytes_per_sec", "net_tx_rate_bytes_per_sec", "ui_meta",
+        "custom", "capabilities", "checksum", "via", "first_seen", "health_score", "location", "resolved_name",
+        "conflicted", "battery", "watched_processes", "hardware", "idempotency_key", "device_class",
+    ];
+
+    /// The serialized record as a JSON object. Goes through the JSON text so f32 values
+    /// keep the short form of the full response.
+    pub fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::to_string(self).ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The serialized record reduced to `fields`; fields the record does not carry are left out
+    pub fn project(&self, fields: &[&str]) -> serde_json::Map<String, serde_json::Value> {
+        let mut object = self.to_json();
+        object.retain(|key, _| fields.contains(&key.as_str()));
+        object
+    }
+}
+
+/// The exact body of a device's last sample, as sent by the agent
+#[derive(Debug, Clone)]
+pub struct RawPayload {
+    pub content_type: String,
+    pub body: Bytes,
+}
@@ -0,0 +1,111 @@
+use crate::{AppState, SortMetric};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Json as JsonResponse;
+use chrono::{DateTime, Duration, Utc};
+use common::humanize::format_duration;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// How the values of a device within the range are summarized
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// Average weighted by the samples folded into each point
+    Avg,
+    Min,
+    Max,
+
+    /// 95th percentile (nearest rank) of the point values
+    P95,
+}
+
+impl Aggregation {
+    /// Aggregate (value, samples) pairs; `None` when there are none
+    fn apply(self, mut values: Vec<(f64, u32)>) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+        let aggregate = match self {
+            Aggregation::Avg => {
+                let samples: f64 = values.iter().map(|(_, samples)| (*samples).max(1) as f64).sum();
+                values.iter().map(|(value, samples)| value * (*samples).max(1) as f64).sum::<f64>() / samples
+            }
+            Aggregation::Min => values.iter().map(|(value, _)| *value).fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().map(|(value, _)| *value).fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::P95 => {
+                values.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let rank = (values.len() * 95).div_ceil(100).max(1);
+                values[rank - 1].0
+            }
+        };
+        Some(aggregate)
+    }
+}
+
+/// Query parameters for the report endpoint
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    metric: SortMetric,
+    agg: Aggregation,
+
+    /// Start and end of the range (RFC 3339)
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+/// Summary of one device within the range
+#[derive(Debug, Serialize)]
+pub struct DeviceReport {
+    value: f64,
+
+    /// History points the value was computed from
+    points: usize,
+
+    /// The device's history starts after `from` (e.g. it joined during the range)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+}
+
+/// Response body of the report endpoint
+#[derive(Debug, Serialize)]
+pub struct ReportResponse {
+    metric: SortMetric,
+    agg: Aggregation,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    devices: BTreeMap<String, DeviceReport>,
+}
+
+/// Handler function to aggregate a metric over a time range, per device.
+/// Devices without history in the range are left out.
+pub async fn get_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReportQuery>,
+) -> Result<JsonResponse<ReportResponse>, (StatusCode, String)> {
+    if query.from >= query.to {
+        return Err((StatusCode::BAD_REQUEST, "'from' must be before 'to'".to_string()));
+    }
+    let retention_secs = state.config().history_retention_secs;
+    if query.from < Utc::now() - Duration::seconds(retention_secs as i64) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY,
+            format!("History does not cover the range: only the last {} are retained", format_duration(retention_secs))));
+    }
+
+    let mut devices = BTreeMap::new();
+    for (device_id, buffer) in state.store.all_history() {
+        let values: Vec<(f64, u32)> = buffer.iter()
+            .filter(|point| !point.synthetic && point.timestamp >= query.from && point.timestamp <= query.to)
+            .map(|point| (query.metric.value(&point.info), point.samples))
+            .collect();
+        let points = values.len();
+        let Some(value) = query.agg.apply(values) else {
+            continue;
+        };
+        let partial = buffer.front().is_some_and(|oldest| oldest.timestamp > query.from);
+        devices.insert(device_id, DeviceReport { value, points, partial });
+    }
+
+    Ok(JsonResponse(ReportResponse { metric: query.metric, agg: query.agg, from: query.from, to: query.to, devices }))
+}
@@ -0,0 +1,227 @@
+use common::{SystemInfo, CAPABILITY_BATTERY, CAPABILITY_CUSTOM, CAPABILITY_NET_RATES, CAPABILITY_PROCESSES, CAPABILITY_SWAP};
+use serde::Serialize;
+use std::fmt;
+
+/// Optional SystemInfo fields the server can drop from stored records (TAILMON_RETAIN_FIELDS).
+/// Identity, CPU, RAM and timestamp fields are always kept.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainField {
+    Swap,
+    NetRates,
+    UiMeta,
+    Custom,
+    Battery,
+    WatchedProcesses,
+    Hardware,
+}
+
+impl RetainField {
+    pub const ALL: [RetainField; 7] = [
+        RetainField::Swap, RetainField::NetRates, RetainField::UiMeta, RetainField::Custom, RetainField::Battery,
+        RetainField::WatchedProcesses, RetainField::Hardware,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "swap" => Some(RetainField::Swap),
+            "net_rates" => Some(RetainField::NetRates),
+            "ui_meta" => Some(RetainField::UiMeta),
+            "custom" => Some(RetainField::Custom),
+            "battery" => Some(RetainField::Battery),
+            "watched_processes" => Some(RetainField::WatchedProcesses),
+            "hardware" => Some(RetainField::Hardware),
+            _ => None,
+        }
+    }
+}
+
+/// Clear every optional field of a sample that is not in `retained`, along with its capability.
+/// The checksum is dropped since it no longer matches the stored record.
+fn strip(system_info: &mut SystemInfo, retained: &[RetainField]) {
+    system_info.checksum = None;
+    for field in RetainField::ALL.iter().filter(|field| !retained.contains(field)) {
+        match field {
+            RetainField::Swap => {
+                system_info.swap_used_mb = 0;
+                system_info.swap_total_mb = 0;
+                system_info.remove_capability(CAPABILITY_SWAP);
+            }
+            RetainField::NetRates => {
+                system_info.net_rx_rate_bytes_per_sec = None;
+                system_info.net_tx_rate_bytes_per_sec = None;
+                system_info.remove_capability(CAPABILITY_NET_RATES);
+            }
+            RetainField::UiMeta => system_info.ui_meta.clear(),
+            RetainField::Custom => {
+                system_info.custom.clear();
+                system_info.remove_capability(CAPABILITY_CUSTOM);
+            }
+            RetainField::Battery => {
+                system_info.battery = None;
+                system_info.remove_capability(CAPABILITY_BATTERY);
+            }
+            RetainField::WatchedProcesses => {
+                system_info.watched_processes.clear();
+                system_info.remove_capability(CAPABILITY_PROCESSES);
+            }
+            RetainField::Hardware => system_info.hardware = None,
+        }
+    }
+}
+
+/// Devices a retention rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleSelector {
+    /// device_id pattern where `*` stands for any run of characters, e.g. `laptop-*`
+    DeviceId(String),
+
+    /// A `ui_meta` label reported by the agent (TAILMON_META), e.g. `role=laptop`
+    Label { key: String, value: String },
+}
+
+impl fmt::Display for RuleSelector {
+    /// The selector as written in TAILMON_RETAIN_RULES
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleSelector::DeviceId(pattern) => write!(f, "{}", pattern),
+            RuleSelector::Label { key, value } => write!(f, "{}={}", key, value),
+        }
+    }
+}
+
+impl RuleSelector {
+    fn matches(&self, system_info: &SystemInfo) -> bool {
+        match self {
+            RuleSelector::DeviceId(pattern) => wildcard_match(pattern, &system_info.device_id),
+            RuleSelector::Label { key, value } => system_info.ui_meta.get(key) == Some(value),
+        }
+    }
+}
+
+/// Optional fields kept for one group of devices (TAILMON_RETAIN_RULES)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetainRule {
+    pub selector: RuleSelector,
+    pub fields: Vec<RetainField>,
+}
+
+impl RetainRule {
+    /// Parse `selector:field,field`, where the selector is a device_id pattern or a `key=value`
+    /// label. The field list may be empty to keep only the core fields.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let (selector, fields) = entry.rsplit_once(':')?;
+        let selector = match selector.trim().split_once('=') {
+            Some((key, value)) => RuleSelector::Label { key: key.trim().to_string(), value: value.trim().to_string() },
+            None => RuleSelector::DeviceId(selector.trim().to_string()),
+        };
+        if matches!(&selector, RuleSelector::DeviceId(pattern) | RuleSelector::Label { key: pattern, .. } if pattern.is_empty()) {
+            return None;
+        }
+        let fields = fields.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(RetainField::parse)
+            .collect::<Option<Vec<_>>>()?;
+        Some(RetainRule { selector, fields })
+    }
+}
+
+/// Optional fields kept in stored records and history: per group of devices (TAILMON_RETAIN_RULES),
+/// otherwise the default (TAILMON_RETAIN_FIELDS)
+#[derive(Debug, Clone, Default)]
+pub struct Retention {
+    pub rules: Vec<RetainRule>,
+
+    /// `None` keeps every field
+    pub default: Option<Vec<RetainField>>,
+}
+
+impl Retention {
+    /// Strip the optional fields the sample's device does not retain
+    pub fn apply(&self, system_info: &mut SystemInfo) {
+        if let Some(retained) = fields_for(&self.rules, self.default.as_deref(), system_info) {
+            strip(system_info, retained);
+        }
+    }
+}
+
+/// Optional fields to keep for a sample: those of the first matching rule, otherwise
+/// `default` (TAILMON_RETAIN_FIELDS). `None` keeps every field.
+fn fields_for<'a>(rules: &'a [RetainRule], default: Option<&'a [RetainField]>, system_info: &SystemInfo) -> Option<&'a [RetainField]> {
+    rules.iter()
+        .find(|rule| rule.selector.matches(system_info))
+        .map(|rule| rule.fields.as_slice())
+        .or(default)
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters (including none)
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all: the whole text must equal the pattern
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_patterns() {
+        let cases = [
+            ("web-1", "web-1", true),
+            ("web-1", "web-10", false),
+            ("", "", true),
+            ("", "web-1", false),
+            ("*", "", true),
+            ("*", "anything", true),
+            ("laptop-*", "laptop-7", true),
+            ("laptop-*", "laptop-", true),
+            ("laptop-*", "my-laptop-7", false),
+            ("*-db", "eu-db", true),
+            ("*-db", "eu-db-2", false),
+            ("a*b*a", "aba", true),
+            ("a*b*a", "abba", true),
+            ("a*b*a", "ab", false),
+            ("a*b*a", "aa", false),
+            ("a*a", "a", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(wildcard_match(pattern, text), expected, "{:?} against {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn rule_parsing() {
+        assert_eq!(RetainRule::parse("laptop-*:custom, net_rates"), Some(RetainRule {
+            selector: RuleSelector::DeviceId("laptop-*".to_string()),
+            fields: vec![RetainField::Custom, RetainField::NetRates],
+        }));
+        assert_eq!(RetainRule::parse(" role = laptop :swap"), Some(RetainRule {
+            selector: RuleSelector::Label { key: "role".to_string(), value: "laptop".to_string() },
+            fields: vec![RetainField::Swap],
+        }));
+
+        // A trailing `:` keeps only the core fields; `*` alone selects every device
+        assert_eq!(RetainRule::parse("*:").unwrap(), RetainRule { selector: RuleSelector::DeviceId("*".to_string()), fields: Vec::new() });
+        assert_eq!(RetainRule::parse("zone=eu:1:custom").unwrap().selector, RuleSelector::Label { key: "zone".to_string(), value: "eu:1".to_string() });
+
+        for invalid in ["", ":", ":battery", " :swap", "=laptop:swap", "laptop-*", "laptop-*:swap,cpu"] {
+            assert_eq!(RetainRule::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+
+/// Counters of one server run, logged as a summary on graceful shutdown
+pub struct RunStats {
+    started: Instant,
+
+    /// Samples (full or patches) applied to the store
+    samples: AtomicU64,
+
+    /// Sample requests answered with a 4xx status (invalid, unauthorized, too large, ...)
+    rejected: AtomicU64,
+
+    /// Most devices stored at once
+    peak_devices: AtomicU64,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        RunStats {
+            started: Instant::now(),
+            samples: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            peak_devices: AtomicU64::new(0),
+        }
+    }
+
+    /// Count one applied sample; `devices` is the number of devices stored after it
+    pub fn record_sample(&self, devices: usize) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        self.peak_devices.fetch_max(devices as u64, Ordering::Relaxed);
+    }
+
+    /// Log the run's totals as one line with structured fields
+    pub fn log_summary(&self, state: &AppState) {
+        info!(
+            uptime_secs = self.started.elapsed().as_secs(),
+            samples = self.samples.load(Ordering::Relaxed),
+            rejected = self.rejected.load(Ordering::Relaxed),
+            dropped = state.ingest_dropped.load(Ordering::Relaxed),
+            peak_devices = self.peak_devices.load(Ordering::Relaxed),
+            devices = state.store.len(),
+            "Run summary"
+        );
+    }
+}
+
+/// Middleware counting sample requests the server rejected with a client error.
+/// 503 answers are not rejections: they are counted as dropped samples or over-limit requests.
+pub async fn count_rejections(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if response.status().is_client_error() {
+        state.run_stats.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
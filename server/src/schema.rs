@@ -0,0 +1,67 @@
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::response::Json as JsonResponse;
+use common::{CAPABILITY_BATTERY, CAPABILITY_NET_RATES, CAPABILITY_SWAP};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Display metadata of one dashboard metric
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct FieldMeta {
+    /// SystemInfo field, `ram_pct` for RAM used as a percentage of total, or `battery_pct`
+    /// for the battery's state of charge
+    pub field: &'static str,
+    pub display_name: &'static str,
+    pub unit: &'static str,
+
+    /// Values above these are highlighted as warning / critical
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub critical: Option<f64>,
+
+    /// Only shown for devices reporting this capability
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capability: Option<&'static str>,
+}
+
+/// Metrics rendered by the dashboard, in display order. New fields only need a row here.
+const FIELDS: &[FieldMeta] = &[
+    FieldMeta { field: "cpu_usage", display_name: "CPU Usage", unit: "%", warn: Some(60.0), critical: Some(80.0), capability: None },
+    FieldMeta { field: "ram_pct", display_name: "RAM Usage", unit: "%", warn: Some(70.0), critical: Some(90.0), capability: None },
+    FieldMeta { field: "ram_used_mb", display_name: "RAM Used", unit: "MB", warn: None, critical: None, capability: None },
+    FieldMeta { field: "ram_total_mb", display_name: "RAM Total", unit: "MB", warn: None, critical: None, capability: None },
+    FieldMeta { field: "swap_used_mb", display_name: "Swap Used", unit: "MB", warn: None, critical: None, capability: Some(CAPABILITY_SWAP) },
+    FieldMeta { field: "net_rx_rate_bytes_per_sec", display_name: "Network RX", unit: "B/s", warn: None, critical: None, capability: Some(CAPABILITY_NET_RATES) },
+    FieldMeta { field: "net_tx_rate_bytes_per_sec", display_name: "Network TX", unit: "B/s", warn: None, critical: None, capability: Some(CAPABILITY_NET_RATES) },
+    FieldMeta { field: "battery_pct", display_name: "Battery", unit: "%", warn: None, critical: None, capability: Some(CAPABILITY_BATTERY) },
+];
+
+/// Response body of the UI schema endpoint
+#[derive(Debug, Serialize)]
+pub struct UiSchema {
+    fields: Vec<FieldMeta>,
+}
+
+/// Query parameters for the UI schema endpoint
+#[derive(Debug, Deserialize)]
+pub struct SchemaQuery {
+    /// Use this device's alert rule as the critical thresholds
+    device_id: Option<String>,
+}
+
+/// Handler function to describe the dashboard metrics (unit, display name, thresholds)
+pub async fn get_ui_schema(State(state): State<Arc<AppState>>, Query(query): Query<SchemaQuery>) -> JsonResponse<UiSchema> {
+    let mut fields = FIELDS.to_vec();
+    if let Some(device_id) = &query.device_id {
+        let thresholds = state.alerts.thresholds_for(device_id);
+        for meta in &mut fields {
+            match meta.field {
+                "cpu_usage" => meta.critical = Some(thresholds.cpu_max),
+                "ram_pct" => meta.critical = Some(thresholds.ram_pct_max),
+                _ => {}
+            }
+        }
+    }
+    JsonResponse(UiSchema { fields })
+}
@@ -0,0 +1,45 @@
+use crate::{AppState, IngestItem, Sample};
+use common::collector::{Collector, SysinfoCollector};
+use common::humanize::format_duration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// device_id under which the server reports its own host
+pub const SELF_DEVICE_ID: &str = "__server__";
+
+/// Collect the server host's metrics on an interval and queue them like an agent sample
+/// (no-op unless TAILMON_SELF_MONITOR=1)
+pub async fn run(state: Arc<AppState>) {
+    if !state.config().self_monitor {
+        return;
+    }
+    info!("Self-monitoring enabled, reporting as {} every {}", SELF_DEVICE_ID, format_duration(state.config().self_monitor_interval_secs));
+
+    let mut collector: Box<dyn Collector> = Box::new(SysinfoCollector::new(HashMap::new(), false, false, Vec::new()));
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config().self_monitor_interval_secs));
+    loop {
+        interval.tick().await;
+        // sysinfo calls block, so the collector is moved to a blocking thread and handed back
+        let (returned, mut system_info) = match tokio::task::spawn_blocking(move || {
+            let system_info = collector.collect();
+            (collector, system_info)
+        }).await {
+            Ok(collected) => collected,
+            Err(e) => {
+                error!("Self-monitor collection failed, stopping self-monitoring: {}", e);
+                return;
+            }
+        };
+        collector = returned;
+
+        system_info.device_id = SELF_DEVICE_ID.to_string();
+        for (field, value) in system_info.clamp_non_finite() {
+            warn!("Self-monitor collected a non-finite {} ({}), storing it clamped or left out", field, value);
+        }
+        if state.ingest_tx.try_send(IngestItem { sample: Sample::Full(system_info), addr: None, raw: None }).is_err() {
+            debug!("Ingest queue full, skipping self-monitor sample");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use common::SystemInfo;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// A metric that can be kept in its own series, independent of the full-sample history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    CpuUsage,
+    RamUsedMb,
+    RamTotalMb,
+    SwapUsedMb,
+    NetRxRate,
+    NetTxRate,
+}
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "cpu_usage" => Some(Metric::CpuUsage),
+            "ram_used_mb" => Some(Metric::RamUsedMb),
+            "ram_total_mb" => Some(Metric::RamTotalMb),
+            "swap_used_mb" => Some(Metric::SwapUsedMb),
+            "net_rx_rate_bytes_per_sec" => Some(Metric::NetRxRate),
+            "net_tx_rate_bytes_per_sec" => Some(Metric::NetTxRate),
+            _ => None,
+        }
+    }
+
+    /// SystemInfo field name, also used as the `metric=` selector
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::CpuUsage => "cpu_usage",
+            Metric::RamUsedMb => "ram_used_mb",
+            Metric::RamTotalMb => "ram_total_mb",
+            Metric::SwapUsedMb => "swap_used_mb",
+            Metric::NetRxRate => "net_rx_rate_bytes_per_sec",
+            Metric::NetTxRate => "net_tx_rate_bytes_per_sec",
+        }
+    }
+
+    /// Value of the metric in a sample (None when the sample does not carry it)
+    fn value(&self, info: &SystemInfo) -> Option<f64> {
+        match self {
+            Metric::CpuUsage => Some(info.cpu_usage as f64),
+            Metric::RamUsedMb => Some(info.ram_used_mb as f64),
+            Metric::RamTotalMb => Some(info.ram_total_mb as f64),
+            Metric::SwapUsedMb => Some(info.swap_used_mb as f64),
+            Metric::NetRxRate => info.net_rx_rate_bytes_per_sec,
+            Metric::NetTxRate => info.net_tx_rate_bytes_per_sec,
+        }
+    }
+}
+
+/// Retention and resolution of one metric's series (TAILMON_SERIES)
+#[derive(Debug, Clone, Copy)]
+pub struct SeriesSpec {
+    pub metric: Metric,
+    pub retention_secs: u64,
+
+    /// Samples within one bucket of this width are averaged into a single point
+    pub resolution_secs: u64,
+}
+
+impl SeriesSpec {
+    /// Start of the bucket containing `timestamp`
+    fn bucket_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let bucket = self.resolution_secs.max(1) as i64;
+        let secs = timestamp.timestamp().div_euclid(bucket) * bucket;
+        Utc.timestamp_opt(secs, 0).single().unwrap_or(timestamp)
+    }
+}
+
+/// One point of a metric series
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct SeriesPoint {
+    /// Bucket start
+    pub timestamp: DateTime<Utc>,
+
+    /// Average of the samples in the bucket
+    pub value: f64,
+
+    /// Number of samples averaged into this point
+    pub samples: u32,
+}
+
+/// Metric series of one device
+pub type DeviceSeries = HashMap<Metric, VecDeque<SeriesPoint>>;
+
+/// Add a sample to every configured series of a device
+pub fn record(specs: &[SeriesSpec], series: &mut DeviceSeries, info: &SystemInfo, timestamp: DateTime<Utc>) {
+    for spec in specs {
+        let Some(value) = spec.metric.value(info) else {
+            continue;
+        };
+        let buffer = series.entry(spec.metric).or_default();
+        let bucket_start = spec.bucket_start(timestamp);
+        match buffer.back_mut() {
+            Some(last) if last.timestamp == bucket_start => {
+                let samples = last.samples as f64;
+                last.value = (last.value * samples + value) / (samples + 1.0);
+                last.samples += 1;
+            }
+            // Late samples for an older bucket are dropped, keeping the series ordered
+            Some(last) if last.timestamp > bucket_start => {}
+            _ => buffer.push_back(SeriesPoint { timestamp: bucket_start, value, samples: 1 }),
+        }
+    }
+}
+
+/// Drop points past each metric's retention, and series of metrics that are no longer configured
+pub fn trim(specs: &[SeriesSpec], series: &mut DeviceSeries, now: DateTime<Utc>) {
+    series.retain(|metric, buffer| {
+        let Some(spec) = specs.iter().find(|spec| spec.metric == *metric) else {
+            return false;
+        };
+        let cutoff = now - Duration::seconds(spec.retention_secs as i64);
+        while buffer.front().is_some_and(|point| point.timestamp < cutoff) {
+            buffer.pop_front();
+        }
+        !buffer.is_empty()
+    });
+}
+
+/// Combine the series of two devices (admin rename), averaging points of the same bucket
+pub fn merge(target: &mut DeviceSeries, moved: DeviceSeries) {
+    for (metric, points) in moved {
+        let buffer = target.entry(metric).or_default();
+        let mut combined: Vec<SeriesPoint> = buffer.drain(..).chain(points).collect();
+        combined.sort_by_key(|point| point.timestamp);
+        for point in combined {
+            match buffer.back_mut() {
+                Some(last) if last.timestamp == point.timestamp => {
+                    let total = (last.samples + point.samples) as f64;
+                    last.value = (last.value * last.samples as f64 + point.value * point.samples as f64) / total;
+                    last.samples += point.samples;
+                }
+                _ => buffer.push_back(point),
+            }
+        }
+    }
+}
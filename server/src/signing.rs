@@ -0,0 +1,56 @@
+use crate::auth::constant_time_eq;
+use crate::AppState;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::signing::{parse_header, SigningKey, SIGNATURE_HEADER};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Largest body read for verification, the limit axum applies to ingest bodies anyway
+const MAX_SIGNED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Id of the secret whose signature matches `body`. A key id in the header selects that
+/// secret; without one every active secret is tried.
+fn verify<'a>(keys: &'a [SigningKey], headers: &HeaderMap, body: &[u8]) -> Result<&'a str, String> {
+    let header = headers.get(SIGNATURE_HEADER).and_then(|value| value.to_str().ok())
+        .ok_or_else(|| "missing signature".to_string())?;
+    let (key_id, signature) = parse_header(header).ok_or_else(|| "malformed signature header".to_string())?;
+    let candidates: Vec<&SigningKey> = match key_id {
+        Some(key_id) => {
+            let key = keys.iter().find(|key| key.key_id.as_deref() == Some(key_id))
+                .ok_or_else(|| format!("unknown signing key id {}", key_id))?;
+            vec![key]
+        }
+        None => keys.iter().collect(),
+    };
+    candidates.into_iter()
+        .find(|key| constant_time_eq(&key.sign(body), &signature.to_ascii_lowercase()))
+        .map(|key| key.key_id.as_deref().unwrap_or_default())
+        .ok_or_else(|| "invalid signature".to_string())
+}
+
+/// Middleware requiring ingest bodies to be signed with one of TAILMON_HMAC_SECRETS.
+/// Without configured secrets unsigned requests are accepted.
+pub async fn require_signature(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    if config.hmac_secrets.is_empty() {
+        return next.run(request).await;
+    }
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_SIGNED_BODY_BYTES).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+    };
+    match verify(&config.hmac_secrets, &parts.headers, &body) {
+        Ok(key_id) => *state.signed_requests.entry(key_id.to_string()).or_default() += 1,
+        Err(reason) => {
+            warn!("Rejected {} {}: {}", parts.method, parts.uri.path(), reason);
+            return (StatusCode::UNAUTHORIZED, "Missing or invalid request signature").into_response();
+        }
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
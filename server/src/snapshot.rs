@@ -0,0 +1,250 @@
+use crate::history::{self, HistoryPoint};
+use crate::series::{self, DeviceSeries, Metric, SeriesPoint};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use common::SystemInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// Bumped whenever the snapshot layout changes; files of another version are discarded
+const SNAPSHOT_VERSION: u32 = 3;
+
+/// On-disk snapshot of all history buffers, metric series and first_seen times
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    devices: Vec<(String, Vec<SnapshotPoint>)>,
+
+    /// First report of each device, in milliseconds since the epoch
+    first_seen: Vec<(String, i64)>,
+
+    /// Metric series of each device
+    series: Vec<(String, Vec<SnapshotSeries>)>,
+}
+
+/// Decoded snapshot contents
+struct Restored {
+    history: Vec<(String, VecDeque<HistoryPoint>)>,
+    first_seen: Vec<(String, DateTime<Utc>)>,
+    series: Vec<(String, DeviceSeries)>,
+}
+
+/// One history point. SystemInfo relies on self-describing serde features
+/// (skipped optional fields, arbitrary JSON in `custom`) that bincode cannot
+/// round-trip, so the sample itself is embedded as JSON.
+#[derive(Serialize, Deserialize)]
+struct SnapshotPoint {
+    timestamp_millis: i64,
+    samples: u32,
+    info_json: Vec<u8>,
+}
+
+/// One metric series of a device, keyed by metric name
+#[derive(Serialize, Deserialize)]
+struct SnapshotSeries {
+    metric: String,
+
+    /// (timestamp in milliseconds since the epoch, value, samples)
+    points: Vec<(i64, f64, u32)>,
+}
+
+/// Encode history buffers, metric series and first_seen times into a snapshot file image
+fn encode(
+    history: Vec<(String, VecDeque<HistoryPoint>)>,
+    first_seen: Vec<(String, DateTime<Utc>)>,
+    series: Vec<(String, DeviceSeries)>,
+) -> Result<Vec<u8>, String> {
+    let devices = history.into_iter()
+        .map(|(device_id, buffer)| {
+            let points = buffer.into_iter()
+                .map(|point| {
+                    Ok(SnapshotPoint {
+                        timestamp_millis: point.timestamp.timestamp_millis(),
+                        samples: point.samples,
+                        info_json: serde_json::to_vec(&point.info).map_err(|e| e.to_string())?,
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok((device_id, points))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let first_seen = first_seen.into_iter()
+        .map(|(device_id, first_seen)| (device_id, first_seen.timestamp_millis()))
+        .collect();
+    let series = series.into_iter()
+        .map(|(device_id, device_series)| {
+            let metrics = device_series.into_iter()
+                .map(|(metric, points)| {
+                    let points = points.into_iter()
+                        .map(|point| (point.timestamp.timestamp_millis(), point.value, point.samples))
+                        .collect();
+                    SnapshotSeries { metric: metric.name().to_string(), points }
+                })
+                .collect();
+            (device_id, metrics)
+        })
+        .collect();
+    bincode::serialize(&Snapshot { version: SNAPSHOT_VERSION, devices, first_seen, series }).map_err(|e| e.to_string())
+}
+
+/// Decode a snapshot file image, rejecting other versions
+fn decode(bytes: &[u8]) -> Result<Restored, String> {
+    // Decode the version alone first so layout changes are reported as such
+    let version: u32 = bincode::deserialize(bytes).map_err(|e| e.to_string())?;
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION));
+    }
+    let snapshot: Snapshot = bincode::deserialize(bytes).map_err(|e| e.to_string())?;
+
+    let history = snapshot.devices.into_iter()
+        .map(|(device_id, points)| {
+            let buffer = points.into_iter()
+                .map(|point| {
+                    let info: SystemInfo = serde_json::from_slice(&point.info_json).map_err(|e| e.to_string())?;
+                    let timestamp = DateTime::<Utc>::from_timestamp_millis(point.timestamp_millis)
+                        .ok_or("invalid timestamp")?;
+                    Ok(HistoryPoint { timestamp, samples: point.samples, synthetic: false, info })
+                })
+                .collect::<Result<VecDeque<_>, String>>()?;
+            Ok((device_id, buffer))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let first_seen = snapshot.first_seen.into_iter()
+        .map(|(device_id, millis)| {
+            let first_seen = DateTime::<Utc>::from_timestamp_millis(millis).ok_or("invalid timestamp")?;
+            Ok((device_id, first_seen))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let series = snapshot.series.into_iter()
+        .map(|(device_id, metrics)| {
+            let mut device_series = DeviceSeries::new();
+            // Metrics this version does not know are skipped
+            for SnapshotSeries { metric, points } in metrics {
+                let Some(metric) = Metric::parse(&metric) else {
+                    continue;
+                };
+                let points = points.into_iter()
+                    .map(|(millis, value, samples)| {
+                        let timestamp = DateTime::<Utc>::from_timestamp_millis(millis).ok_or("invalid timestamp")?;
+                        Ok(SeriesPoint { timestamp, value, samples })
+                    })
+                    .collect::<Result<VecDeque<_>, String>>()?;
+                device_series.insert(metric, points);
+            }
+            Ok((device_id, device_series))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Restored { history, first_seen, series })
+}
+
+/// Write a snapshot atomically (temporary file + rename)
+async fn write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    fs::write(&tmp_path, bytes).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+/// Read a snapshot file; a missing file is an empty snapshot
+async fn read(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Load the history and first_seen snapshot (if configured) into the state. Unreadable or
+/// version-mismatched files are discarded and overwritten by the next snapshot.
+pub async fn restore(state: &AppState) {
+    let config = state.config();
+    let Some(path) = config.snapshot_path.as_deref().map(Path::new) else {
+        return;
+    };
+    let bytes = match read(path).await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to read history snapshot {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let restored = match decode(&bytes) {
+        Ok(restored) => restored,
+        Err(e) => {
+            warn!("Discarding history snapshot {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let now = Utc::now();
+    let mut points = 0;
+    let mut devices = 0;
+    for (device_id, first_seen) in restored.first_seen {
+        state.first_seen.insert(device_id, first_seen);
+    }
+    for (device_id, mut buffer) in restored.history {
+        history::trim(&mut buffer, state.config().history_retention_secs, now);
+        points += buffer.len();
+        // Percentiles are not snapshotted; the restored history is the best estimate of the window
+        if !buffer.is_empty() {
+            let mut rolling = state.percentiles.entry(device_id.clone()).or_default();
+            for point in buffer.iter().filter(|point| !point.synthetic) {
+                rolling.record(point.info.cpu_usage as f64, point.timestamp, config.history_retention_secs, now);
+            }
+        }
+        if !buffer.is_empty() {
+            state.store.update_history(&device_id, &mut |target| std::mem::swap(target, &mut buffer));
+            devices += 1;
+        }
+    }
+    for (device_id, mut device_series) in restored.series {
+        series::trim(&config.series, &mut device_series, now);
+        if !device_series.is_empty() {
+            state.series.insert(device_id, device_series);
+        }
+    }
+    info!("Restored {} history points for {} devices from {}", points, devices, path.display());
+}
+
+/// Write the current history buffers to the snapshot file (no-op without TAILMON_SNAPSHOT_PATH)
+pub async fn save(state: &AppState) {
+    let config = state.config();
+    let Some(path) = config.snapshot_path.as_deref().map(Path::new) else {
+        return;
+    };
+    // Copy the buffers out first so encoding does not hold the map's shard locks
+    let history = state.store.all_history();
+    let first_seen: Vec<_> = state.first_seen.iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    let series: Vec<_> = state.series.iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+    let result = match encode(history, first_seen, series) {
+        Ok(bytes) => write(path, &bytes).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Failed to write history snapshot {}: {}", path.display(), e);
+    }
+}
+
+/// Snapshot the history periodically
+pub async fn run(state: Arc<AppState>) {
+    if state.config().snapshot_path.is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config().snapshot_interval_secs));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        save(&state).await;
+    }
+}
@@ -0,0 +1,309 @@
+use crate::history::HistoryPoint;
+use crate::record::DeviceRecord;
+use crate::retain::Retention;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::RwLock;
+
+/// Storage of each device's current record and retained history. Handlers only go through
+/// this trait, so a persistent backend (e.g. SQLite) can replace the in-memory default.
+///
+/// Records and history points are stored without the optional fields their device does not
+/// retain (see `set_retention`), whichever path writes them.
+///
+/// Closures passed to the `add_history`, `update*` and `evict` methods may run under a lock
+/// of the backend and must not call back into the store.
+pub trait MetricsStore: Send + Sync {
+    /// Replace the optional fields kept of each device; applies to records and points written afterwards
+    fn set_retention(&self, retention: Retention);
+
+    /// Store a device's latest record, replacing the previous one
+    fn upsert(&self, record: DeviceRecord);
+
+    /// Change a stored record in place; false when the device is unknown
+    fn update(&self, device_id: &str, change: &mut dyn FnMut(&mut DeviceRecord)) -> bool;
+
+    /// Current record of a device
+    fn get(&self, device_id: &str) -> Option<DeviceRecord>;
+
+    /// Whether a device has a current record
+    fn contains(&self, device_id: &str) -> bool {
+        self.get(device_id).is_some()
+    }
+
+    /// Current records of every device, in no particular order
+    fn all(&self) -> Vec<DeviceRecord>;
+
+    /// Number of devices with a current record
+    fn len(&self) -> usize;
+
+    /// Take a device's record and history out of the store (e.g. to rename it)
+    fn remove(&self, device_id: &str) -> Option<(DeviceRecord, VecDeque<HistoryPoint>)>;
+
+    /// The points `select` picks from the number of points in a device's retained history, oldest
+    /// first, along with that number; only the selected points are copied. `None` when it has none.
+    fn history(&self, device_id: &str, select: &dyn Fn(usize) -> Range<usize>) -> Option<(usize, Vec<HistoryPoint>)>;
+
+    /// Retained history of every device
+    fn all_history(&self) -> Vec<(String, VecDeque<HistoryPoint>)>;
+
+    /// Add a point to a device's history, starting from an empty one. `insert` puts the point
+    /// in place (or skips it) and may drop old points.
+    fn add_history(&self, device_id: &str, point: HistoryPoint, insert: &mut dyn FnMut(&mut VecDeque<HistoryPoint>, HistoryPoint));
+
+    /// Change a device's history in place, starting from an empty one, e.g. to move points
+    /// over from another device
+    fn update_history(&self, device_id: &str, change: &mut dyn FnMut(&mut VecDeque<HistoryPoint>));
+
+    /// Run `evict` on every history, dropping or compacting old points
+    fn evict(&self, evict: &mut dyn FnMut(&mut VecDeque<HistoryPoint>));
+}
+
+/// Default store keeping everything in memory; lost on restart unless the history snapshot is enabled
+pub struct InMemoryStore {
+    records: DashMap<String, DeviceRecord>,
+    history: DashMap<String, VecDeque<HistoryPoint>>,
+    retention: RwLock<Retention>,
+}
+
+impl InMemoryStore {
+    pub fn new(retention: Retention) -> Self {
+        InMemoryStore { records: DashMap::new(), history: DashMap::new(), retention: RwLock::new(retention) }
+    }
+
+    fn retention(&self) -> std::sync::RwLockReadGuard<'_, Retention> {
+        self.retention.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl MetricsStore for InMemoryStore {
+    fn set_retention(&self, retention: Retention) {
+        *self.retention.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = retention;
+    }
+
+    fn upsert(&self, mut record: DeviceRecord) {
+        self.retention().apply(&mut record.info);
+        self.records.insert(record.info.device_id.clone(), record);
+    }
+
+    fn update(&self, device_id: &str, change: &mut dyn FnMut(&mut DeviceRecord)) -> bool {
+        match self.records.get_mut(device_id) {
+            Some(mut record) => {
+                change(&mut record);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get(&self, device_id: &str) -> Option<DeviceRecord> {
+        self.records.get(device_id).map(|record| record.clone())
+    }
+
+    fn contains(&self, device_id: &str) -> bool {
+        self.records.contains_key(device_id)
+    }
+
+    fn all(&self) -> Vec<DeviceRecord> {
+        self.records.iter().map(|entry| entry.value().clone()).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn remove(&self, device_id: &str) -> Option<(DeviceRecord, VecDeque<HistoryPoint>)> {
+        let (_, record) = self.records.remove(device_id)?;
+        let history = self.history.remove(device_id).map(|(_, buffer)| buffer).unwrap_or_default();
+        Some((record, history))
+    }
+
+    fn history(&self, device_id: &str, select: &dyn Fn(usize) -> Range<usize>) -> Option<(usize, Vec<HistoryPoint>)> {
+        self.history.get(device_id).map(|buffer| (buffer.len(), buffer.range(select(buffer.len())).cloned().collect()))
+    }
+
+    fn all_history(&self) -> Vec<(String, VecDeque<HistoryPoint>)> {
+        self.history.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+    }
+
+    fn add_history(&self, device_id: &str, mut point: HistoryPoint, insert: &mut dyn FnMut(&mut VecDeque<HistoryPoint>, HistoryPoint)) {
+        self.retention().apply(&mut point.info);
+        insert(&mut self.history.entry(device_id.to_string()).or_default(), point);
+    }
+
+    fn update_history(&self, device_id: &str, change: &mut dyn FnMut(&mut VecDeque<HistoryPoint>)) {
+        let mut buffer = self.history.entry(device_id.to_string()).or_default();
+        change(&mut buffer);
+        // Moved points may fall under a different rule than where they came from
+        let retention = self.retention();
+        for point in buffer.iter_mut() {
+            retention.apply(&mut point.info);
+        }
+    }
+
+    fn evict(&self, evict: &mut dyn FnMut(&mut VecDeque<HistoryPoint>)) {
+        for mut buffer in self.history.iter_mut() {
+            evict(&mut buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history;
+    use crate::retain::{RetainField, RetainRule};
+    use chrono::{TimeZone, Utc};
+    use common::{test_util, SystemInfo};
+
+    fn sample(device_id: &str, last_seen: &str, cpu_usage: f32) -> SystemInfo {
+        let mut info = test_util::sample();
+        info.device_id = device_id.to_string();
+        info.last_seen = last_seen.to_string();
+        info.cpu_usage = cpu_usage;
+        info
+    }
+
+    fn record(device_id: &str, cpu_usage: f32) -> DeviceRecord {
+        DeviceRecord {
+            info: sample(device_id, "2026-01-01T00:00:00Z", cpu_usage),
+            first_seen: Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+            health_score: 100.0,
+            location: None,
+            resolved_name: None,
+            conflicted: false,
+            device_class: None,
+        }
+    }
+
+    fn point(last_seen: &str) -> HistoryPoint {
+        HistoryPoint::new(sample("web-1", last_seen, 10.0))
+    }
+
+    /// A device's whole history
+    fn history_of(store: &InMemoryStore, device_id: &str) -> Option<Vec<HistoryPoint>> {
+        store.history(device_id, &|len| 0..len).map(|(_, points)| points)
+    }
+
+    fn timestamps(points: &[HistoryPoint]) -> Vec<String> {
+        points.iter().map(|point| point.info.last_seen.clone()).collect()
+    }
+
+    #[test]
+    fn upsert_then_get() {
+        let store = InMemoryStore::new(Retention::default());
+        assert!(store.get("web-1").is_none());
+        assert!(!store.contains("web-1"));
+
+        store.upsert(record("web-1", 12.5));
+        assert_eq!(store.get("web-1").unwrap().info.cpu_usage, 12.5);
+        assert!(store.contains("web-1"));
+        assert!(store.get("web-2").is_none());
+        assert_eq!(store.all().len(), 1);
+    }
+
+    #[test]
+    fn upsert_overwrites() {
+        let store = InMemoryStore::new(Retention::default());
+        store.upsert(record("web-1", 12.5));
+        store.upsert(record("web-2", 50.0));
+        store.upsert(record("web-1", 80.0));
+        assert_eq!(store.all().len(), 2);
+        assert_eq!(store.get("web-1").unwrap().info.cpu_usage, 80.0);
+
+        let mut all: Vec<(String, f32)> = store.all().into_iter().map(|record| (record.info.device_id, record.info.cpu_usage)).collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(all, vec![("web-1".to_string(), 80.0), ("web-2".to_string(), 50.0)]);
+
+        assert!(store.update("web-2", &mut |record| record.conflicted = true));
+        assert!(store.get("web-2").unwrap().conflicted);
+        assert!(!store.update("web-3", &mut |record| record.conflicted = true));
+    }
+
+    #[test]
+    fn history_keeps_time_order() {
+        let store = InMemoryStore::new(Retention::default());
+        assert!(history_of(&store, "web-1").is_none());
+        for last_seen in ["2026-01-01T00:00:00Z", "2026-01-01T00:00:05Z", "2026-01-01T00:00:10Z"] {
+            store.update_history("web-1", &mut |buffer| buffer.push_back(point(last_seen)));
+        }
+        // An out-of-order sample is filed at its place in time
+        store.update_history("web-1", &mut |buffer| history::insert_ordered(buffer, point("2026-01-01T00:00:07Z")));
+        assert_eq!(timestamps(&history_of(&store, "web-1").unwrap()), vec![
+            "2026-01-01T00:00:00Z", "2026-01-01T00:00:05Z", "2026-01-01T00:00:07Z", "2026-01-01T00:00:10Z",
+        ]);
+        assert!(history_of(&store, "web-2").is_none());
+
+        // Only the selected points are read
+        let (len, points) = store.history("web-1", &|len| len - 2..len - 1).unwrap();
+        assert_eq!(len, 4);
+        assert_eq!(timestamps(&points), vec!["2026-01-01T00:00:07Z"]);
+    }
+
+    #[test]
+    fn evict_drops_points_past_retention() {
+        let store = InMemoryStore::new(Retention::default());
+        store.upsert(record("web-1", 12.5));
+        for last_seen in ["2026-01-01T00:00:00Z", "2026-01-01T00:00:30Z", "2026-01-01T00:01:00Z"] {
+            store.update_history("web-1", &mut |buffer| buffer.push_back(point(last_seen)));
+            store.update_history("web-2", &mut |buffer| buffer.push_back(point(last_seen)));
+        }
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 0, 1, 10).unwrap();
+        store.evict(&mut |buffer| history::trim(buffer, 45, now));
+        for device_id in ["web-1", "web-2"] {
+            assert_eq!(timestamps(&history_of(&store, device_id).unwrap()), vec!["2026-01-01T00:00:30Z", "2026-01-01T00:01:00Z"]);
+        }
+        assert_eq!(store.all_history().len(), 2);
+
+        // Eviction only touches history, and removing a device takes both along
+        assert!(store.contains("web-1"));
+        let (record, history) = store.remove("web-1").unwrap();
+        assert_eq!(record.info.device_id, "web-1");
+        assert_eq!(history.len(), 2);
+        assert!(!store.contains("web-1"));
+        assert!(history_of(&store, "web-1").is_none());
+    }
+
+    #[test]
+    fn writes_keep_only_retained_fields() {
+        let laptops = Retention {
+            rules: vec![RetainRule::parse("laptop-*:net_rates").unwrap()],
+            default: Some(vec![RetainField::Swap]),
+        };
+        let store = InMemoryStore::new(laptops);
+        let with_extras = |device_id: &str| {
+            let mut record = record(device_id, 12.5);
+            record.info.swap_total_mb = 1024;
+            record.info.net_rx_rate_bytes_per_sec = Some(1500.0);
+            record.info.ui_meta.insert("icon".to_string(), "💻".to_string());
+            record
+        };
+
+        store.upsert(with_extras("laptop-1"));
+        store.upsert(with_extras("web-1"));
+        let laptop = store.get("laptop-1").unwrap().info;
+        assert!(laptop.net_rx_rate_bytes_per_sec.is_some() && laptop.swap_total_mb == 0 && laptop.ui_meta.is_empty());
+        let web = store.get("web-1").unwrap().info;
+        assert!(web.net_rx_rate_bytes_per_sec.is_none() && web.swap_total_mb == 1024 && web.ui_meta.is_empty());
+
+        store.add_history("laptop-1", HistoryPoint::new(with_extras("laptop-1").info), &mut |buffer, point| buffer.push_back(point));
+        let point = &history_of(&store, "laptop-1").unwrap()[0].info;
+        assert!(point.net_rx_rate_bytes_per_sec.is_some() && point.swap_total_mb == 0);
+
+        // Points moved to another device follow that device's rule
+        let (_, moved) = store.remove("laptop-1").unwrap();
+        store.update_history("web-1", &mut |buffer| {
+            buffer.extend(moved.iter().cloned().map(|mut point| {
+                point.info.device_id = "web-1".to_string();
+                point
+            }));
+        });
+        assert!(history_of(&store, "web-1").unwrap()[0].info.net_rx_rate_bytes_per_sec.is_none());
+
+        // A reload applies to later writes
+        store.set_retention(Retention::default());
+        store.upsert(with_extras("web-1"));
+        assert!(store.get("web-1").unwrap().info.net_rx_rate_bytes_per_sec.is_some());
+    }
+}
@@ -0,0 +1,125 @@
+use axum::{extract::ConnectInfo, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+/// Identity of a TLS client, taken from the Common Name of its certificate.
+/// Inserted into every request served over TLS (`None` without a client cert).
+#[derive(Debug, Clone)]
+pub struct ClientIdentity(pub Option<String>);
+
+/// Build the rustls server configuration, optionally requiring client certificates
+/// signed by the CA bundle at `client_ca_path`
+pub fn load_server_config(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> Result<ServerConfig, String> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots.add(cert).map_err(|e| format!("invalid CA certificate in {}: {}", ca_path, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| format!("failed to build client verifier: {}", e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid server certificate/key: {}", e))
+}
+
+/// Accept TLS connections until `shutdown` resolves, serving each one with `app`
+pub async fn serve(listener: TcpListener, app: Router, config: ServerConfig, shutdown: impl Future<Output = ()>) {
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    tokio::pin!(shutdown);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => return,
+        };
+        let (stream, remote_addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+            let identity = ClientIdentity(
+                stream.get_ref().1.peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(|cert| common_name(cert)),
+            );
+
+            let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+                request.extensions_mut().insert(identity.clone());
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+                app.clone().oneshot(request)
+            });
+            if let Err(e) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection from {} closed with error: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+/// Extract the subject Common Name from a DER-encoded certificate
+fn common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let common_name = parsed.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+/// Load all PEM certificates from a file
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to parse certificates in {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+/// Load the first PEM private key from a file
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("failed to parse private key in {}: {}", path, e))?
+        .ok_or_else(|| format!("no private key found in {}", path))
+}
@@ -0,0 +1,97 @@
+use common::{SystemInfo, CAPABILITY_CUSTOM};
+use rhai::{Dynamic, Engine, Scope, AST};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Operations between two checks of the time limit
+const DEADLINE_CHECK_OPS: u64 = 256;
+
+/// Largest string, array or map a script may build
+const MAX_SCRIPT_VALUE_SIZE: usize = 4096;
+
+/// Nesting limit for script function calls
+const MAX_SCRIPT_CALL_LEVELS: usize = 16;
+
+thread_local! {
+    /// Time after which the script running on this thread is aborted; kept per thread so
+    /// scripts run at the same time on other threads keep their own limit
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Rhai script run on every ingested sample (TAILMON_TRANSFORM_SCRIPT). The script sees the
+/// sample as the read-only map `sample` and adds derived fields to the map `custom`, e.g.
+/// `custom.ram_cost = sample.ram_total_mb * 0.002;`
+pub struct Transform {
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+impl Transform {
+    /// Compile the script at `path`. The engine has no file, network or process access;
+    /// `eval` is disabled and value sizes, call depth and run time are limited.
+    pub fn open(path: &str, timeout: Duration) -> Result<Self, String> {
+        let script = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+        let mut engine = Engine::new();
+        engine.disable_symbol("eval");
+        engine.set_max_string_size(MAX_SCRIPT_VALUE_SIZE);
+        engine.set_max_array_size(MAX_SCRIPT_VALUE_SIZE);
+        engine.set_max_map_size(MAX_SCRIPT_VALUE_SIZE);
+        engine.set_max_call_levels(MAX_SCRIPT_CALL_LEVELS);
+        engine.on_print(|text| info!("Transform script: {}", text));
+        engine.on_debug(|text, _, _| info!("Transform script: {}", text));
+        engine.on_progress(|ops| {
+            let expired = ops % DEADLINE_CHECK_OPS == 0
+                && DEADLINE.with(Cell::get).is_some_and(|deadline| Instant::now() > deadline);
+            expired.then(|| Dynamic::from("time limit exceeded"))
+        });
+
+        let ast = engine.compile(&script).map_err(|e| format!("failed to compile {}: {}", path, e))?;
+        Ok(Transform { engine, ast, timeout })
+    }
+
+    /// Run the script on a sample and merge the `custom` entries it produced. A failing or
+    /// timed-out script leaves the sample unchanged. The agent's checksum is dropped when
+    /// anything was added, since it no longer matches the stored record. The script runs on
+    /// the calling thread, which it blocks for up to the time limit.
+    pub fn apply(&self, system_info: &mut SystemInfo) {
+        let sample = match rhai::serde::to_dynamic(&*system_info) {
+            Ok(sample) => sample,
+            Err(e) => {
+                warn!("Transform script skipped for {}: {}", system_info.device_id, e);
+                return;
+            }
+        };
+        let mut scope = Scope::new();
+        scope.push_constant("sample", sample);
+        scope.push("custom", rhai::Map::new());
+
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        DEADLINE.with(|deadline| deadline.set(None));
+        if let Err(e) = result {
+            warn!("Transform script failed for {}: {}", system_info.device_id, e);
+            return;
+        }
+
+        let Some(custom) = scope.get_value::<rhai::Map>("custom") else {
+            warn!("Transform script replaced `custom` with a non-map value for {}", system_info.device_id);
+            return;
+        };
+        if custom.is_empty() {
+            return;
+        }
+        for (key, value) in custom {
+            match rhai::serde::from_dynamic::<serde_json::Value>(&value) {
+                Ok(value) => {
+                    system_info.custom.insert(key.to_string(), value);
+                }
+                Err(e) => warn!("Transform script produced an unusable value for custom.{}: {}", key, e),
+            }
+        }
+        system_info.add_capability(CAPABILITY_CUSTOM);
+        system_info.checksum = None;
+    }
+}
@@ -0,0 +1,209 @@
+use common::humanize::format_duration;
+use common::signing::{SigningKey, SIGNATURE_HEADER};
+use common::{SystemInfo, MAX_BATCH_SIZE};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tower::retry::budget::Budget;
+use tower::retry::{Policy, Retry, RetryLayer};
+use tower::{Layer, Service, ServiceExt};
+use tracing::{info, warn};
+
+/// Longest wait between forward attempts once the retry budget is spent
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Delay before the first in-request retry; doubled for each further retry
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Window over which successful forwards earn retries
+const BUDGET_TTL: Duration = Duration::from_secs(10);
+
+/// Retries per second allowed regardless of successes, so a fresh relay can recover
+const BUDGET_MIN_PER_SEC: u32 = 1;
+
+/// Relays ingested samples to an upstream tailmon server (TAILMON_UPSTREAM_URL)
+pub struct Upstream {
+    tx: mpsc::Sender<SystemInfo>,
+
+    /// Samples dropped because the forward buffer was full or upstream rejected them
+    dropped: Arc<AtomicU64>,
+}
+
+impl Upstream {
+    /// Start the forwarding task for `url` (the upstream's ingest URL, e.g. `http://central:3000/api/metrics`).
+    /// `retry_ratio` is the number of retries each successful forward earns (TAILMON_UPSTREAM_RETRY_RATIO).
+    /// Requests carry a signature when `signing_key` is set (TAILMON_UPSTREAM_HMAC_SECRET).
+    pub fn spawn(url: String, api_key: Option<String>, signing_key: Option<SigningKey>, buffer_size: usize, retry_ratio: f32) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("failed to create HTTP client: {}", e))?;
+        let policy = BudgetPolicy {
+            budget: Arc::new(Budget::new(BUDGET_TTL, BUDGET_MIN_PER_SEC, retry_ratio)),
+            delay: FIRST_RETRY_DELAY,
+        };
+        let service = RetryLayer::new(policy).layer(Forward { client, url, api_key, signing_key });
+        let (tx, rx) = mpsc::channel(buffer_size);
+        let dropped = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(service, rx, buffer_size, dropped.clone()));
+        Ok(Upstream { tx, dropped })
+    }
+
+    /// Queue a sample for forwarding without waiting; a full buffer drops it
+    pub fn forward(&self, system_info: SystemInfo) {
+        if self.tx.try_send(system_info).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Why forwarding a chunk failed
+enum ForwardError {
+    /// Upstream refused the samples; retrying cannot help
+    Rejected(reqwest::StatusCode),
+    /// Upstream was unreachable or failed temporarily
+    Retry(String),
+}
+
+/// Service POSTing one chunk to the upstream batch endpoint
+#[derive(Clone)]
+struct Forward {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    signing_key: Option<SigningKey>,
+}
+
+impl Service<Arc<Vec<SystemInfo>>> for Forward {
+    type Response = ();
+    type Error = ForwardError;
+    type Future = Pin<Box<dyn Future<Output = Result<(), ForwardError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, chunk: Arc<Vec<SystemInfo>>) -> Self::Future {
+        let body = serde_json::to_vec(&*chunk).unwrap_or_default();
+        let mut request = self.client.post(format!("{}/batch", self.url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(signing_key) = &self.signing_key {
+            request = request.header(SIGNATURE_HEADER, signing_key.header_value(&body));
+        }
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let request = request.body(body);
+        Box::pin(async move {
+            let response = request.send().await.map_err(|e| ForwardError::Retry(e.to_string()))?;
+            let status = response.status();
+            if status.is_success() {
+                Ok(())
+            } else if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                Err(ForwardError::Rejected(status))
+            } else {
+                Err(ForwardError::Retry(format!("upstream returned {}", status)))
+            }
+        })
+    }
+}
+
+/// Retries temporary failures while the budget allows, with a doubling delay.
+/// Every success deposits into the shared budget, so retries stay proportional
+/// to successful forwards and a down upstream cannot cause a retry storm.
+#[derive(Clone)]
+struct BudgetPolicy {
+    budget: Arc<Budget>,
+    delay: Duration,
+}
+
+impl Policy<Arc<Vec<SystemInfo>>, (), ForwardError> for BudgetPolicy {
+    type Future = Pin<Box<dyn Future<Output = Self> + Send>>;
+
+    fn retry(&self, _chunk: &Arc<Vec<SystemInfo>>, result: Result<&(), &ForwardError>) -> Option<Self::Future> {
+        match result {
+            Ok(()) => {
+                self.budget.deposit();
+                None
+            }
+            Err(ForwardError::Rejected(_)) => None,
+            Err(ForwardError::Retry(reason)) => {
+                self.budget.withdraw().ok()?;
+                let delay = self.delay;
+                warn!("Upstream forward failed, retrying in {} ms: {}", delay.as_millis(), reason);
+                let next = BudgetPolicy {
+                    budget: self.budget.clone(),
+                    delay: (delay * 2).min(Duration::from_secs(MAX_BACKOFF_SECS)),
+                };
+                Some(Box::pin(async move {
+                    tokio::time::sleep(delay).await;
+                    next
+                }))
+            }
+        }
+    }
+
+    fn clone_request(&self, chunk: &Arc<Vec<SystemInfo>>) -> Option<Arc<Vec<SystemInfo>>> {
+        Some(chunk.clone())
+    }
+}
+
+/// Forward queued samples in order, oldest first. Once the retry budget is spent the
+/// chunk stays buffered and is tried again after a backoff while upstream is down.
+/// Samples beyond `buffer_size` waiting for upstream are dropped oldest first.
+async fn run(
+    service: Retry<BudgetPolicy, Forward>,
+    mut rx: mpsc::Receiver<SystemInfo>,
+    buffer_size: usize,
+    dropped: Arc<AtomicU64>,
+) {
+    info!("Forwarding ingested samples to upstream {}", service.get_ref().url);
+    let mut buffer: VecDeque<SystemInfo> = VecDeque::new();
+    let mut backoff_secs = 1;
+
+    loop {
+        if buffer.is_empty() {
+            match rx.recv().await {
+                Some(system_info) => buffer.push_back(system_info),
+                None => return,
+            }
+        }
+        while let Ok(system_info) = rx.try_recv() {
+            buffer.push_back(system_info);
+        }
+        if buffer.len() > buffer_size {
+            let excess = buffer.len() - buffer_size;
+            buffer.drain(..excess);
+            dropped.fetch_add(excess as u64, Ordering::Relaxed);
+        }
+
+        let count = buffer.len().min(MAX_BATCH_SIZE);
+        let chunk: Arc<Vec<SystemInfo>> = Arc::new(buffer.iter().take(count).cloned().collect());
+        match service.clone().oneshot(chunk).await {
+            Ok(()) => {
+                buffer.drain(..count);
+                backoff_secs = 1;
+            }
+            Err(ForwardError::Rejected(status)) => {
+                warn!("Upstream rejected {} samples with {}, dropping them", count, status);
+                buffer.drain(..count);
+                dropped.fetch_add(count as u64, Ordering::Relaxed);
+            }
+            Err(ForwardError::Retry(reason)) => {
+                warn!("Failed to forward {} samples upstream ({} buffered), retry budget spent, retrying in {}: {}",
+                    count, buffer.len(), format_duration(backoff_secs), reason);
+                tokio::time::sleep(Duration::from_secs(backoff_secs)).await;
+                backoff_secs = (backoff_secs * 2).min(MAX_BACKOFF_SECS);
+            }
+        }
+    }
+}
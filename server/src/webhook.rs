@@ -0,0 +1,71 @@
+use crate::alerts::{ActiveBreach, AlertEvent, AlertMode};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use common::humanize::format_duration;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Body of a digest webhook call
+#[derive(Debug, Serialize)]
+struct Digest {
+    generated_at: DateTime<Utc>,
+
+    /// Every threshold exceeded at the time of the digest
+    active: Vec<ActiveBreach>,
+
+    /// Transitions since the previous digest
+    events: Vec<AlertEvent>,
+}
+
+/// POST a JSON body to the webhook; failures are logged and the payload dropped
+pub async fn post<T: Serialize>(client: &reqwest::Client, url: &str, body: &T) {
+    match client.post(url).json(body).send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Alert webhook returned {}", response.status()),
+        Err(e) => warn!("Failed to call alert webhook: {}", e),
+    }
+}
+
+/// Deliver alert transitions to TAILMON_ALERT_WEBHOOK_URL, one call per
+/// transition or as a periodic digest (no-op without a webhook)
+pub async fn run(state: Arc<AppState>) {
+    let Some(url) = state.config().alert_webhook_url.clone() else {
+        return;
+    };
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Alert webhook disabled: failed to create HTTP client: {}", e);
+            return;
+        }
+    };
+
+    match state.config().alert_mode {
+        AlertMode::Immediate => {
+            info!("Sending alerts to webhook {}", url);
+            loop {
+                state.alerts.notify.notified().await;
+                for event in state.alerts.drain_events() {
+                    post(&client, &url, &event).await;
+                }
+            }
+        }
+        AlertMode::Digest => {
+            info!("Sending an alert digest to webhook {} every {}", url, format_duration(state.config().alert_digest_secs));
+            let mut interval = tokio::time::interval(Duration::from_secs(state.config().alert_digest_secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let events = state.alerts.drain_events();
+                let active = state.alerts.active_breaches();
+                // Quiet fleets produce no digest
+                if events.is_empty() && active.is_empty() {
+                    continue;
+                }
+                post(&client, &url, &Digest { generated_at: Utc::now(), active, events }).await;
+            }
+        }
+    }
+}
@@ -1,9 +1,45 @@
 // Tailmon Dashboard JavaScript
 
+// Read API key (TAILMON_READ_API_KEY), taken once from ?key=... and remembered in this browser
+const READ_KEY_STORAGE = 'tailmonReadKey';
+const keyParam = new URLSearchParams(window.location.search).get('key');
+if (keyParam) {
+    localStorage.setItem(READ_KEY_STORAGE, keyParam);
+}
+
+// GET an API path (relative URL so a reverse-proxy prefix is respected), with the read key if any
+function apiFetch(path) {
+    const readKey = localStorage.getItem(READ_KEY_STORAGE);
+    return fetch(path, {
+        headers: readKey ? { 'X-API-Key': readKey } : {}
+    });
+}
+
+// Field metadata (unit, display name, thresholds) from /api/schema/ui, loaded once
+let uiFields = null;
+
+async function fetchSchema() {
+    try {
+        const response = await apiFetch('api/schema/ui');
+        if (response.ok) {
+            uiFields = (await response.json()).fields;
+        }
+    } catch (error) {
+        console.error('Error fetching UI schema:', error);
+    }
+}
+
 // Fetch metrics from server
 async function fetchMetrics() {
     try {
-        const response = await fetch('/api/all_metrics');
+        const response = await apiFetch('api/all_metrics');
+        if (response.status === 401) {
+            // Ask once for the key; an empty answer keeps the dashboard empty
+            const key = window.prompt('This dashboard requires a read API key:');
+            if (key) {
+                localStorage.setItem(READ_KEY_STORAGE, key.trim());
+            }
+        }
         if (!response.ok) {
             throw new Error(`HTTP error! status: ${response.status}`);
         }
@@ -15,6 +51,82 @@ async function fetchMetrics() {
     }
 }
 
+// Fetch the latest activity events (newest first)
+async function fetchActivity() {
+    try {
+        const response = await apiFetch('api/activity?limit=10');
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        return await response.json();
+    } catch (error) {
+        console.error('Error fetching activity:', error);
+        return [];
+    }
+}
+
+// One-line description of an activity event
+function describeActivity(event) {
+    switch (event.type) {
+        case 'new_device':
+            return 'started reporting';
+        case 'change':
+            return `${event.field} changed from ${event.old} to ${event.new}`;
+        case 'alert':
+            return event.state === 'firing'
+                ? `${event.metric} at ${event.value.toFixed(1)}% exceeds ${event.threshold.toFixed(1)}%`
+                : `${event.metric} back to ${event.value.toFixed(1)}%`;
+        case 'offline':
+            return 'went offline';
+        case 'online':
+            return 'is back online';
+        case 'renamed':
+            return `renamed from ${event.from}`;
+        default:
+            return event.type;
+    }
+}
+
+// Render the activity feed
+function renderActivity(events) {
+    const feed = document.getElementById('activity-feed');
+    if (events.length === 0) {
+        feed.innerHTML = '<li class="activity-empty">Nothing has happened yet</li>';
+        return;
+    }
+    feed.innerHTML = events.map(event => `
+        <li class="activity-item activity-${event.type}${event.state ? ` activity-${event.state}` : ''}">
+            <span class="activity-time">${getTimeAgo(new Date(event.timestamp))}</span>
+            <span class="activity-device">${escapeHtml(event.device_id)}</span>
+            ${escapeHtml(describeActivity(event))}
+        </li>`).join('');
+}
+
+// Fetch the server's recent log lines (oldest first)
+async function fetchLogs() {
+    try {
+        const response = await apiFetch('api/logs?limit=200');
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        return await response.json();
+    } catch (error) {
+        console.error('Error fetching server logs:', error);
+        return [];
+    }
+}
+
+// Render the server logs, keeping the view at the newest line
+function renderLogs(lines) {
+    const view = document.getElementById('server-logs');
+    const atBottom = view.scrollTop + view.clientHeight >= view.scrollHeight - 5;
+    view.innerHTML = lines.map(line => `<span class="log-${line.level.toLowerCase()}">${escapeHtml(
+        `${new Date(line.timestamp).toLocaleTimeString()} ${line.level.padEnd(5)} ${line.message}`)}</span>`).join('\n');
+    if (atBottom) {
+        view.scrollTop = view.scrollHeight;
+    }
+}
+
 // Render dashboard with device data
 function renderDashboard(devices) {
     const container = document.getElementById('dashboard-container');
@@ -30,48 +142,46 @@ function renderDashboard(devices) {
     }
     
     container.innerHTML = devices.map(device => {
-        // Determine status class based on CPU and RAM usage
+        // Fields shown for this device: those it has data and the capability for
+        const capabilities = device.capabilities || [];
+        const fields = (uiFields || []).filter(meta =>
+            (!meta.capability || capabilities.includes(meta.capability)) && fieldValue(device, meta.field) != null);
+        
+        // Determine status class from the schema thresholds
         let statusClass = '';
-        if (device.cpu_usage > 80 || (device.ram_used_mb / device.ram_total_mb) > 0.9) {
+        if (fields.some(meta => meta.critical != null && fieldValue(device, meta.field) > meta.critical)) {
             statusClass = 'status-critical';
-        } else if (device.cpu_usage > 60 || (device.ram_used_mb / device.ram_total_mb) > 0.7) {
+        } else if (fields.some(meta => meta.warn != null && fieldValue(device, meta.field) > meta.warn)) {
             statusClass = 'status-warning';
         }
         
-        // Calculate RAM usage percentage
-        const ramUsagePercent = ((device.ram_used_mb / device.ram_total_mb) * 100).toFixed(1);
-        
         // Format last seen time
         const lastSeen = new Date(device.last_seen);
         const timeAgo = getTimeAgo(lastSeen);
         
+        // Optional presentational hints sent by the agent (TAILMON_META)
+        const uiMeta = device.ui_meta || {};
+        const accentStyle = isSafeColor(uiMeta.color) ? `style="border-left: 4px solid ${uiMeta.color};"` : '';
+        const icon = uiMeta.icon ? `<span class="device-icon">${escapeHtml(uiMeta.icon)}</span>` : '';
+        
+        const tiles = fields.map(meta => `
+                    <div class="metric-item">
+                        <div class="metric-label">${escapeHtml(meta.display_name)}</div>
+                        <div class="metric-value field-${meta.field}">${formatValue(fieldValue(device, meta.field), meta.unit)}${meta.field === 'battery_pct' && device.battery.charging ? '<span class="battery-charging" title="Charging">⚡</span>' : ''}</div>
+                    </div>`).join('');
+        
+        // The server's own host (TAILMON_SELF_MONITOR) is tagged so it is not mistaken for an agent
+        const selfBadge = device.device_id === '__server__' ? '<span class="device-badge">server</span>' : '';
+        
         return `
-            <div class="device-card ${statusClass}">
+            <div class="device-card ${statusClass}" ${accentStyle}>
                 <div class="device-header">
-                    <div class="device-name">${escapeHtml(device.device_id)}</div>
+                    <div class="device-name">${icon}${escapeHtml(device.device_id)}${selfBadge}</div>
                     <div class="device-os">${escapeHtml(device.os_info)}</div>
+                    ${device.resolved_name ? `<div class="device-os">${escapeHtml(device.resolved_name)}</div>` : ''}
                 </div>
                 
-                <div class="metrics-grid">
-                    <div class="metric-item">
-                        <div class="metric-label">CPU Usage</div>
-                        <div class="metric-value cpu-usage">${device.cpu_usage.toFixed(1)}<span class="metric-unit">%</span></div>
-                    </div>
-                    
-                    <div class="metric-item">
-                        <div class="metric-label">RAM Usage</div>
-                        <div class="metric-value ram-usage">${ramUsagePercent}<span class="metric-unit">%</span></div>
-                    </div>
-                    
-                    <div class="metric-item">
-                        <div class="metric-label">RAM Used</div>
-                        <div class="metric-value">${device.ram_used_mb}<span class="metric-unit">MB</span></div>
-                    </div>
-                    
-                    <div class="metric-item">
-                        <div class="metric-label">RAM Total</div>
-                        <div class="metric-value">${device.ram_total_mb}<span class="metric-unit">MB</span></div>
-                    </div>
+                <div class="metrics-grid">${tiles}
                 </div>
                 
                 <div class="last-seen">
@@ -89,6 +199,43 @@ function escapeHtml(text) {
     return div.innerHTML;
 }
 
+// Helper function to accept only plain CSS colors (hex or named) from ui_meta
+function isSafeColor(color) {
+    return typeof color === 'string' && /^(#[0-9a-fA-F]{3,8}|[a-zA-Z]{3,20})$/.test(color);
+}
+
+// Helper function to read a schema field from a device; ram_pct and battery_pct are derived
+function fieldValue(device, field) {
+    if (field === 'ram_pct') {
+        return device.ram_total_mb > 0 ? (device.ram_used_mb / device.ram_total_mb) * 100 : null;
+    }
+    if (field === 'battery_pct') {
+        return device.battery ? device.battery.percent : null;
+    }
+    return device[field];
+}
+
+// Helper function to render a value in its schema unit
+function formatValue(value, unit) {
+    if (unit === 'B/s') {
+        return formatRate(value);
+    }
+    const number = unit === '%' ? value.toFixed(1) : value;
+    return `${number}<span class="metric-unit">${escapeHtml(unit)}</span>`;
+}
+
+// Helper function to format a byte rate (e.g. "1.2 MB/s")
+function formatRate(bytesPerSec) {
+    const units = ['B/s', 'KB/s', 'MB/s', 'GB/s'];
+    let value = bytesPerSec || 0;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit++;
+    }
+    return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
 // Helper function to get time ago
 function getTimeAgo(date) {
     const now = new Date();
@@ -110,8 +257,17 @@ function getTimeAgo(date) {
 
 // Update dashboard every 3 seconds
 async function updateDashboard() {
-    const devices = await fetchMetrics();
+    if (!uiFields) {
+        await fetchSchema();
+    }
+    const [devices, activity] = await Promise.all([fetchMetrics(), fetchActivity()]);
     renderDashboard(devices);
+    renderActivity(activity);
+    
+    // Logs are only fetched while their panel is open
+    if (document.getElementById('logs-panel').open) {
+        renderLogs(await fetchLogs());
+    }
 }
 
 // Initialize dashboard